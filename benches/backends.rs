@@ -0,0 +1,76 @@
+//! Times the two backends on a few CPU-bound scripts.
+//!
+//! Run with `cargo bench`. Each script runs a few times on a fresh engine
+//! per backend; the best time is reported along with the VM's speedup.
+
+use std::time::{Duration, Instant};
+
+use netl::{Backend, Engine};
+
+const RUNS: usize = 5;
+
+const SCRIPTS: [(&str, &str); 4] = [
+    (
+        "recursive calls",
+        "fn fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }
+         print fib(25);",
+    ),
+    (
+        "global loop",
+        "let total = 0;
+         let i = 0;
+         while i < 1000000 { total = total + i * i % 7; i = i + 1; }
+         print total;",
+    ),
+    (
+        "local loop",
+        "fn sum(n) {
+             let total = 0;
+             for i in 0..n { total = total + i * 2 - 1; }
+             return total;
+         }
+         print sum(1000000);",
+    ),
+    (
+        "float arithmetic",
+        "fn integrate(steps) {
+             let x = 0.0;
+             let area = 0.0;
+             let width = 1.0 / steps;
+             while x < 1.0 { area = area + x * x * width; x = x + width; }
+             return area;
+         }
+         print integrate(500000);",
+    ),
+];
+
+fn time(backend: Backend, source: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut engine = Engine::with_backend(backend);
+            engine.on_print(|_| {});
+            let start = Instant::now();
+            engine.eval(source).expect("the benchmark runs");
+            start.elapsed()
+        })
+        .min()
+        .expect("at least one run")
+}
+
+fn main() {
+    println!(
+        "{:<18} {:>12} {:>12} {:>8}",
+        "script", "tree-walker", "vm", "speedup"
+    );
+    for (name, source) in SCRIPTS {
+        let tree = time(Backend::TreeWalker, source);
+        let vm = time(Backend::Vm, source);
+        println!(
+            "{:<18} {:>10.1}ms {:>10.1}ms {:>7.1}x",
+            name,
+            tree.as_secs_f64() * 1000.0,
+            vm.as_secs_f64() * 1000.0,
+            tree.as_secs_f64() / vm.as_secs_f64()
+        );
+    }
+}
@@ -1,8 +1,8 @@
 let num1 = 10;
 let num2 = 2;
 
-if (num1 > num2) {
-    print("num1 is greater and " + num1);
+if num1 > num2 {
+    print "num1 is greater and " + num1;
 } else {
-    print("num2 is greater and " + num2);
-}
\ No newline at end of file
+    print "num2 is greater and " + num2;
+}
@@ -0,0 +1,345 @@
+use netl::Backend;
+
+pub const USAGE: &str = "\
+Usage:
+    netl run [options] <file> [args...]
+    netl run [options] - [args...]
+    netl run [options] -e <code> [args...]
+    netl repl [--backend <name>]
+    netl fmt [--check] [files...]
+    netl check [files...]
+    netl help
+
+Runs a netl script. `-` or no file reads the script from stdin. Arguments
+after the script are available to it as the `args` list. `repl` starts an
+interactive session, which is also the default when stdin is a terminal.
+`fmt` rewrites files in the canonical style, or formats stdin to stdout
+when no file is given; with `--check` it changes nothing and fails if any
+file is not formatted. `check` reports likely mistakes in scripts, such as
+undefined names and unused variables, without running them; it reads
+stdin for `-` or when no file is given.
+
+Options:
+    -e, --eval <code>       run <code> instead of reading a file
+        --backend <name>    execute with `vm` (the default), the bytecode
+                            virtual machine, or `tree`, the slower
+                            tree-walking interpreter
+        --allow-read=<dir>  let the script read files in <dir>; repeatable
+        --allow-write=<dir> let the script create, change and remove files
+                            in <dir>; repeatable
+        --dump-tokens       print the token stream before running
+        --dump-ast          print the syntax tree before running
+        --dump-bytecode     print the compiled bytecode before running
+    -h, --help              print this help
+    -V, --version           print the version";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(RunOptions),
+    Repl(Backend),
+    Fmt(FmtOptions),
+    /// Checks the given scripts, or stdin when there are none.
+    Check(Vec<Source>),
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    File(String),
+    Stdin,
+    Eval(String),
+}
+
+impl Source {
+    /// Name of the source as shown in diagnostics.
+    pub fn name(&self) -> &str {
+        match self {
+            Source::File(path) => path,
+            Source::Stdin => "<stdin>",
+            Source::Eval(_) => "<eval>",
+        }
+    }
+
+    pub fn read(&self) -> std::io::Result<String> {
+        match self {
+            Source::File(path) => std::fs::read_to_string(path),
+            Source::Stdin => std::io::read_to_string(std::io::stdin()),
+            Source::Eval(code) => Ok(code.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOptions {
+    pub source: Source,
+    pub args: Vec<String>,
+    pub backend: Backend,
+    /// Directories the script may read files in.
+    pub allow_read: Vec<String>,
+    /// Directories the script may write files in.
+    pub allow_write: Vec<String>,
+    pub dump_tokens: bool,
+    pub dump_ast: bool,
+    pub dump_bytecode: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FmtOptions {
+    /// Files to format; stdin when empty.
+    pub files: Vec<String>,
+    pub check: bool,
+}
+
+/// Parses the command line, excluding the program name. Without a
+/// subcommand the arguments are treated as if `run` had been given.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+
+    match args.peek().map(String::as_str) {
+        Some("run") => {
+            args.next();
+        }
+        Some("repl") => {
+            args.next();
+            return match (args.next(), args.next()) {
+                (None, _) => Ok(Command::Repl(Backend::default())),
+                (Some(option), backend) if option == "--backend" => {
+                    let backend = parse_backend(&option, backend)?;
+                    match args.next() {
+                        Some(extra) => Err(format!("unexpected argument `{}`", extra)),
+                        None => Ok(Command::Repl(backend)),
+                    }
+                }
+                (Some(extra), _) => Err(format!("unexpected argument `{}`", extra)),
+            };
+        }
+        Some("fmt") => {
+            args.next();
+            let mut options = FmtOptions {
+                files: Vec::new(),
+                check: false,
+            };
+            for arg in args {
+                match arg.as_str() {
+                    "--check" => options.check = true,
+                    option if option.starts_with('-') => {
+                        return Err(format!("unknown option `{}`", option))
+                    }
+                    _ => options.files.push(arg),
+                }
+            }
+            return Ok(Command::Fmt(options));
+        }
+        Some("check") => {
+            args.next();
+            return args
+                .map(|arg| match arg.as_str() {
+                    "-" => Ok(Source::Stdin),
+                    option if option.starts_with('-') => {
+                        Err(format!("unknown option `{}`", option))
+                    }
+                    _ => Ok(Source::File(arg)),
+                })
+                .collect::<Result<_, _>>()
+                .map(Command::Check);
+        }
+        Some("help") => return Ok(Command::Help),
+        _ => {}
+    }
+
+    let mut source = None;
+    let mut dump_tokens = false;
+    let mut dump_ast = false;
+    let mut dump_bytecode = false;
+    let mut backend = Backend::default();
+    let mut allow_read = Vec::new();
+    let mut allow_write = Vec::new();
+
+    while source.is_none() {
+        let Some(arg) = args.next() else {
+            break;
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--dump-tokens" => dump_tokens = true,
+            "--dump-ast" => dump_ast = true,
+            "--dump-bytecode" => dump_bytecode = true,
+            "--backend" => backend = parse_backend(&arg, args.next())?,
+            "-e" | "--eval" => match args.next() {
+                Some(code) => source = Some(Source::Eval(code)),
+                None => return Err(format!("`{}` expects a code argument", arg)),
+            },
+            option if option.starts_with("--allow-read=") => {
+                allow_read.push(directory(option, "--allow-read=")?)
+            }
+            option if option.starts_with("--allow-write=") => {
+                allow_write.push(directory(option, "--allow-write=")?)
+            }
+            "-" => source = Some(Source::Stdin),
+            "--" => source = Some(args.next().map_or(Source::Stdin, Source::File)),
+            option if option.starts_with('-') => {
+                return Err(format!("unknown option `{}`", option))
+            }
+            _ => source = Some(Source::File(arg)),
+        }
+    }
+
+    Ok(Command::Run(RunOptions {
+        source: source.unwrap_or(Source::Stdin),
+        args: args.collect(),
+        backend,
+        allow_read,
+        allow_write,
+        dump_tokens,
+        dump_ast,
+        dump_bytecode,
+    }))
+}
+
+/// The directory of an `--allow-...=<dir>` option.
+fn directory(option: &str, prefix: &str) -> Result<String, String> {
+    match &option[prefix.len()..] {
+        "" => Err(format!(
+            "`{}` expects a directory",
+            prefix.trim_end_matches('=')
+        )),
+        dir => Ok(dir.to_string()),
+    }
+}
+
+fn parse_backend(option: &str, name: Option<String>) -> Result<Backend, String> {
+    match name.as_deref() {
+        Some("tree") => Ok(Backend::TreeWalker),
+        Some("vm") => Ok(Backend::Vm),
+        Some(name) => Err(format!(
+            "unknown backend `{}`; expected `tree` or `vm`",
+            name
+        )),
+        None => Err(format!("`{}` expects a backend name", option)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run_options(source: Source) -> RunOptions {
+        RunOptions {
+            source,
+            args: Vec::new(),
+            backend: Backend::default(),
+            allow_read: Vec::new(),
+            allow_write: Vec::new(),
+            dump_tokens: false,
+            dump_ast: false,
+            dump_bytecode: false,
+        }
+    }
+
+    #[test]
+    fn runs_a_file_with_options_before_it_and_arguments_after() {
+        assert_eq!(
+            parse(&[
+                "run",
+                "--backend",
+                "tree",
+                "--dump-ast",
+                "--allow-read=data",
+                "f.nl",
+                "--backend",
+                "x"
+            ]),
+            Ok(Command::Run(RunOptions {
+                args: vec!["--backend".to_string(), "x".to_string()],
+                backend: Backend::TreeWalker,
+                allow_read: vec!["data".to_string()],
+                dump_ast: true,
+                ..run_options(Source::File("f.nl".to_string()))
+            }))
+        );
+        assert_eq!(parse(&["f.nl"]), parse(&["run", "f.nl"]));
+    }
+
+    #[test]
+    fn reads_stdin_without_a_file() {
+        assert_eq!(parse(&[]), Ok(Command::Run(run_options(Source::Stdin))));
+        assert_eq!(
+            parse(&["run", "-"]),
+            Ok(Command::Run(run_options(Source::Stdin)))
+        );
+        assert_eq!(
+            parse(&["--", "-f.nl"]),
+            Ok(Command::Run(run_options(Source::File("-f.nl".to_string()))))
+        );
+        assert_eq!(
+            parse(&["-e", "print 1;", "a"]),
+            Ok(Command::Run(RunOptions {
+                args: vec!["a".to_string()],
+                ..run_options(Source::Eval("print 1;".to_string()))
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_the_other_subcommands() {
+        assert_eq!(parse(&["repl"]), Ok(Command::Repl(Backend::Vm)));
+        assert_eq!(
+            parse(&["repl", "--backend", "tree"]),
+            Ok(Command::Repl(Backend::TreeWalker))
+        );
+        assert_eq!(
+            parse(&["fmt", "--check", "a.nl", "b.nl"]),
+            Ok(Command::Fmt(FmtOptions {
+                files: vec!["a.nl".to_string(), "b.nl".to_string()],
+                check: true,
+            }))
+        );
+        assert_eq!(parse(&["check"]), Ok(Command::Check(Vec::new())));
+        assert_eq!(
+            parse(&["check", "a.nl", "-"]),
+            Ok(Command::Check(vec![
+                Source::File("a.nl".to_string()),
+                Source::Stdin
+            ]))
+        );
+        assert_eq!(parse(&["help"]), Ok(Command::Help));
+        assert_eq!(parse(&["run", "-h", "f.nl"]), Ok(Command::Help));
+        assert_eq!(parse(&["--version"]), Ok(Command::Version));
+    }
+
+    #[test]
+    fn rejects_malformed_arguments() {
+        let error = |message: &str| Err(message.to_string());
+        assert_eq!(parse(&["--bogus"]), error("unknown option `--bogus`"));
+        assert_eq!(
+            parse(&["--backend"]),
+            error("`--backend` expects a backend name")
+        );
+        assert_eq!(
+            parse(&["--backend", "jit"]),
+            error("unknown backend `jit`; expected `tree` or `vm`")
+        );
+        assert_eq!(parse(&["-e"]), error("`-e` expects a code argument"));
+        assert_eq!(
+            parse(&["--allow-write="]),
+            error("`--allow-write` expects a directory")
+        );
+        assert_eq!(parse(&["repl", "x"]), error("unexpected argument `x`"));
+        assert_eq!(
+            parse(&["repl", "--backend", "vm", "x"]),
+            error("unexpected argument `x`")
+        );
+        assert_eq!(parse(&["fmt", "-w"]), error("unknown option `-w`"));
+        assert_eq!(
+            parse(&["check", "a.nl", "--fix"]),
+            error("unknown option `--fix`")
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::logic::ast::{self, ASTNode};
+use crate::logic::diagnostic::{Diagnostic, SourceFile};
+use crate::logic::exec::{Interpreter, RuntimeError};
+use crate::logic::tokenizer::tokenize;
+use crate::logic::value::{NativeFunction, Value};
+use crate::logic::vm::Vm;
+use crate::sandbox::{self, Capabilities};
+
+/// Why evaluating a piece of source failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(Diagnostic),
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::Parse(diagnostic) => diagnostic.clone(),
+            Error::Runtime(error) => error.to_diagnostic(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(diagnostic) => write!(f, "{}", diagnostic),
+            Error::Runtime(error) => write!(f, "{} at {}", error, error.span()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Error {
+        Error::Parse(diagnostic)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Error {
+        Error::Runtime(error)
+    }
+}
+
+/// Adapts a callback to `Write`. The interpreter writes each printed line
+/// with a single call, so every `write` is one line.
+struct PrintCallback<F>(F);
+
+impl<F: FnMut(&str)> Write for PrintCallback<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        (self.0)(text.strip_suffix('\n').unwrap_or(&text));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Which implementation executes scripts. Both run the same language with
+/// the same results and errors; the VM, the default, compiles to bytecode
+/// first, which pays off on longer-running scripts. `cargo bench` compares
+/// the two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    TreeWalker,
+    #[default]
+    Vm,
+}
+
+enum Runtime {
+    TreeWalker(Interpreter),
+    Vm(Vm),
+}
+
+/// An embedded netl instance. Globals and registered functions persist
+/// across evaluations, so a host can run scripts incrementally.
+///
+/// Scripts get no file access unless it is granted with
+/// [`set_capabilities`](Engine::set_capabilities), except for importing
+/// modules from their own directory.
+pub struct Engine {
+    runtime: Runtime,
+    capabilities: Rc<RefCell<Capabilities>>,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    /// Creates an engine using the bytecode VM.
+    pub fn new() -> Engine {
+        Engine::with_backend(Backend::default())
+    }
+
+    pub fn with_backend(backend: Backend) -> Engine {
+        let capabilities: Rc<RefCell<Capabilities>> = Rc::default();
+        let runtime = match backend {
+            Backend::TreeWalker => {
+                let mut interpreter = Interpreter::new();
+                interpreter.set_capabilities(capabilities.clone());
+                Runtime::TreeWalker(interpreter)
+            }
+            Backend::Vm => {
+                let mut vm = Vm::new();
+                vm.set_capabilities(capabilities.clone());
+                Runtime::Vm(vm)
+            }
+        };
+        let mut engine = Engine {
+            runtime,
+            capabilities,
+        };
+        for native in sandbox::natives(&engine.capabilities) {
+            let name = native.name.clone();
+            engine.set_global(&name, Value::Native(Rc::new(native)));
+        }
+        engine
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.runtime {
+            Runtime::TreeWalker(_) => Backend::TreeWalker,
+            Runtime::Vm(_) => Backend::Vm,
+        }
+    }
+
+    /// Evaluates source code. If the source is a single expression its value
+    /// is returned; otherwise it is run as a program and the result is `nil`.
+    /// Errors later raised in functions it declares render against it, under
+    /// the name `<eval>`.
+    // A parse error is the parser's `Diagnostic`, returned as it is.
+    #[allow(clippy::result_large_err)]
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        self.set_source("<eval>", source);
+        match ast::parse_input(tokenize(source))? {
+            program @ ASTNode::Program(..) => {
+                self.execute(program)?;
+                Ok(Value::Nil)
+            }
+            expression => Ok(self.evaluate(expression)?),
+        }
+    }
+
+    /// Names the source code the programs and expressions executed next were
+    /// parsed from. Functions they declare keep it, so that errors raised in
+    /// them when called from code run later render against their own source.
+    pub fn set_source(&mut self, name: &str, source: &str) {
+        let file = Some(Rc::new(SourceFile {
+            name: name.to_string(),
+            text: source.into(),
+        }));
+        match &mut self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.set_file(file),
+            Runtime::Vm(vm) => vm.set_file(file),
+        }
+    }
+
+    /// Runs an already parsed program.
+    pub fn execute(&mut self, program: ASTNode) -> Result<(), RuntimeError> {
+        match &mut self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.run(program),
+            Runtime::Vm(vm) => vm.run(program),
+        }
+    }
+
+    /// Evaluates an already parsed expression.
+    pub fn evaluate(&mut self, expression: ASTNode) -> Result<Value, RuntimeError> {
+        match &mut self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.evaluate(expression),
+            Runtime::Vm(vm) => vm.evaluate(expression),
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match &self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.global(name),
+            Runtime::Vm(vm) => vm.global(name),
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        match &mut self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.define_global(name, value),
+            Runtime::Vm(vm) => vm.define_global(name, value),
+        }
+    }
+
+    /// Sets the file the engine's scripts come from. `import`s resolve
+    /// relative to its directory instead of the working directory, and
+    /// modules may not import it back.
+    pub fn set_script_path(&mut self, path: impl AsRef<Path>) {
+        match &mut self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.set_script_path(path.as_ref()),
+            Runtime::Vm(vm) => vm.set_script_path(path.as_ref()),
+        }
+    }
+
+    /// Replaces the file access scripts are allowed, taking effect for the
+    /// next file operation.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        *self.capabilities.borrow_mut() = capabilities;
+    }
+
+    /// Sends the output of `print` to `output` instead of stdout.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        match &mut self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.set_output(Box::new(output)),
+            Runtime::Vm(vm) => vm.set_output(Box::new(output)),
+        }
+    }
+
+    /// Calls `callback` with the text of every `print`, without the trailing
+    /// newline, instead of writing to stdout.
+    pub fn on_print<F: FnMut(&str) + 'static>(&mut self, callback: F) {
+        self.set_output(PrintCallback(callback));
+    }
+
+    /// All global variables, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        match &self.runtime {
+            Runtime::TreeWalker(interpreter) => interpreter.globals(),
+            Runtime::Vm(vm) => vm.globals(),
+        }
+    }
+
+    /// Makes a Rust function callable from scripts under `name`. With an
+    /// `arity` the argument count is checked before `function` is called;
+    /// an `Err` returned by `function` becomes a runtime error in the script.
+    pub fn register_fn<F>(&mut self, name: &str, arity: Option<usize>, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.set_global(name, Value::Native(Rc::new(native)));
+    }
+}
@@ -0,0 +1,46 @@
+//! netl is a small scripting language meant to be embedded in Rust programs.
+//!
+//! [`Engine`] is the entry point for hosts: it evaluates source code, exposes
+//! the script's globals and lets Rust functions be called from scripts.
+//! Scripts run on a bytecode VM by default; [`Engine::with_backend`] selects
+//! the simpler tree-walking interpreter instead.
+//!
+//! ```
+//! use netl::{Engine, Value};
+//!
+//! let mut engine = Engine::new();
+//! engine.register_fn("double", Some(1), |args| match &args[0] {
+//!     Value::Int(n) => Ok(Value::Int(n * 2)),
+//!     other => Err(format!("expected an int, found {}", other.type_name())),
+//! });
+//! engine.set_global("base", Value::Int(20));
+//! engine.eval("let answer = double(base) + 2;").unwrap();
+//!
+//! assert_eq!(engine.get_global("answer"), Some(Value::Int(42)));
+//! assert_eq!(engine.eval("answer - 2").unwrap(), Value::Int(40));
+//! ```
+
+pub mod engine;
+pub mod loader;
+pub mod logic;
+pub mod sandbox;
+
+pub use engine::{Backend, Engine, Error};
+pub use logic::diagnostic::Diagnostic;
+pub use logic::exec::RuntimeError;
+pub use logic::value::Value;
+pub use sandbox::Capabilities;
+
+use logic::ast::{ASTNode, SpannedToken};
+
+/// Splits source code into tokens, ending with `Token::EndOfFile`.
+pub fn tokenize(source: &str) -> Vec<SpannedToken> {
+    logic::tokenizer::tokenize(source)
+}
+
+/// Parses source code into a `Program` syntax tree.
+// Returns the parser's `Diagnostic` as it is; see `logic::ast`.
+#[allow(clippy::result_large_err)]
+pub fn parse(source: &str) -> Result<ASTNode, Diagnostic> {
+    logic::ast::parse(tokenize(source))
+}
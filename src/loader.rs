@@ -1,80 +1,234 @@
-use crate::parser::Token;
-
-pub fn tokenize(code: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut iter = code.chars().peekable();
-
-    while let Some(ch) = iter.next() {
-        match ch {
-            ' ' | '\t' | '\n' => continue,
-            '=' => tokens.push(Token::Equal),
-            '(' => tokens.push(Token::LeftParenthesis),
-            ')' => tokens.push(Token::RightParenthesis),
-            '{' => tokens.push(Token::LeftBrace),
-            '}' => tokens.push(Token::RightBrace),
-            ';' => tokens.push(Token::SemiColon),
-            '+' => tokens.push(Token::Plus),
-            '-' => tokens.push(Token::Minus),
-            '!' => {
-                if iter.peek() == Some(&'=') {
-                    iter.next();
-                    tokens.push(Token::NotEqual);
-                }
-            }
-            ',' => tokens.push(Token::Comma),
-            '"' => {
-                let mut string_literal = String::new();
-                while let Some(ch) = iter.next() {
-                    if ch == '"' {
-                        break;
-                    } else {
-                        string_literal.push(ch);
-                    }
-                }
-                tokens.push(Token::StringLiteral(string_literal));
-            }
-            c if c.is_alphabetic() => {
-                let mut identifier = String::new();
-                identifier.push(c);
-
-                while let Some(&next_ch) = iter.peek() {
-                    if next_ch.is_alphanumeric() || next_ch == '_' {
-                        identifier.push(next_ch);
-                        iter.next();
-                    } else {
-                        break;
-                    }
-                }
-
-                match identifier.as_str() {
-                    "const" => tokens.push(Token::Const),
-                    "while" => tokens.push(Token::While),
-                    "print" => tokens.push(Token::Print),
-                    "fn" => tokens.push(Token::Fn),
-                    _ => tokens.push(Token::Ident(identifier)),
-                }
-            }
-            c if c.is_digit(10) => {
-                let mut number = String::new();
-                number.push(c);
-
-                while let Some(&next_ch) = iter.peek() {
-                    if next_ch.is_digit(10) {
-                        number.push(next_ch);
-                        iter.next();
-                    } else {
-                        break;
-                    }
-                }
-
-                if let Ok(value) = number.parse::<i32>() {
-                    tokens.push(Token::Number(value));
-                }
-            }
-            _ => tokens.push(Token::Unknown),
+//! Loading the files scripts import.
+//!
+//! `import "util.nl";` and `import util;` name a file relative to the
+//! directory of the importing file, or of the working directory for code
+//! without a file such as the REPL. Each file runs once, in a scope of its
+//! own; later imports of the same file share the resulting module.
+//!
+//! Modules must lie in the directory of the main script or below it, or
+//! the working directory for code without a file, unless the script's
+//! [`Capabilities`] allow reading them. Otherwise scripts without any file
+//! access could still run, and through parse errors show, any file.
+//!
+//! The loader finds, reads and parses modules and keeps track of the ones
+//! being imported, but leaves running them to the backend, which calls
+//! [`Loader::begin`] before and [`Loader::finish`] after executing a module.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::logic::ast::{self, ASTNode};
+use crate::logic::diagnostic::SourceFile;
+use crate::logic::exec::RuntimeError;
+use crate::logic::span::Span;
+use crate::logic::tokenizer::tokenize;
+use crate::logic::value::{Module, Value};
+use crate::sandbox::{self, Capabilities};
+
+/// Maximum number of files running at once: the main script and the chain
+/// of modules being imported, each by the one before. Both backends run a
+/// module's code nested in the import that needs it, so without a limit a
+/// long enough chain would use up the host's stack.
+pub const MAX_IMPORT_DEPTH: usize = 100;
+
+/// Whether two pieces of code come from the same file, `None` being the
+/// main script.
+pub fn same_file(a: &Option<Rc<SourceFile>>, b: &Option<Rc<SourceFile>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// The result of [`Loader::begin`].
+pub enum Import {
+    /// The module already ran; here is its value.
+    Loaded(Value),
+    /// The module has been parsed and must now be executed.
+    Pending(PendingModule),
+}
+
+/// A parsed module waiting to be executed by the backend.
+pub struct PendingModule {
+    pub name: String,
+    pub program: ASTNode,
+    pub file: Rc<SourceFile>,
+    path: PathBuf,
+    /// The names the module declares with `export`.
+    exports: Vec<String>,
+}
+
+/// A file that is running, either the main script or a module.
+struct Running {
+    /// Where the file was found, for resolving its imports and for messages.
+    path: PathBuf,
+    /// The canonical path identifying the file.
+    key: PathBuf,
+}
+
+#[derive(Default)]
+pub struct Loader {
+    /// The files currently running, outermost first. Importing one of them
+    /// again would be a cycle.
+    running: Vec<Running>,
+    /// Modules that finished running, by canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// The canonical directory of the main script, if it has a file.
+    root: Option<PathBuf>,
+    capabilities: Rc<RefCell<Capabilities>>,
+}
+
+impl Loader {
+    pub fn new() -> Loader {
+        Loader::default()
+    }
+
+    /// Sets the file of the main script, which imports are resolved against
+    /// and which no module may import back.
+    pub fn set_script_path(&mut self, path: &Path) {
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.root = sandbox::resolve(path.parent().unwrap_or(Path::new("")));
+        self.running = vec![Running {
+            path: path.to_path_buf(),
+            key,
+        }];
+    }
+
+    /// Shares the capabilities that decide which other directories modules
+    /// may be imported from.
+    pub fn set_capabilities(&mut self, capabilities: Rc<RefCell<Capabilities>>) {
+        self.capabilities = capabilities;
+    }
+
+    /// Starts importing `path`, written in the importing file at `span`.
+    /// Unless the module is already loaded, it is read and parsed, and
+    /// counts as running until [`finish`](Loader::finish) is called.
+    pub fn begin(&mut self, path: &str, span: Span) -> Result<Import, RuntimeError> {
+        let path = match self.running.last() {
+            Some(importer) => importer.path.parent().unwrap_or(Path::new("")).join(path),
+            None => PathBuf::from(path),
+        };
+        if !self.may_import(&path) {
+            return Err(RuntimeError::ImportDenied {
+                path: path.display().to_string(),
+                span,
+            });
+        }
+        let not_found = |error: std::io::Error| RuntimeError::ModuleNotFound {
+            path: path.display().to_string(),
+            reason: error.to_string(),
+            span,
+        };
+
+        let key = fs::canonicalize(&path).map_err(not_found)?;
+        if let Some(module) = self.modules.get(&key) {
+            return Ok(Import::Loaded(module.clone()));
+        }
+        if let Some(position) = self.running.iter().position(|running| running.key == key) {
+            let mut cycle: Vec<String> = self.running[position..]
+                .iter()
+                .map(|running| running.path.display().to_string())
+                .collect();
+            cycle.push(path.display().to_string());
+            return Err(RuntimeError::ImportCycle { cycle, span });
         }
+        if self.running.len() >= MAX_IMPORT_DEPTH {
+            return Err(RuntimeError::ImportTooDeep {
+                limit: MAX_IMPORT_DEPTH,
+                span,
+            });
+        }
+
+        let text = fs::read_to_string(&path).map_err(not_found)?;
+        let file = Rc::new(SourceFile {
+            name: path.display().to_string(),
+            text: Rc::from(text),
+        });
+        let program =
+            ast::parse(tokenize(&file.text)).map_err(|diagnostic| RuntimeError::InModule {
+                module: Some(file.name.clone()),
+                diagnostic: Box::new(diagnostic.with_file(file.clone())),
+                span,
+            })?;
+
+        let exports = match &program {
+            ASTNode::Program(statements, _) => statements
+                .iter()
+                .filter_map(|statement| match statement {
+                    ASTNode::Export(declaration, _) => match &**declaration {
+                        ASTNode::Variable(name, _, _) | ASTNode::Function(name, _, _, _) => {
+                            Some(name.clone())
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        self.running.push(Running {
+            path: path.clone(),
+            key,
+        });
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        Ok(Import::Pending(PendingModule {
+            name,
+            program,
+            path,
+            file,
+            exports,
+        }))
+    }
+
+    /// Whether `path` lies in the directory of the main script, or the
+    /// working directory without one, or in a directory the script may read.
+    fn may_import(&self, path: &Path) -> bool {
+        let Some(resolved) = sandbox::resolve(path) else {
+            return false;
+        };
+        let root = match &self.root {
+            Some(root) => Some(root.clone()),
+            None => sandbox::resolve(Path::new("")),
+        };
+        root.is_some_and(|root| resolved.starts_with(root))
+            || self.capabilities.borrow().can_read(&resolved)
     }
 
-    tokens.push(Token::EndOfFile);
-    tokens
-}
\ No newline at end of file
+    /// Completes an import started by [`begin`](Loader::begin) once the
+    /// backend has run the module. `global` reads the module's globals,
+    /// from which its exports are taken. Errors raised inside the module
+    /// are reported against its source.
+    pub fn finish(
+        &mut self,
+        module: PendingModule,
+        result: Result<(), RuntimeError>,
+        global: impl Fn(&str) -> Option<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let running = self.running.pop().expect("begin pushed the module");
+
+        if let Err(error) = result {
+            return Err(error.in_file(Some(&module.file), span));
+        }
+
+        let exports = module
+            .exports
+            .into_iter()
+            .filter_map(|name| Some((name.clone(), global(&name)?)))
+            .collect();
+        let value = Value::Module(Rc::new(Module {
+            name: module.name,
+            path: module.path,
+            exports,
+        }));
+        self.modules.insert(running.key, value.clone());
+        Ok(value)
+    }
+}
@@ -1,22 +1,42 @@
-use std::cmp::Ordering;
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+use super::tokenizer::{tokenize, LexError};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Let,
     Fn,
+    Return,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
     If,
     Else,
     IfElse,
+    True,
+    False,
+    Nil,
+    Import,
+    Export,
     Equal,
+    EqualEqual,
     NotEqual,
+    Not,
+    AndAnd,
+    OrOr,
     Plus,
     Minus,
     Asterisk,
     Slash,
     Modulo,
     Identifier(String),
-    Number(i32),
+    Number(i64),
+    Float(f64),
     StringLiteral(String),
+    /// A string containing at least one `${...}` interpolation.
+    InterpolatedString(Vec<StringSegment>),
     Print,
     LeftParenthesis,
     RightParenthesis,
@@ -25,268 +45,1116 @@ pub enum Token {
     LeftBracket,
     RightBracket,
     LessThan,
+    LessThanOrEqual,
     GreaterThan,
+    GreaterThanOrEqual,
     Comma,
+    Colon,
+    Dot,
+    DotDot,
     SemiColon,
     EndOfFile,
     Unknown,
+    Invalid(Box<LexError>),
 }
 
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let symbol = match self {
+            Token::Let => "let",
+            Token::Fn => "fn",
+            Token::Return => "return",
+            Token::While => "while",
+            Token::For => "for",
+            Token::In => "in",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::IfElse => "elif",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Nil => "nil",
+            Token::Import => "import",
+            Token::Export => "export",
+            Token::Print => "print",
+            Token::Equal => "=",
+            Token::EqualEqual => "==",
+            Token::NotEqual => "!=",
+            Token::Not => "!",
+            Token::AndAnd => "&&",
+            Token::OrOr => "||",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Asterisk => "*",
+            Token::Slash => "/",
+            Token::Modulo => "%",
+            Token::LeftParenthesis => "(",
+            Token::RightParenthesis => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::LessThan => "<",
+            Token::LessThanOrEqual => "<=",
+            Token::GreaterThan => ">",
+            Token::GreaterThanOrEqual => ">=",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::SemiColon => ";",
+            Token::Identifier(name) => return write!(f, "identifier `{}`", name),
+            Token::Number(value) => return write!(f, "number `{}`", value),
+            Token::Float(value) => return write!(f, "number `{}`", value),
+            Token::StringLiteral(value) => return write!(f, "string {:?}", value),
+            Token::InterpolatedString(_) => return write!(f, "interpolated string"),
+            Token::EndOfFile => return write!(f, "end of file"),
+            Token::Unknown => return write!(f, "unknown character"),
+            Token::Invalid(_) => return write!(f, "invalid token"),
+        };
+        write!(f, "`{}`", symbol)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Part of an interpolated string token: literal text, or the tokens of an
+/// embedded expression followed by its closing `}` and `Token::EndOfFile`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringSegment {
+    Text(String),
+    Code(Vec<SpannedToken>),
+}
+
+/// Part of an interpolated string: literal text or an expression whose value
+/// is inserted as `print` would show it.
+#[derive(Debug, Clone)]
+pub enum StringPart {
+    Text(String),
+    Expression(ASTNode),
+}
+
+/// Syntax tree node. The last field of every variant is the span of the
+/// source the node was parsed from.
 #[derive(Debug, Clone)]
 pub enum ASTNode {
-    Program(Box<Vec<ASTNode>>),
-    Assignment(String, Box<ASTNode>),
-    Variable(String, Box<ASTNode>),
-    Print(Box<ASTNode>),
-    Identifier(String),
-    Number(i32),
-    StringLiteral(String),
-    BinaryOperation(Box<ASTNode>, Token, Box<ASTNode>),
-    If(Box<ASTNode>, Vec<Box<ASTNode>>),
-    IfElse(Box<ASTNode>, Vec<Box<ASTNode>>, Vec<Box<ASTNode>>),
+    Program(Vec<ASTNode>, Span),
+    Assignment(String, Box<ASTNode>, Span),
+    Variable(String, Box<ASTNode>, Span),
+    Print(Box<ASTNode>, Span),
+    Identifier(String, Span),
+    Number(i64, Span),
+    Float(f64, Span),
+    StringLiteral(String, Span),
+    Bool(bool, Span),
+    Nil(Span),
+    /// `"text ${expression} text"`.
+    InterpolatedString(Vec<StringPart>, Span),
+    BinaryOperation(Box<ASTNode>, Token, Box<ASTNode>, Span),
+    UnaryOperation(Token, Box<ASTNode>, Span),
+    /// List literal, `[a, b, c]`.
+    List(Vec<ASTNode>, Span),
+    /// Map literal, `{key: value, ...}`.
+    Map(Vec<(ASTNode, ASTNode)>, Span),
+    /// `target[index]`.
+    Index(Box<ASTNode>, Box<ASTNode>, Span),
+    /// `module.name`.
+    Member(Box<ASTNode>, String, Span),
+    /// `target[index] = value;`
+    IndexAssignment(Box<ASTNode>, Box<ASTNode>, Box<ASTNode>, Span),
+    /// `if` / `elif` / `else`. Each branch pairs a condition with its body;
+    /// branches are tried in order and the optional body runs if none match.
+    If(Vec<(ASTNode, Vec<ASTNode>)>, Option<Vec<ASTNode>>, Span),
+    Function(String, Vec<String>, Vec<ASTNode>, Span),
+    Call(Box<ASTNode>, Vec<ASTNode>, Span),
+    Return(Option<Box<ASTNode>>, Span),
+    Expression(Box<ASTNode>, Span),
+    While(Box<ASTNode>, Vec<ASTNode>, Span),
+    For(String, Box<ASTNode>, Vec<ASTNode>, Span),
+    Break(Span),
+    Continue(Span),
+    /// `import "path.nl";` or `import name;`, binding the module to the
+    /// second field.
+    Import(String, String, Span),
+    /// `export let ...;` or `export fn ...`, only allowed at the top level.
+    Export(Box<ASTNode>, Span),
+}
+
+impl ASTNode {
+    pub fn span(&self) -> Span {
+        match self {
+            ASTNode::Program(_, span)
+            | ASTNode::Assignment(_, _, span)
+            | ASTNode::Variable(_, _, span)
+            | ASTNode::Print(_, span)
+            | ASTNode::Identifier(_, span)
+            | ASTNode::Number(_, span)
+            | ASTNode::Float(_, span)
+            | ASTNode::StringLiteral(_, span)
+            | ASTNode::Bool(_, span)
+            | ASTNode::Nil(span)
+            | ASTNode::InterpolatedString(_, span)
+            | ASTNode::BinaryOperation(_, _, _, span)
+            | ASTNode::UnaryOperation(_, _, span)
+            | ASTNode::List(_, span)
+            | ASTNode::Map(_, span)
+            | ASTNode::Index(_, _, span)
+            | ASTNode::Member(_, _, span)
+            | ASTNode::IndexAssignment(_, _, _, span)
+            | ASTNode::If(_, _, span)
+            | ASTNode::Function(_, _, _, span)
+            | ASTNode::Call(_, _, span)
+            | ASTNode::Return(_, span)
+            | ASTNode::Expression(_, span)
+            | ASTNode::While(_, _, span)
+            | ASTNode::For(_, _, _, span)
+            | ASTNode::Break(span)
+            | ASTNode::Continue(span)
+            | ASTNode::Import(_, _, span)
+            | ASTNode::Export(_, span) => *span,
+        }
+    }
+
+    /// Whether `self` and `other` are the same tree apart from their spans,
+    /// as when the same code is laid out differently.
+    pub fn same_shape(&self, other: &ASTNode) -> bool {
+        use ASTNode::*;
+        match (self, other) {
+            (Program(a, _), Program(b, _)) => same_shapes(a, b),
+            (Assignment(a, x, _), Assignment(b, y, _)) | (Variable(a, x, _), Variable(b, y, _)) => {
+                a == b && x.same_shape(y)
+            }
+            (Print(a, _), Print(b, _))
+            | (Expression(a, _), Expression(b, _))
+            | (Export(a, _), Export(b, _)) => a.same_shape(b),
+            (Identifier(a, _), Identifier(b, _)) | (StringLiteral(a, _), StringLiteral(b, _)) => {
+                a == b
+            }
+            (Number(a, _), Number(b, _)) => a == b,
+            (Float(a, _), Float(b, _)) => a == b,
+            (Bool(a, _), Bool(b, _)) => a == b,
+            (Nil(_), Nil(_)) | (Break(_), Break(_)) | (Continue(_), Continue(_)) => true,
+            (InterpolatedString(a, _), InterpolatedString(b, _)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|parts| match parts {
+                        (StringPart::Text(a), StringPart::Text(b)) => a == b,
+                        (StringPart::Expression(a), StringPart::Expression(b)) => a.same_shape(b),
+                        _ => false,
+                    })
+            }
+            (BinaryOperation(a, x, c, _), BinaryOperation(b, y, d, _)) => {
+                x == y && a.same_shape(b) && c.same_shape(d)
+            }
+            (UnaryOperation(x, a, _), UnaryOperation(y, b, _)) => x == y && a.same_shape(b),
+            (List(a, _), List(b, _)) => same_shapes(a, b),
+            (Map(a, _), Map(b, _)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|((k, v), (l, w))| k.same_shape(l) && v.same_shape(w))
+            }
+            (Index(a, x, _), Index(b, y, _)) => a.same_shape(b) && x.same_shape(y),
+            (Member(a, x, _), Member(b, y, _)) => x == y && a.same_shape(b),
+            (IndexAssignment(a, x, c, _), IndexAssignment(b, y, d, _)) => {
+                a.same_shape(b) && x.same_shape(y) && c.same_shape(d)
+            }
+            (If(a, a_else, _), If(b, b_else, _)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|((c, x), (d, y))| c.same_shape(d) && same_shapes(x, y))
+                    && match (a_else, b_else) {
+                        (Some(x), Some(y)) => same_shapes(x, y),
+                        (x, y) => x.is_none() && y.is_none(),
+                    }
+            }
+            (Function(a, x, c, _), Function(b, y, d, _)) => a == b && x == y && same_shapes(c, d),
+            (Call(a, x, _), Call(b, y, _)) => a.same_shape(b) && same_shapes(x, y),
+            (Return(a, _), Return(b, _)) => match (a, b) {
+                (Some(a), Some(b)) => a.same_shape(b),
+                (a, b) => a.is_none() && b.is_none(),
+            },
+            (While(a, x, _), While(b, y, _)) => a.same_shape(b) && same_shapes(x, y),
+            (For(a, x, c, _), For(b, y, d, _)) => a == b && x.same_shape(y) && same_shapes(c, d),
+            (Import(a, x, _), Import(b, y, _)) => a == b && x == y,
+            _ => false,
+        }
+    }
 }
 
+fn same_shapes(a: &[ASTNode], b: &[ASTNode]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_shape(b))
+}
+
+/// How deeply blocks, expressions and string interpolations may nest.
+/// Parsing, compiling and formatting code recurse once per level, so deeper
+/// code is rejected instead of overflowing the stack.
+pub(crate) const MAX_NESTING: usize = 128;
+
 struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     current: usize,
+    function_depth: usize,
+    loop_depth: usize,
+    /// How many blocks and expressions enclose the current token.
+    nesting: usize,
+    /// Whether an expression that makes up the whole input may leave out
+    /// its `;`, as in [`parse_input`].
+    lone_expression: bool,
 }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
+    fn new(tokens: Vec<SpannedToken>) -> Parser {
+        Parser {
+            tokens,
+            current: 0,
+            function_depth: 0,
+            loop_depth: 0,
+            nesting: 0,
+            lone_expression: false,
+        }
     }
 
-    fn parse(&mut self) -> Result<ASTNode, String> {
+    fn parse(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
         let mut statements = Vec::new();
 
         while self.current_token() != Token::EndOfFile {
-            let statement = self.parse_statement()?;
+            let statement = match self.current_token() {
+                Token::Export => self.parse_export()?,
+                _ => self.parse_statement()?,
+            };
             statements.push(statement);
         }
 
-        Ok(ASTNode::Program(Box::new(statements)))
+        Ok(ASTNode::Program(statements, start.to(self.current_span())))
+    }
+
+    /// Parses input consisting of exactly one expression, optionally
+    /// followed by `;`.
+    fn parse_single_expression(&mut self) -> Result<ASTNode, Diagnostic> {
+        let expression = self.parse_expression()?;
+        if self.current_token() == Token::SemiColon {
+            self.next_token()?;
+        }
+        if self.current_token() != Token::EndOfFile {
+            return Err(self.unexpected_token("end of input"));
+        }
+
+        Ok(expression)
     }
 
-    fn parse_statement(&mut self) -> Result<ASTNode, String> {
+    /// Parses the expression inside `${...}` up to its closing brace.
+    fn parse_interpolation(&mut self) -> Result<ASTNode, Diagnostic> {
+        if self.current_token() == Token::RightBrace {
+            return Err(Diagnostic::error("expected an expression, found `}`")
+                .with_code("E0101")
+                .with_label(self.current_span(), "empty interpolation")
+                .with_help("write `\\${` to include a literal `${`"));
+        }
+        let expression = self.parse_expression()?;
+        if self.current_token() != Token::RightBrace {
+            return Err(self.unexpected_token("`}`"));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_statement(&mut self) -> Result<ASTNode, Diagnostic> {
         match self.current_token() {
             Token::Let => self.parse_variable_declaration(),
             Token::Print => self.parse_print_statement(),
-            Token::Identifier(_) => self.parse_assignment(),
+            Token::Identifier(_) if self.peek_token() == Token::Equal => self.parse_assignment(),
             Token::If => self.parse_if_statement(),
-            _ => Err(format!(
-                "Unexpected token {:?} at {}",
-                self.current_token(),
-                self.current
-            )),
-            _ => self.parse_assignment(),
+            Token::Fn => self.parse_function_declaration(),
+            Token::Return => self.parse_return_statement(),
+            Token::While => self.parse_while_statement(),
+            Token::For => self.parse_for_statement(),
+            Token::Break | Token::Continue => self.parse_loop_control(),
+            Token::Import => self.parse_import(),
+            Token::Export => Err(Diagnostic::error("`export` outside the top level")
+                .with_code("E0110")
+                .with_label(self.current_span(), "cannot export from here")
+                .with_note("only declarations at the top level of a file can be exported")),
+            _ => self.parse_expression_statement(),
+        }
+    }
+
+    /// Parses `import "path.nl";` or `import name;`, either followed by an
+    /// optional `as name`. Without one, the module is bound to its file name.
+    fn parse_import(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.expect_token(Token::Import)?;
+
+        let path_span = self.current_span();
+        let (path, file_name) = match self.current_token() {
+            Token::StringLiteral(path) => {
+                let file_name = std::path::Path::new(&path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned());
+                (path, file_name)
+            }
+            Token::Identifier(name) => (format!("{}.nl", name), Some(name)),
+            _ => return Err(self.unexpected_token("a module name or path")),
+        };
+        self.next_token()?;
+
+        let name = if self.current_token() == Token::Identifier("as".to_string()) {
+            self.next_token()?;
+            self.expect_identifier()?
+        } else {
+            match file_name.filter(|name| is_identifier(name)) {
+                Some(name) => name,
+                None => {
+                    return Err(
+                        Diagnostic::error(format!("cannot name the module `{}`", path))
+                            .with_code("E0111")
+                            .with_label(path_span, "file name is not an identifier")
+                            .with_help(format!("name it with `import {:?} as name;`", path)),
+                    )
+                }
+            }
+        };
+        self.expect_token(Token::SemiColon)?;
+
+        Ok(ASTNode::Import(path, name, start.to(self.previous_span())))
+    }
+
+    fn parse_export(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.expect_token(Token::Export)?;
+        let declaration = match self.current_token() {
+            Token::Let => self.parse_variable_declaration()?,
+            Token::Fn => self.parse_function_declaration()?,
+            _ => return Err(self.unexpected_token("`let` or `fn` after `export`")),
+        };
+
+        Ok(ASTNode::Export(
+            Box::new(declaration),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn parse_function_declaration(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.expect_token(Token::Fn)?;
+        let name = self.expect_identifier()?;
+
+        self.expect_token(Token::LeftParenthesis)?;
+        let mut parameters = Vec::new();
+        while self.current_token() != Token::RightParenthesis {
+            let parameter_span = self.current_span();
+            let parameter = self.expect_identifier()?;
+            if parameters.contains(&parameter) {
+                return Err(Diagnostic::error(format!(
+                    "parameter `{}` is declared more than once",
+                    parameter
+                ))
+                .with_code("E0106")
+                .with_label(parameter_span, "duplicate parameter"));
+            }
+            parameters.push(parameter);
+
+            if self.current_token() != Token::RightParenthesis {
+                self.expect_token(Token::Comma)?;
+            }
+        }
+        self.expect_token(Token::RightParenthesis)?;
+
+        // `break` and `continue` cannot reach loops outside the function.
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        let body = self.parse_block();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+
+        Ok(ASTNode::Function(
+            name,
+            parameters,
+            body?,
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn parse_return_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        if self.function_depth == 0 {
+            return Err(Diagnostic::error("`return` outside of a function")
+                .with_code("E0107")
+                .with_label(start, "cannot return from here")
+                .with_note("`return` may only be used inside a `fn` body"));
+        }
+        self.expect_token(Token::Return)?;
+
+        let value = if self.current_token() == Token::SemiColon {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        self.expect_token(Token::SemiColon)?;
+
+        Ok(ASTNode::Return(value, start.to(self.previous_span())))
+    }
+
+    fn parse_while_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.expect_token(Token::While)?;
+        let condition = self.parse_expression()?;
+        let body = self.parse_loop_body()?;
+
+        Ok(ASTNode::While(
+            Box::new(condition),
+            body,
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn parse_for_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.expect_token(Token::For)?;
+        let variable = self.expect_identifier()?;
+        self.expect_token(Token::In)?;
+        let iterable = self.parse_expression()?;
+        let body = self.parse_loop_body()?;
+
+        Ok(ASTNode::For(
+            variable,
+            Box::new(iterable),
+            body,
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn parse_loop_body(&mut self) -> Result<Vec<ASTNode>, Diagnostic> {
+        self.loop_depth += 1;
+        let body = self.parse_block();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn parse_loop_control(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        let keyword = self.current_token();
+        if self.loop_depth == 0 {
+            return Err(Diagnostic::error(format!("{} outside of a loop", keyword))
+                .with_code("E0108")
+                .with_label(start, "not inside a loop")
+                .with_note(format!(
+                    "{} may only be used inside `while` or `for` loops",
+                    keyword
+                )));
+        }
+        self.next_token()?;
+        self.expect_token(Token::SemiColon)?;
+
+        let span = start.to(self.previous_span());
+        Ok(match keyword {
+            Token::Break => ASTNode::Break(span),
+            _ => ASTNode::Continue(span),
+        })
+    }
+
+    /// Parses an expression used as a statement, or an assignment to an
+    /// element, `target[index] = value;`.
+    fn parse_expression_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        let lone = self.lone_expression && self.current == 0;
+        let expression = match self.current_token() {
+            Token::Identifier(_) | Token::LeftParenthesis => self.parse_expression()?,
+            // Any expression may make up the whole input.
+            _ if lone => {
+                let not_a_statement = self.unexpected_token("a statement");
+                let expression = self.parse_expression()?;
+                if self.current_token() == Token::SemiColon {
+                    self.next_token()?;
+                }
+                if self.current_token() != Token::EndOfFile {
+                    return Err(not_a_statement);
+                }
+                return Ok(ASTNode::Expression(
+                    Box::new(expression),
+                    start.to(self.previous_span()),
+                ));
+            }
+            _ => return Err(self.unexpected_token("a statement")),
+        };
+
+        if self.current_token() == Token::Equal {
+            let ASTNode::Index(target, index, _) = expression else {
+                return Err(Diagnostic::error("invalid assignment target")
+                    .with_code("E0109")
+                    .with_label(expression.span(), "cannot assign to this")
+                    .with_help("only variables and elements like `xs[0]` can be assigned to"));
+            };
+            self.next_token()?;
+            let value = self.parse_expression()?;
+            self.expect_token(Token::SemiColon)?;
+            return Ok(ASTNode::IndexAssignment(
+                target,
+                index,
+                Box::new(value),
+                start.to(self.previous_span()),
+            ));
+        }
+        if !(lone && self.current_token() == Token::EndOfFile) {
+            self.expect_token(Token::SemiColon)?;
         }
+
+        Ok(ASTNode::Expression(
+            Box::new(expression),
+            start.to(self.previous_span()),
+        ))
     }
 
-    fn parse_if_statement(&mut self) -> Result<ASTNode, String> {
+    fn parse_if_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
         self.expect_token(Token::If)?;
         let condition = self.parse_expression()?;
+        let statements = self.parse_block()?;
+        let mut branches = vec![(condition, statements)];
+
+        while self.current_token() == Token::IfElse {
+            self.next_token()?;
+            let condition = self.parse_expression()?;
+            let statements = self.parse_block()?;
+            branches.push((condition, statements));
+        }
+
+        let else_statements = if self.current_token() == Token::Else {
+            self.next_token()?;
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+
+        Ok(ASTNode::If(
+            branches,
+            else_statements,
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<ASTNode>, Diagnostic> {
+        let open = self.current_span();
         self.expect_token(Token::LeftBrace)?;
+
         let mut statements = Vec::new();
         while self.current_token() != Token::RightBrace {
-            let statement = self.parse_statement()?;
-            statements.push(Box::new(statement));
+            if self.current_token() == Token::EndOfFile {
+                return Err(Diagnostic::error("unclosed block")
+                    .with_code("E0105")
+                    .with_label(self.current_span(), "expected `}` before end of file")
+                    .with_secondary_label(open, "block opened here"));
+            }
+            let statement = self.nested(Parser::parse_statement)?;
+            statements.push(statement);
         }
         self.expect_token(Token::RightBrace)?;
 
-        if self.current_token() == Token::Else {
-            self.next_token()?;
-            self.expect_token(Token::LeftBrace)?;
-            let mut else_statements = Vec::new();
-            while self.current_token() != Token::RightBrace {
-                let statement = self.parse_statement()?;
-                else_statements.push(Box::new(statement));
-            }
-            self.expect_token(Token::RightBrace)?;
-
-            Ok(ASTNode::IfElse(
-                Box::new(condition),
-                statements,
-                else_statements,
-            ))
-        } else {
-            Ok(ASTNode::If(Box::new(condition), statements))
-        }
+        Ok(statements)
     }
 
-    fn parse_variable_declaration(&mut self) -> Result<ASTNode, String> {
+    fn parse_variable_declaration(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
         self.expect_token(Token::Let)?;
         let identifier = self.expect_identifier()?;
         self.expect_token(Token::Equal)?;
         let value = self.parse_expression()?;
         self.expect_token(Token::SemiColon)?;
 
-        Ok(ASTNode::Variable(identifier, Box::new(value)))
+        Ok(ASTNode::Variable(
+            identifier,
+            Box::new(value),
+            start.to(self.previous_span()),
+        ))
     }
 
-    fn parse_print_statement(&mut self) -> Result<ASTNode, String> {
+    fn parse_print_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
         self.expect_token(Token::Print)?;
         let expression = self.parse_expression()?;
         self.expect_token(Token::SemiColon)?;
 
-        Ok(ASTNode::Print(Box::new(expression)))
+        Ok(ASTNode::Print(
+            Box::new(expression),
+            start.to(self.previous_span()),
+        ))
     }
 
-    fn parse_assignment(&mut self) -> Result<ASTNode, String> {
+    fn parse_assignment(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
         let identifier = self.expect_identifier()?;
         self.expect_token(Token::Equal)?;
         let expression = self.parse_expression()?;
         self.expect_token(Token::SemiColon)?;
 
-        Ok(ASTNode::Assignment(identifier, Box::new(expression)))
+        Ok(ASTNode::Assignment(
+            identifier,
+            Box::new(expression),
+            start.to(self.previous_span()),
+        ))
     }
 
-    fn parse_expression(&mut self) -> Result<ASTNode, String> {
-        let mut left_node = self.parse_term()?;
-
-        while self.current_token() == Token::Plus
-            || self.current_token() == Token::Minus
-            || self.current_token() == Token::Equal
-            || self.current_token() == Token::NotEqual
-            || self.current_token() == Token::Asterisk
-            || self.current_token() == Token::Slash
-            || self.current_token() == Token::Modulo
-            || self.current_token() == Token::LessThan
-            || self.current_token() == Token::GreaterThan
-        {
-            let operator = self.current_token();
-            self.next_token()?;
+    fn parse_expression(&mut self) -> Result<ASTNode, Diagnostic> {
+        self.nested(|parser| parser.parse_binary_operation(0))
+    }
 
-            let right_node = self.parse_term()?;
-            left_node = ASTNode::BinaryOperation(
-                Box::new(left_node),
-                operator,
-                Box::new(right_node),
-            );
+    /// Runs `parse` one level deeper into the code, failing instead if that
+    /// would exceed [`MAX_NESTING`].
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser) -> Result<T, Diagnostic>,
+    ) -> Result<T, Diagnostic> {
+        if self.nesting >= MAX_NESTING {
+            return Err(Diagnostic::error("code is nested too deeply")
+                .with_code("E0112")
+                .with_label(
+                    self.current_span(),
+                    format!("more than {} levels deep", MAX_NESTING),
+                )
+                .with_help("move part of it into a variable or a function"));
         }
-
-        Ok(left_node)
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
     }
 
-    fn parse_term(&mut self) -> Result<ASTNode, String> {
-        let mut left_node = self.parse_factor()?;
+    /// Precedence climbing: parses operands and every binary operator that
+    /// binds at least as tightly as `minimum_precedence`.
+    fn parse_binary_operation(&mut self, minimum_precedence: u8) -> Result<ASTNode, Diagnostic> {
+        let mut left_node = self.parse_unary()?;
 
-        while self.current_token() == Token::Equal
-            || self.current_token() == Token::NotEqual
-        {
+        while let Some(precedence) = binary_precedence(&self.current_token()) {
+            if precedence < minimum_precedence {
+                break;
+            }
             let operator = self.current_token();
             self.next_token()?;
 
-            let right_node = self.parse_factor()?;
-            left_node = ASTNode::BinaryOperation(
-                Box::new(left_node),
-                operator,
-                Box::new(right_node),
-            );
+            // All binary operators are left-associative.
+            let right_node = self.parse_binary_operation(precedence + 1)?;
+            let span = left_node.span().to(right_node.span());
+            left_node =
+                ASTNode::BinaryOperation(Box::new(left_node), operator, Box::new(right_node), span);
         }
 
         Ok(left_node)
     }
 
-    fn parse_factor(&mut self) -> Result<ASTNode, String> {
+    fn parse_unary(&mut self) -> Result<ASTNode, Diagnostic> {
         match self.current_token() {
-            Token::Number(value) => {
+            operator @ (Token::Minus | Token::Not) => {
+                let start = self.current_span();
                 self.next_token()?;
-                Ok(ASTNode::Number(value))
+                // `-9223372036854775808` is the only way to write `i64::MIN`.
+                if let (Token::Minus, Token::Invalid(error)) = (&operator, self.current_token()) {
+                    if error.is_negatable() {
+                        let span = start.to(self.current_span());
+                        self.next_token()?;
+                        return self.parse_postfix(ASTNode::Number(i64::MIN, span));
+                    }
+                }
+                let operand = self.nested(Parser::parse_unary)?;
+                let span = start.to(operand.span());
+                Ok(ASTNode::UnaryOperation(operator, Box::new(operand), span))
             }
-            Token::StringLiteral(value) => {
-                self.next_token()?;
-                Ok(ASTNode::StringLiteral(value))
+            _ => self.parse_factor(),
+        }
+    }
+
+    /// Parses a primary expression followed by any number of calls and
+    /// index operations.
+    fn parse_factor(&mut self) -> Result<ASTNode, Diagnostic> {
+        let primary = self.parse_primary()?;
+        self.parse_postfix(primary)
+    }
+
+    /// Parses the calls, index operations and member accesses following
+    /// `expression`. Kept apart from [`parse_factor`](Parser::parse_factor)
+    /// so that its locals are not on the stack while nested parentheses are
+    /// parsed.
+    fn parse_postfix(&mut self, mut expression: ASTNode) -> Result<ASTNode, Diagnostic> {
+        loop {
+            match self.current_token() {
+                Token::LeftParenthesis => {
+                    self.next_token()?;
+                    let arguments = self.parse_expression_list(Token::RightParenthesis)?;
+                    let span = expression.span().to(self.previous_span());
+                    expression = ASTNode::Call(Box::new(expression), arguments, span);
+                }
+                Token::LeftBracket => {
+                    self.next_token()?;
+                    let index = self.parse_expression()?;
+                    self.expect_token(Token::RightBracket)?;
+                    let span = expression.span().to(self.previous_span());
+                    expression = ASTNode::Index(Box::new(expression), Box::new(index), span);
+                }
+                Token::Dot => {
+                    self.next_token()?;
+                    let name = self.expect_identifier()?;
+                    let span = expression.span().to(self.previous_span());
+                    expression = ASTNode::Member(Box::new(expression), name, span);
+                }
+                _ => return Ok(expression),
             }
-            Token::Identifier(value) => {
+        }
+    }
+
+    /// Parses comma-separated expressions up to and including `closing`. A
+    /// trailing comma is allowed.
+    fn parse_expression_list(&mut self, closing: Token) -> Result<Vec<ASTNode>, Diagnostic> {
+        let mut expressions = Vec::new();
+        while self.current_token() != closing {
+            expressions.push(self.parse_expression()?);
+            if self.current_token() != closing {
+                self.expect_token(Token::Comma)?;
+            }
+        }
+        self.expect_token(closing)?;
+
+        Ok(expressions)
+    }
+
+    fn parse_primary(&mut self) -> Result<ASTNode, Diagnostic> {
+        let span = self.current_span();
+        let token = self.current_token();
+        let literal = match token {
+            Token::Number(value) => ASTNode::Number(value, span),
+            Token::Float(value) => ASTNode::Float(value, span),
+            Token::StringLiteral(value) => ASTNode::StringLiteral(value, span),
+            Token::True | Token::False => ASTNode::Bool(token == Token::True, span),
+            Token::Nil => ASTNode::Nil(span),
+            Token::Identifier(value) => ASTNode::Identifier(value, span),
+            Token::InterpolatedString(segments) => {
                 self.next_token()?;
-                Ok(ASTNode::Identifier(value))
+                return self.parse_interpolated_string(segments, span);
             }
             Token::LeftParenthesis => {
                 self.next_token()?;
                 let expression = self.parse_expression()?;
                 self.expect_token(Token::RightParenthesis)?;
-                Ok(expression)
+                return Ok(expression);
+            }
+            Token::LeftBracket => {
+                self.next_token()?;
+                let items = self.parse_expression_list(Token::RightBracket)?;
+                return Ok(ASTNode::List(items, span.to(self.previous_span())));
+            }
+            // Blocks only follow statement keywords, so a brace in expression
+            // position always opens a map.
+            Token::LeftBrace => {
+                self.next_token()?;
+                return self.parse_map(span);
+            }
+            // Only `2^63` after a unary minus gets past `check_tokens`.
+            Token::Invalid(error) => return Err(error.to_diagnostic(span)),
+            _ => return Err(self.unexpected_token("an expression")),
+        };
+
+        self.next_token()?;
+        Ok(literal)
+    }
+
+    /// Parses the parts of a string literal containing `${...}`.
+    fn parse_interpolated_string(
+        &mut self,
+        segments: Vec<StringSegment>,
+        span: Span,
+    ) -> Result<ASTNode, Diagnostic> {
+        let parts = segments
+            .into_iter()
+            .map(|segment| match segment {
+                StringSegment::Text(text) => Ok(StringPart::Text(text)),
+                StringSegment::Code(tokens) => {
+                    let mut parser = Parser::new(tokens);
+                    parser.nesting = self.nesting;
+                    parser.parse_interpolation().map(StringPart::Expression)
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ASTNode::InterpolatedString(parts, span))
+    }
+
+    /// Parses the entries of a map literal after its `{`, which is at `span`.
+    fn parse_map(&mut self, span: Span) -> Result<ASTNode, Diagnostic> {
+        let mut entries = Vec::new();
+        while self.current_token() != Token::RightBrace {
+            let key = self.parse_expression()?;
+            self.expect_token(Token::Colon)?;
+            let value = self.parse_expression()?;
+            entries.push((key, value));
+            if self.current_token() != Token::RightBrace {
+                self.expect_token(Token::Comma)?;
             }
-            _ => Err(format!(
-                "Unexpected token {:?} at {}",
-                self.current_token(),
-                self.current
-            )),
         }
+        self.expect_token(Token::RightBrace)?;
+        Ok(ASTNode::Map(entries, span.to(self.previous_span())))
     }
 
-    fn expect_token(&mut self, token: Token) -> Result<(), String> {
+    fn expect_token(&mut self, token: Token) -> Result<(), Diagnostic> {
         if self.current_token() == token {
             self.next_token()?;
             Ok(())
         } else {
-            Err(format!(
-                "Expected token {:?} but found {:?} at {}",
+            let mut diagnostic = Diagnostic::error(format!(
+                "expected {}, found {}",
                 token,
-                self.current_token(),
-                self.current
+                self.current_token()
             ))
+            .with_code("E0102")
+            .with_label(self.current_span(), format!("expected {}", token));
+
+            if token == Token::SemiColon && self.current > 0 {
+                diagnostic = diagnostic
+                    .with_secondary_label(self.previous_span(), "statement should end here")
+                    .with_help("every statement must end with `;`");
+            }
+
+            Err(diagnostic)
         }
     }
 
-    fn expect_identifier(&mut self) -> Result<String, String> {
+    fn expect_identifier(&mut self) -> Result<String, Diagnostic> {
         match self.current_token() {
             Token::Identifier(identifier) => {
                 self.next_token()?;
                 Ok(identifier)
             }
-            _ => Err(format!(
-                "Expected identifier but found {:?} at {}",
-                self.current_token(),
-                self.current
-            )),
+            _ => Err(Diagnostic::error(format!(
+                "expected identifier, found {}",
+                self.current_token()
+            ))
+            .with_code("E0103")
+            .with_label(self.current_span(), "expected identifier")),
+        }
+    }
+
+    fn unexpected_token(&self, expected: &str) -> Diagnostic {
+        let token = self.current_token();
+        let diagnostic = Diagnostic::error(format!("expected {}, found {}", expected, token))
+            .with_code("E0101")
+            .with_label(self.current_span(), format!("expected {}", expected));
+
+        match token {
+            Token::Unknown => diagnostic.with_note("this character is not part of netl's syntax"),
+            _ => diagnostic,
         }
     }
 
     fn current_token(&self) -> Token {
-        self.tokens[self.current].clone()
+        self.tokens[self.current].token.clone()
     }
 
-    fn next_token(&mut self) -> Result<(), String> {
+    fn peek_token(&self) -> Token {
+        let next = (self.current + 1).min(self.tokens.len() - 1);
+        self.tokens[next].token.clone()
+    }
+
+    fn current_span(&self) -> Span {
+        self.tokens[self.current].span
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.current.saturating_sub(1)].span
+    }
+
+    fn next_token(&mut self) -> Result<(), Diagnostic> {
         if self.current < self.tokens.len() - 1 {
             self.current += 1;
             Ok(())
         } else {
-            Err("Unexpected end of file".to_string())
+            Err(Diagnostic::error("unexpected end of file")
+                .with_code("E0104")
+                .with_label(self.current_span(), "unexpected end of file"))
         }
     }
 }
 
-impl Eq for ASTNode {}
-
-impl PartialOrd for ASTNode {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (ASTNode::Number(a), ASTNode::Number(b)) => a.partial_cmp(b),
-            (ASTNode::StringLiteral(a), ASTNode::StringLiteral(b)) => a.partial_cmp(b),
-            _ => None,
-        }
+/// Binding strength of a binary operator; higher binds tighter.
+pub fn binary_precedence(token: &Token) -> Option<u8> {
+    match token {
+        Token::OrOr => Some(1),
+        Token::AndAnd => Some(2),
+        Token::EqualEqual | Token::NotEqual => Some(3),
+        Token::LessThan
+        | Token::LessThanOrEqual
+        | Token::GreaterThan
+        | Token::GreaterThanOrEqual
+        | Token::In => Some(4),
+        Token::DotDot => Some(5),
+        Token::Plus | Token::Minus => Some(6),
+        Token::Asterisk | Token::Slash | Token::Modulo => Some(7),
+        _ => None,
     }
 }
 
-impl Ord for ASTNode {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
-    }
+/// Whether `name` would be read as a single identifier.
+fn is_identifier(name: &str) -> bool {
+    matches!(
+        tokenize(name).as_slice(),
+        [
+            SpannedToken {
+                token: Token::Identifier(_),
+                ..
+            },
+            SpannedToken {
+                token: Token::EndOfFile,
+                ..
+            }
+        ]
+    )
 }
 
-impl PartialEq for ASTNode {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (ASTNode::Number(a), ASTNode::Number(b)) => a == b,
-            (ASTNode::StringLiteral(a), ASTNode::StringLiteral(b)) => a == b,
-            (ASTNode::Identifier(a), ASTNode::Identifier(b)) => a == b,
-            _ => false,
+/// Reports the first malformed token, so lexical errors take precedence over
+/// the syntax errors they would otherwise cause. An int literal of 2^63
+/// after a `-` is left to the parser, which reads it as `i64::MIN` if the
+/// minus is unary.
+fn check_tokens(tokens: &[SpannedToken]) -> Result<(), Diagnostic> {
+    for (index, token) in tokens.iter().enumerate() {
+        let negated = index > 0 && tokens[index - 1].token == Token::Minus;
+        match &token.token {
+            Token::Invalid(error) if negated && error.is_negatable() => {}
+            Token::Invalid(error) => return Err(error.to_diagnostic(token.span)),
+            Token::InterpolatedString(segments) => {
+                for segment in segments {
+                    if let StringSegment::Code(tokens) = segment {
+                        check_tokens(tokens)?;
+                    }
+                }
+            }
+            _ => {}
         }
     }
+    Ok(())
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<ASTNode, String> {
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<ASTNode, Diagnostic> {
+    check_tokens(&tokens)?;
     let mut parser = Parser::new(tokens);
     parser.parse()
 }
+
+pub fn parse_expression(tokens: Vec<SpannedToken>) -> Result<ASTNode, Diagnostic> {
+    check_tokens(&tokens)?;
+    let mut parser = Parser::new(tokens);
+    parser.parse_single_expression()
+}
+
+/// Parses input that may be a program or a lone expression, such as the
+/// source given to [`Engine::eval`](crate::Engine::eval). A lone expression,
+/// whose `;` may be left out, is returned by itself rather than as a
+/// `Program`.
+pub fn parse_input(tokens: Vec<SpannedToken>) -> Result<ASTNode, Diagnostic> {
+    check_tokens(&tokens)?;
+    let mut parser = Parser::new(tokens);
+    parser.lone_expression = true;
+    match parser.parse()? {
+        ASTNode::Program(mut statements, _)
+            if matches!(statements.as_slice(), [ASTNode::Expression(..)]) =>
+        {
+            match statements.pop() {
+                Some(ASTNode::Expression(expression, _)) => Ok(*expression),
+                _ => unreachable!("the program is a single expression statement"),
+            }
+        }
+        program => Ok(program),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_source(source: &str) -> Result<ASTNode, Diagnostic> {
+        parse_expression(tokenize(source))
+    }
+
+    #[test]
+    fn a_negated_literal_of_two_to_the_63_is_the_smallest_int() {
+        for source in ["-9223372036854775808", "- 0x8000_0000_0000_0000"] {
+            let parsed = parse_source(source);
+            assert!(
+                matches!(parsed, Ok(ASTNode::Number(i64::MIN, span)) if span.end == source.len()),
+                "{:?}",
+                parsed
+            );
+        }
+        assert!(matches!(
+            parse_source("--9223372036854775808"),
+            Ok(ASTNode::UnaryOperation(Token::Minus, operand, _)) if matches!(*operand, ASTNode::Number(i64::MIN, _))
+        ));
+    }
+
+    #[test]
+    fn two_to_the_63_is_out_of_range_anywhere_else() {
+        for source in [
+            "9223372036854775808",
+            "1 - 9223372036854775808",
+            "-(9223372036854775808)",
+            "-9223372036854775809",
+        ] {
+            let error = parse_source(source).expect_err(source);
+            assert_eq!(error.code, Some("E0001"), "{}", source);
+        }
+    }
+
+    #[test]
+    fn input_is_a_lone_expression_or_a_program() {
+        let parse_input = |source: &str| parse_input(tokenize(source));
+        for source in ["1 + 2", "-x;", "[1][0]", "f(1)", "{\"a\": 1};", "(x)"] {
+            let parsed = parse_input(source);
+            assert!(
+                !matches!(parsed, Ok(ASTNode::Program(..)) | Err(_)),
+                "{}: {:?}",
+                source,
+                parsed
+            );
+        }
+        for source in ["let x = 1;", "f(); g();", "xs[0] = 1;", "print 1;", ""] {
+            let parsed = parse_input(source);
+            assert!(
+                matches!(parsed, Ok(ASTNode::Program(..))),
+                "{}: {:?}",
+                source,
+                parsed
+            );
+        }
+        for (source, code) in [
+            ("1; 2;", "E0101"),
+            ("f() g()", "E0102"),
+            ("print 1", "E0102"),
+            ("x = 1", "E0102"),
+        ] {
+            let error = parse_input(source).expect_err(source);
+            assert_eq!(error.code, Some(code), "{}: {:?}", source, error);
+        }
+    }
+
+    #[test]
+    fn same_shape_ignores_layout_but_not_content() {
+        let program = |source: &str| parse(tokenize(source)).unwrap();
+        let code = "fn f(a, b) { if a { return [a, {\"k\": b}]; } elif !b { x.y[0] = 1; } else { print \"${a}\"; } }";
+        let spaced = "fn f(a, b) {\n    if a {\n        return [a, {\"k\": b}];\n    }\n    elif !b {\n        x.y[0] = 1;\n    }\n    else {\n        print \"${ a }\";\n    }\n}\n";
+        assert!(program(code).same_shape(&program(spaced)));
+
+        for changed in [
+            "fn f(a, c) { if a { return [a, {\"k\": b}]; } elif !b { x.y[0] = 1; } else { print \"${a}\"; } }",
+            "fn f(a, b) { if a { return [a, {\"k\": b}]; } elif !b { x.y[1] = 1; } else { print \"${a}\"; } }",
+            "fn f(a, b) { if a { return [a, {\"j\": b}]; } elif !b { x.y[0] = 1; } else { print \"${a}\"; } }",
+            "fn f(a, b) { if a { return [a, {\"k\": b}]; } elif -b { x.y[0] = 1; } else { print \"${a}\"; } }",
+            "fn f(a, b) { if a { return [a, {\"k\": b}]; } elif !b { x.y[0] = 1; } else { print \"${b}\"; } }",
+            "fn f(a, b) { if a { return [a, {\"k\": b}]; } elif !b { x.y[0] = 1; } }",
+            "fn f(a, b) { if a { return [a]; } elif !b { x.y[0] = 1; } else { print \"${a}\"; } }",
+        ] {
+            assert!(!program(code).same_shape(&program(changed)), "{}", changed);
+        }
+        assert!(!program("print 1;").same_shape(&program("print 1.0;")));
+        assert!(!program("print 1 - 2 - 3;").same_shape(&program("print 1 - (2 - 3);")));
+    }
+}
@@ -0,0 +1,480 @@
+use std::cmp::Ordering;
+
+use super::exec::RuntimeError;
+use super::operations;
+use super::span::Span;
+use super::value::{NativeFunction, Value};
+
+/// Native functions available to every script as globals.
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        native("len", Some(1), len),
+        native("type", Some(1), type_of),
+        native("str", Some(1), stringify),
+        native("int", Some(1), int),
+        native("float", Some(1), float),
+        native("abs", Some(1), abs),
+        native("min", None, min),
+        native("max", None, max),
+        native("pow", Some(2), pow),
+        native("sqrt", Some(1), sqrt),
+        native("upper", Some(1), upper),
+        native("lower", Some(1), lower),
+        native("trim", Some(1), trim),
+        native("split", Some(2), split),
+        native("join", Some(2), join),
+        native("contains", Some(2), contains),
+        native("replace", Some(3), replace),
+        native("starts_with", Some(2), starts_with),
+        native("push", Some(2), push),
+        native("pop", Some(1), pop),
+        native("keys", Some(1), keys),
+        native("values", Some(1), values),
+        NativeFunction {
+            name: "delete".to_string(),
+            arity: Some(2),
+            function: Box::new(delete),
+        },
+    ]
+}
+
+fn native(
+    name: &str,
+    arity: Option<usize>,
+    function: fn(&[Value]) -> Result<Value, String>,
+) -> NativeFunction {
+    NativeFunction::new(name, arity, function)
+}
+
+/// `len(value)`: the number of items in a list, characters in a string or
+/// entries in a map.
+fn len(arguments: &[Value]) -> Result<Value, String> {
+    let length = match &arguments[0] {
+        Value::List(items) => items.borrow().len(),
+        Value::String(string) => string.chars().count(),
+        Value::Map(map) => map.borrow().len(),
+        value => {
+            return Err(format!(
+                "expected a list, string or map, found {}",
+                value.type_name()
+            ))
+        }
+    };
+    Ok(Value::Int(length as i64))
+}
+
+/// `type(value)`: the name of the type of `value`, such as `"int"`.
+fn type_of(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(arguments[0].type_name()))
+}
+
+/// `str(value)`: `value` as a string, shown as `print` would show it.
+fn stringify(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::String(_) => Ok(arguments[0].clone()),
+        value => Ok(Value::from(value.to_string())),
+    }
+}
+
+/// `int(value)`: converts a float (truncating towards zero), a bool or a
+/// string of decimal digits to an int.
+fn int(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Int(value) => Ok(Value::Int(*value)),
+        Value::Float(value) => {
+            if value.is_finite() && *value >= i64::MIN as f64 && *value < i64::MAX as f64 {
+                Ok(Value::Int(*value as i64))
+            } else {
+                Err(format!("{} does not fit in an int", arguments[0]))
+            }
+        }
+        Value::Bool(value) => Ok(Value::Int(*value as i64)),
+        Value::String(string) => string
+            .trim()
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| format!("cannot convert \"{}\" to an int", string)),
+        value => Err(format!(
+            "expected a number, bool or string, found {}",
+            value.type_name()
+        )),
+    }
+}
+
+/// `float(value)`: converts an int, a bool or a string holding a number to
+/// a float.
+fn float(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Int(value) => Ok(Value::Float(*value as f64)),
+        Value::Float(value) => Ok(Value::Float(*value)),
+        Value::Bool(value) => Ok(Value::Float(*value as i64 as f64)),
+        Value::String(string) => string
+            .trim()
+            .parse()
+            .map(Value::Float)
+            .map_err(|_| format!("cannot convert \"{}\" to a float", string)),
+        value => Err(format!(
+            "expected a number, bool or string, found {}",
+            value.type_name()
+        )),
+    }
+}
+
+/// `abs(number)`: the absolute value of an int or float.
+fn abs(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Int(value) => value
+            .checked_abs()
+            .map(Value::Int)
+            .ok_or_else(|| "result does not fit in an int".to_string()),
+        Value::Float(value) => Ok(Value::Float(value.abs())),
+        value => Err(format!("expected a number, found {}", value.type_name())),
+    }
+}
+
+/// `min(a, b, ...)` or `min(list)`: the smallest of the arguments, or of
+/// the items of a single list argument.
+fn min(arguments: &[Value]) -> Result<Value, String> {
+    extreme(arguments, Ordering::Less)
+}
+
+/// `max(a, b, ...)` or `max(list)`: the largest of the arguments, or of the
+/// items of a single list argument.
+fn max(arguments: &[Value]) -> Result<Value, String> {
+    extreme(arguments, Ordering::Greater)
+}
+
+/// The first of the candidates that no other is ordered `wanted` of.
+fn extreme(arguments: &[Value], wanted: Ordering) -> Result<Value, String> {
+    let candidates = match arguments {
+        [Value::List(items)] => items.borrow().clone(),
+        _ => arguments.to_vec(),
+    };
+    let mut candidates = candidates.into_iter();
+    let mut best = candidates
+        .next()
+        .ok_or_else(|| "expected at least one value".to_string())?;
+    for candidate in candidates {
+        match candidate.partial_cmp(&best) {
+            Some(ordering) if ordering == wanted => best = candidate,
+            Some(_) => {}
+            None => {
+                return Err(format!(
+                    "cannot compare {} and {}",
+                    best.type_name(),
+                    candidate.type_name()
+                ))
+            }
+        }
+    }
+    Ok(best)
+}
+
+/// `pow(base, exponent)`: `base` raised to `exponent`. Two ints give an int
+/// unless the exponent is negative; otherwise the result is a float.
+fn pow(arguments: &[Value]) -> Result<Value, String> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Int(base), Value::Int(exponent)) if *exponent >= 0 => u32::try_from(*exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent))
+            .map(Value::Int)
+            .ok_or_else(|| "result does not fit in an int".to_string()),
+        (base, exponent) => Ok(Value::Float(number(base)?.powf(number(exponent)?))),
+    }
+}
+
+/// `sqrt(number)`: the square root of a non-negative number, as a float.
+fn sqrt(arguments: &[Value]) -> Result<Value, String> {
+    let value = number(&arguments[0])?;
+    if value < 0.0 {
+        return Err(format!("cannot take the square root of {}", arguments[0]));
+    }
+    Ok(Value::Float(value.sqrt()))
+}
+
+/// `upper(string)`: `string` in upper case.
+fn upper(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(string(&arguments[0])?.to_uppercase()))
+}
+
+/// `lower(string)`: `string` in lower case.
+fn lower(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(string(&arguments[0])?.to_lowercase()))
+}
+
+/// `trim(string)`: `string` without leading and trailing whitespace.
+fn trim(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(string(&arguments[0])?.trim()))
+}
+
+/// `split(string, separator)`: the parts of `string` between occurrences of
+/// `separator`, or its characters if `separator` is empty.
+fn split(arguments: &[Value]) -> Result<Value, String> {
+    let (string, separator) = (string(&arguments[0])?, string(&arguments[1])?);
+    let parts: Vec<Value> = if separator.is_empty() {
+        string
+            .chars()
+            .map(|ch| Value::from(ch.to_string()))
+            .collect()
+    } else {
+        string.split(separator).map(Value::from).collect()
+    };
+    Ok(Value::from(parts))
+}
+
+/// `join(list, separator)`: the items of `list`, shown as `print` would
+/// show them, with `separator` between each pair.
+fn join(arguments: &[Value]) -> Result<Value, String> {
+    let separator = string(&arguments[1])?;
+    match &arguments[0] {
+        Value::List(items) => Ok(Value::from(
+            items
+                .borrow()
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(separator),
+        )),
+        value => Err(format!("expected a list, found {}", value.type_name())),
+    }
+}
+
+/// `contains(haystack, needle)`: the same as `needle in haystack`.
+fn contains(arguments: &[Value]) -> Result<Value, String> {
+    operations::contains(&arguments[1], &arguments[0]).ok_or_else(|| {
+        format!(
+            "cannot look for a value of type {} in a value of type {}",
+            arguments[1].type_name(),
+            arguments[0].type_name()
+        )
+    })
+}
+
+/// `replace(string, from, to)`: `string` with every occurrence of `from`
+/// replaced by `to`.
+fn replace(arguments: &[Value]) -> Result<Value, String> {
+    let (string, from, to) = (
+        string(&arguments[0])?,
+        string(&arguments[1])?,
+        string(&arguments[2])?,
+    );
+    Ok(Value::from(string.replace(from, to)))
+}
+
+/// `starts_with(string, prefix)`: whether `string` begins with `prefix`.
+fn starts_with(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::Bool(
+        string(&arguments[0])?.starts_with(string(&arguments[1])?),
+    ))
+}
+
+/// `push(list, item)`: appends `item` to the end of `list`.
+fn push(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::List(items) => {
+            items.borrow_mut().push(arguments[1].clone());
+            Ok(Value::Nil)
+        }
+        value => Err(format!("expected a list, found {}", value.type_name())),
+    }
+}
+
+/// `pop(list)`: removes and returns the last item of `list`.
+fn pop(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::List(items) => items
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| "cannot pop from an empty list".to_string()),
+        value => Err(format!("expected a list, found {}", value.type_name())),
+    }
+}
+
+/// `keys(map)`: the keys of `map` in insertion order.
+fn keys(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Map(map) => Ok(Value::from(
+            map.borrow()
+                .iter()
+                .map(|(key, _)| key.to_value())
+                .collect::<Vec<_>>(),
+        )),
+        value => Err(format!("expected a map, found {}", value.type_name())),
+    }
+}
+
+/// `values(map)`: the values of `map` in insertion order.
+fn values(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Map(map) => Ok(Value::from(
+            map.borrow()
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>(),
+        )),
+        value => Err(format!("expected a map, found {}", value.type_name())),
+    }
+}
+
+/// `delete(map, key)`: removes `key` from `map`, returning its value or
+/// `nil` if it was not present. A key of a type maps cannot hold fails the
+/// same way as indexing with it.
+fn delete(arguments: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Map(map) => {
+            let key = operations::map_key(&arguments[1], span)?;
+            Ok(map.borrow_mut().remove(&key).unwrap_or(Value::Nil))
+        }
+        value => Err(RuntimeError::NativeError {
+            name: "delete".to_string(),
+            message: format!("expected a map, found {}", value.type_name()),
+            span,
+        }),
+    }
+}
+
+/// The text of a string argument, or the error for any other type.
+pub(crate) fn string(value: &Value) -> Result<&str, String> {
+    match value {
+        Value::String(string) => Ok(string),
+        value => Err(format!("expected a string, found {}", value.type_name())),
+    }
+}
+
+fn number(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Int(value) => Ok(*value as f64),
+        Value::Float(value) => Ok(*value),
+        value => Err(format!("expected a number, found {}", value.type_name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls the native `name` as a script would, returning the message of
+    /// the runtime error it fails with.
+    fn call(name: &str, arguments: &[Value]) -> Result<Value, String> {
+        let native = natives()
+            .into_iter()
+            .find(|native| native.name == name)
+            .expect("the native exists");
+        operations::call_native(&native, arguments, Span::default())
+            .map_err(|error| error.to_string())
+    }
+
+    fn list(items: &[Value]) -> Value {
+        Value::from(items.to_vec())
+    }
+
+    #[test]
+    fn arity_is_checked_before_calling() {
+        assert_eq!(
+            call("pow", &[Value::Int(2)]),
+            Err("`pow` takes 2 arguments but 1 was supplied".to_string())
+        );
+        assert_eq!(
+            call("len", &[]),
+            Err("`len` takes 1 argument but 0 were supplied".to_string())
+        );
+        assert_eq!(
+            call("replace", &["a".into(), "b".into()]),
+            Err("`replace` takes 3 arguments but 2 were supplied".to_string())
+        );
+        assert_eq!(
+            call("max", &[Value::Int(3), Value::Int(7), Value::Int(5)]),
+            Ok(Value::Int(7))
+        );
+    }
+
+    #[test]
+    fn string_helpers_reject_other_types() {
+        let expected_string = |found: &str| Err(format!("expected a string, found {}", found));
+        assert_eq!(
+            call("split", &[Value::Int(1), ",".into()]),
+            expected_string("int")
+        );
+        assert_eq!(
+            call("split", &["a,b".into(), Value::Nil]),
+            expected_string("nil")
+        );
+        assert_eq!(
+            call("join", &[list(&[]), Value::Int(0)]),
+            expected_string("int")
+        );
+        assert_eq!(
+            call("join", &["ab".into(), ",".into()]),
+            Err("expected a list, found string".to_string())
+        );
+        assert_eq!(
+            call("replace", &["ab".into(), "a".into(), list(&[])]),
+            expected_string("list")
+        );
+        assert_eq!(call("upper", &[Value::Bool(true)]), expected_string("bool"));
+    }
+
+    #[test]
+    fn math_rejects_non_numbers_and_bad_values() {
+        assert_eq!(
+            call("pow", &["2".into(), Value::Int(2)]),
+            Err("expected a number, found string".to_string())
+        );
+        assert_eq!(
+            call("pow", &[Value::Int(10), Value::Int(30)]),
+            Err("result does not fit in an int".to_string())
+        );
+        assert_eq!(
+            call("pow", &[Value::Int(2), Value::Int(-1)]),
+            Ok(Value::Float(0.5))
+        );
+        assert_eq!(
+            call("sqrt", &[Value::Nil]),
+            Err("expected a number, found nil".to_string())
+        );
+        assert_eq!(
+            call("sqrt", &[Value::Int(-4)]),
+            Err("cannot take the square root of -4".to_string())
+        );
+        assert_eq!(
+            call("abs", &[Value::Int(i64::MIN)]),
+            Err("result does not fit in an int".to_string())
+        );
+    }
+
+    #[test]
+    fn conversions_reject_bad_strings() {
+        assert_eq!(call("int", &[" 42 ".into()]), Ok(Value::Int(42)));
+        assert_eq!(
+            call("int", &["4.2".into()]),
+            Err("cannot convert \"4.2\" to an int".to_string())
+        );
+        assert_eq!(
+            call("float", &["one".into()]),
+            Err("cannot convert \"one\" to a float".to_string())
+        );
+        assert_eq!(
+            call("int", &[list(&[])]),
+            Err("expected a number, bool or string, found list".to_string())
+        );
+        assert_eq!(
+            call("int", &[Value::Float(1e20)]),
+            Err("1e20 does not fit in an int".to_string())
+        );
+    }
+
+    #[test]
+    fn min_and_max_need_comparable_values() {
+        let empty = Err("expected at least one value".to_string());
+        assert_eq!(call("min", &[]), empty);
+        assert_eq!(call("max", &[list(&[])]), empty);
+        assert_eq!(
+            call("min", &[list(&[Value::Int(3), Value::Float(1.5)])]),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(
+            call("max", &[Value::Int(1), "a".into()]),
+            Err("cannot compare int and string".to_string())
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::span::Span;
+use super::value::Value;
+
+/// One VM instruction. Operands index into the chunk's tables, the local
+/// slots of the current call frame or the global slots of the file; jump
+/// targets are absolute offsets into the chunk's code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Pushes `constants[index]`.
+    Constant(u32),
+    Nil,
+    Pop,
+    /// Pops the given number of locals at the end of a scope, closing any
+    /// upvalues that refer to them.
+    PopLocals(u32),
+    GetLocal(u32),
+    /// Pops a value into a local slot.
+    SetLocal(u32),
+    /// Pops a value into the slot reserved for a declaration when it runs.
+    /// Closures that captured the slot before now refer to it too.
+    DeclareLocal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    /// Pushes the global in the given slot.
+    GetGlobal(u32),
+    /// Pops a value into the global in the given slot, which must be
+    /// defined.
+    SetGlobal(u32),
+    /// Pops a value into the global in the given slot, defining it if need
+    /// be.
+    DefineGlobal(u32),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Contains,
+    Negate,
+    Not,
+    /// Pops the given number of items and pushes a list of them.
+    List(u32),
+    /// Pops the given number of values and pushes them joined into a string.
+    Interpolate(u32),
+    /// Pops the given number of key-value pairs and pushes a map of them.
+    Map(u32),
+    /// Pops an index and a target and pushes `target[index]`.
+    GetIndex,
+    /// Pops a module and pushes its export named `names[index]`.
+    GetMember(u32),
+    /// Pops a value, an index and a target and performs
+    /// `target[index] = value`.
+    SetIndex,
+    /// Pops `end` and `start` and pushes the list `start..end`.
+    Range,
+    /// Checks that the two values on top of the stack are valid range
+    /// bounds, leaving them in place as the state of a `for` loop.
+    CheckRange,
+    /// Replaces the value on top of the stack with a snapshot of its items
+    /// and pushes a zero index, the state of a `for` loop over it.
+    IntoIterator,
+    /// Advances the `for` loop whose state is in the two locals starting at
+    /// `slot`, pushing the next item, or jumps to `exit` when it is done.
+    Iterate {
+        slot: u32,
+        exit: u32,
+    },
+    Jump(u32),
+    /// Pops the condition and jumps if it does not hold.
+    JumpIfFalse(u32),
+    /// Jumps, keeping the value on top of the stack, if it is false;
+    /// otherwise pops it. Used by `&&`.
+    JumpIfFalseOrPop(u32),
+    /// Jumps, keeping the value on top of the stack, if it is true;
+    /// otherwise pops it. Used by `||`.
+    JumpIfTrueOrPop(u32),
+    /// Calls the value below the given number of arguments.
+    Call(u32),
+    /// Creates a closure over `prototypes[index]`.
+    Closure(u32),
+    Return,
+    Print,
+    /// Pushes the module at the path `constants[index]`, running it first
+    /// if this is its first import.
+    Import(u32),
+}
+
+/// Compiled code together with its tables. `spans` runs parallel to `code`
+/// so runtime errors can point at the source of the failing instruction.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub prototypes: Vec<Rc<Prototype>>,
+}
+
+/// Where a closure finds one of its upvalues when it is created: a local
+/// slot of the enclosing frame, or an upvalue of the enclosing closure.
+///
+/// A slot reserved for a declaration that has not run when the closure is
+/// created comes with `pending`, the variable the name refers to in the
+/// enclosing function until the declaration runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capture {
+    pub local: bool,
+    pub index: u32,
+    pub pending: Option<Binding>,
+}
+
+/// A variable of the function creating a closure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Local(u32),
+    Upvalue(u32),
+    /// The global in the given slot.
+    Global(u32),
+}
+
+/// The names of the globals of one file, each with the slot the compiler
+/// gave it. The table lasts as long as the file's globals, so that all the
+/// code compiled for the file, such as successive REPL entries, agrees on
+/// the slots.
+#[derive(Debug, Default)]
+pub struct GlobalNames {
+    names: Vec<String>,
+    slots: HashMap<String, u32>,
+}
+
+impl GlobalNames {
+    /// The slot of `name`, giving it the next free one if it has none yet.
+    pub fn slot(&mut self, name: &str) -> u32 {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
+        }
+        let slot = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.slots.insert(name.to_string(), slot);
+        slot
+    }
+
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.slots.get(name).copied()
+    }
+
+    pub fn name(&self, slot: u32) -> &str {
+        &self.names[slot as usize]
+    }
+
+    /// All the names, in the order of their slots.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+/// A compiled function: the script itself or a `fn` declaration.
+#[derive(Debug, Default)]
+pub struct Prototype {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+impl Chunk {
+    pub fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.code.push(instruction);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        (self.constants.len() - 1) as u32
+    }
+
+    pub fn add_name(&mut self, name: &str) -> u32 {
+        match self.names.iter().position(|existing| existing == name) {
+            Some(index) => index as u32,
+            None => {
+                self.names.push(name.to_string());
+                (self.names.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Describes the operand of `instruction` for the disassembly listing.
+    fn describe(&self, instruction: Instruction, globals: &GlobalNames) -> String {
+        match instruction {
+            Instruction::Constant(index) | Instruction::Import(index) => {
+                format!("{:<4} ; {}", index, self.constants[index as usize].repr())
+            }
+            Instruction::GetGlobal(slot)
+            | Instruction::SetGlobal(slot)
+            | Instruction::DefineGlobal(slot) => {
+                format!("{:<4} ; {}", slot, globals.name(slot))
+            }
+            Instruction::GetMember(index) => {
+                format!("{:<4} ; {}", index, self.names[index as usize])
+            }
+            Instruction::Closure(index) => {
+                format!(
+                    "{:<4} ; <fn {}>",
+                    index, self.prototypes[index as usize].name
+                )
+            }
+            Instruction::PopLocals(operand)
+            | Instruction::GetLocal(operand)
+            | Instruction::SetLocal(operand)
+            | Instruction::DeclareLocal(operand)
+            | Instruction::GetUpvalue(operand)
+            | Instruction::SetUpvalue(operand)
+            | Instruction::List(operand)
+            | Instruction::Interpolate(operand)
+            | Instruction::Map(operand)
+            | Instruction::Call(operand) => operand.to_string(),
+            Instruction::Jump(target)
+            | Instruction::JumpIfFalse(target)
+            | Instruction::JumpIfFalseOrPop(target)
+            | Instruction::JumpIfTrueOrPop(target) => {
+                format!("-> {:04}", target)
+            }
+            Instruction::Iterate { slot, exit } => format!("{:<4} -> {:04}", slot, exit),
+            _ => String::new(),
+        }
+    }
+}
+
+impl Prototype {
+    /// Renders a human-readable listing of the prototype's code, followed by
+    /// the listings of the functions declared in it. `globals` names the
+    /// global slots the code was compiled against.
+    pub fn disassemble(&self, globals: &GlobalNames) -> String {
+        let mut output = String::new();
+        self.disassemble_into(&mut output, globals);
+        output
+    }
+
+    fn disassemble_into(&self, output: &mut String, globals: &GlobalNames) {
+        output.push_str(&format!("== {} ==\n", self.name));
+        for (offset, (instruction, span)) in
+            self.chunk.code.iter().zip(&self.chunk.spans).enumerate()
+        {
+            let name = format!("{:?}", instruction);
+            let name = name.split(['(', ' ']).next().unwrap_or_default();
+            let line = format!(
+                "{:04}  {:>7}  {:<16} {}",
+                offset,
+                span.to_string(),
+                name,
+                self.chunk.describe(*instruction, globals)
+            );
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+
+        for prototype in &self.chunk.prototypes {
+            output.push('\n');
+            prototype.disassemble_into(output, globals);
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Static analysis of a parsed script, as run by `netl check`.
+//!
+//! Without running anything, the checker reports errors the interpreter
+//! would raise once it reached the offending code, using the same
+//! diagnostics: reading a name that is not declared, assigning to one that
+//! is not declared, and operators applied to operands whose types are known
+//! from the code alone and do not support them, such as `"a" - 1` or
+//! `[x] + 1`. It also warns about local variables that are never read and
+//! about statements that can never run because they follow a `return`,
+//! `break` or `continue`.
+//!
+//! Names are resolved the way the interpreter resolves them: a block sees
+//! what was declared before it in the enclosing blocks, and a function body
+//! additionally sees every top-level declaration of the file, since
+//! functions usually run after the whole file has been.
+
+use std::collections::HashSet;
+
+use super::ast::{ASTNode, StringPart, Token};
+use super::diagnostic::Diagnostic;
+use super::exec::RuntimeError;
+use super::operations;
+use super::span::Span;
+use super::value::{Map, Value};
+
+/// Checks `program`, whose globals start out as `predefined`, such as the
+/// native functions. Returns the diagnostics in source order.
+pub fn check(program: &ASTNode, predefined: &[String]) -> Vec<Diagnostic> {
+    let ASTNode::Program(statements, _) = program else {
+        return Vec::new();
+    };
+
+    let mut checker = Checker {
+        scopes: vec![Scope::new(statements)],
+        functions: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    for name in predefined {
+        checker.declare(name, Span::default(), "");
+    }
+    checker.block(statements);
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.primary_span().map(|span| span.start));
+    diagnostics
+}
+
+struct Variable {
+    name: String,
+    span: Span,
+    /// What the name was declared as, for the warning if it is never read;
+    /// empty for names that are not reported, such as parameters.
+    kind: &'static str,
+    used: bool,
+}
+
+struct Scope {
+    /// The variables declared so far, in order.
+    variables: Vec<Variable>,
+    /// Every name the block declares, including further down. Function
+    /// bodies in the block may use them all, as they usually run after the
+    /// block has.
+    names: HashSet<String>,
+    /// Names that function bodies read before the block declared them.
+    read_early: HashSet<String>,
+}
+
+impl Scope {
+    fn new(statements: &[ASTNode]) -> Scope {
+        let names = statements
+            .iter()
+            .map(|statement| match statement {
+                ASTNode::Export(declaration, _) => &**declaration,
+                statement => statement,
+            })
+            .filter_map(|statement| match statement {
+                ASTNode::Variable(name, _, _)
+                | ASTNode::Function(name, _, _, _)
+                | ASTNode::Import(_, name, _) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        Scope {
+            variables: Vec::new(),
+            names,
+            read_early: HashSet::new(),
+        }
+    }
+}
+
+struct Checker {
+    /// The enclosing blocks, outermost (the globals) first.
+    scopes: Vec<Scope>,
+    /// The index in `scopes` of the parameters of each enclosing function.
+    functions: Vec<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    /// Checks the statements of a block, warning once about any that
+    /// follow a statement control never continues past.
+    fn block(&mut self, statements: &[ASTNode]) {
+        let mut exit: Option<Span> = None;
+        let mut reported = false;
+        for statement in statements {
+            match exit {
+                Some(exit) if !reported => {
+                    self.diagnostics.push(
+                        Diagnostic::warning("unreachable statement")
+                            .with_label(statement.span(), "unreachable statement")
+                            .with_secondary_label(exit, "any code following this is unreachable"),
+                    );
+                    reported = true;
+                }
+                None if diverges(statement) => exit = Some(statement.span()),
+                _ => {}
+            }
+            self.statement(statement);
+        }
+    }
+
+    fn scoped_block(&mut self, statements: &[ASTNode]) {
+        self.scopes.push(Scope::new(statements));
+        self.block(statements);
+        self.end_scope();
+    }
+
+    fn statement(&mut self, statement: &ASTNode) {
+        match statement {
+            ASTNode::Variable(name, value, span) => {
+                self.expression(value);
+                self.declare(name, *span, "variable");
+            }
+            ASTNode::Assignment(name, value, span) => {
+                self.expression(value);
+                if !self.resolve(name, false) {
+                    self.error(RuntimeError::UndeclaredAssignment {
+                        name: name.clone(),
+                        span: *span,
+                    });
+                }
+            }
+            ASTNode::IndexAssignment(target, index, value, _) => {
+                self.expression(target);
+                self.expression(index);
+                self.expression(value);
+            }
+            ASTNode::Print(value, _) | ASTNode::Expression(value, _) => {
+                self.expression(value);
+            }
+            ASTNode::Return(value, _) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            ASTNode::If(branches, otherwise, _) => {
+                for (condition, body) in branches {
+                    self.expression(condition);
+                    self.scoped_block(body);
+                }
+                if let Some(body) = otherwise {
+                    self.scoped_block(body);
+                }
+            }
+            ASTNode::While(condition, body, _) => {
+                self.expression(condition);
+                self.scoped_block(body);
+            }
+            ASTNode::For(variable, iterable, body, span) => {
+                self.expression(iterable);
+                self.scopes.push(Scope::new(body));
+                self.declare(variable, *span, "");
+                self.block(body);
+                self.end_scope();
+            }
+            ASTNode::Function(name, parameters, body, span) => {
+                // Declared first so that the function can call itself.
+                self.declare(name, *span, "function");
+                self.functions.push(self.scopes.len());
+                self.scopes.push(Scope::new(body));
+                for parameter in parameters {
+                    self.declare(parameter, *span, "");
+                }
+                self.block(body);
+                self.end_scope();
+                self.functions.pop();
+            }
+            ASTNode::Import(_, name, span) => self.declare(name, *span, "import"),
+            ASTNode::Export(declaration, _) => self.statement(declaration),
+            ASTNode::Break(_) | ASTNode::Continue(_) => {}
+            expression => {
+                self.expression(expression);
+            }
+        }
+    }
+
+    /// Checks an expression, returning a sample value of the type it is
+    /// known to evaluate to, if any.
+    fn expression(&mut self, expression: &ASTNode) -> Option<Value> {
+        match expression {
+            ASTNode::Number(_, _) => Some(Value::Int(1)),
+            ASTNode::Float(_, _) => Some(Value::Float(1.0)),
+            ASTNode::StringLiteral(_, _) => Some(Value::from("")),
+            ASTNode::Bool(_, _) => Some(Value::Bool(true)),
+            ASTNode::Nil(_) => Some(Value::Nil),
+            ASTNode::InterpolatedString(parts, _) => {
+                for part in parts {
+                    if let StringPart::Expression(expression) = part {
+                        self.expression(expression);
+                    }
+                }
+                Some(Value::from(""))
+            }
+            ASTNode::List(items, _) => {
+                for item in items {
+                    self.expression(item);
+                }
+                Some(Value::from(Vec::new()))
+            }
+            ASTNode::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                Some(Value::from(Map::new()))
+            }
+            ASTNode::Identifier(name, span) => {
+                if !self.resolve(name, true) {
+                    self.error(RuntimeError::UndefinedVariable {
+                        name: name.clone(),
+                        span: *span,
+                    });
+                }
+                None
+            }
+            ASTNode::UnaryOperation(operator, operand, span) => {
+                let operand = self.expression(operand)?;
+                self.known(operations::unary(operator, &operand, *span))
+            }
+            ASTNode::BinaryOperation(left, operator, right, span) => {
+                let (left, right) = (self.expression(left), self.expression(right));
+                let (left, right) = (left?, right?);
+                match operator {
+                    Token::DotDot => self.known(
+                        operations::range_bounds(&left, &right, *span)
+                            .map(|_| Value::from(Vec::new())),
+                    ),
+                    // The result is one of the operands.
+                    Token::AndAnd | Token::OrOr => {
+                        (left.type_name() == right.type_name()).then_some(left)
+                    }
+                    operator => self.known(operations::binary(&left, operator, &right, *span)),
+                }
+            }
+            ASTNode::Call(callee, arguments, _) => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                None
+            }
+            ASTNode::Index(target, index, _) => {
+                self.expression(target);
+                self.expression(index);
+                None
+            }
+            ASTNode::Member(target, _, _) => {
+                self.expression(target);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The sample value of the type an operation on sample values results
+    /// in, reporting the operation if the types do not support it.
+    fn known(&mut self, result: Result<Value, RuntimeError>) -> Option<Value> {
+        match result {
+            Ok(value) => Some(sample(&value)),
+            Err(
+                error
+                @ (RuntimeError::InvalidOperands { .. } | RuntimeError::InvalidOperand { .. }),
+            ) => {
+                self.error(error);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn declare(&mut self, name: &str, span: Span, kind: &'static str) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("the globals are never popped");
+        scope.variables.push(Variable {
+            name: name.to_string(),
+            span,
+            kind,
+            used: scope.read_early.contains(name),
+        });
+    }
+
+    /// Whether `name` can be found from the current block, marking it as
+    /// read if `read` is set. Blocks outside the current function count
+    /// everything they declare, wherever they declare it.
+    fn resolve(&mut self, name: &str, read: bool) -> bool {
+        let function = self.functions.last().copied().unwrap_or(0);
+        for (index, scope) in self.scopes.iter_mut().enumerate().rev() {
+            if let Some(variable) = scope
+                .variables
+                .iter_mut()
+                .rev()
+                .find(|variable| variable.name == name)
+            {
+                variable.used |= read;
+                return true;
+            }
+            if index < function && scope.names.contains(name) {
+                if read {
+                    scope.read_early.insert(name.to_string());
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Leaves a block, warning about its variables that were never read.
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().expect("every block has a scope");
+        for variable in scope.variables {
+            if !variable.used && !variable.kind.is_empty() {
+                self.diagnostics.push(
+                    Diagnostic::warning(format!("unused {} `{}`", variable.kind, variable.name))
+                        .with_label(variable.span, "never used")
+                        .with_help("remove it if it is not needed"),
+                );
+            }
+        }
+    }
+
+    fn error(&mut self, error: RuntimeError) {
+        self.diagnostics.push(error.to_diagnostic());
+    }
+}
+
+/// Whether control never continues past `statement` to the next one.
+fn diverges(statement: &ASTNode) -> bool {
+    match statement {
+        ASTNode::Return(..) | ASTNode::Break(_) | ASTNode::Continue(_) => true,
+        ASTNode::If(branches, Some(otherwise), _) => {
+            branches.iter().all(|(_, body)| body.iter().any(diverges))
+                && otherwise.iter().any(diverges)
+        }
+        _ => false,
+    }
+}
+
+/// A fixed value of the same type as `value`, so that checking an
+/// operation on samples depends only on the types involved.
+fn sample(value: &Value) -> Value {
+    match value {
+        Value::Int(_) => Value::Int(1),
+        Value::Float(_) => Value::Float(1.0),
+        Value::String(_) => Value::from(""),
+        Value::Bool(_) => Value::Bool(true),
+        Value::List(_) => Value::from(Vec::new()),
+        Value::Map(_) => Value::from(Map::new()),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::ast::parse;
+    use crate::logic::builtins::natives;
+    use crate::logic::diagnostic::Severity;
+    use crate::logic::tokenizer::tokenize;
+
+    /// The diagnostics for `source`, each as its severity, code, message
+    /// and the source text of every label.
+    fn check_source(source: &str) -> Vec<String> {
+        let program = parse(tokenize(source)).expect("the source parses");
+        let predefined: Vec<String> = natives().into_iter().map(|native| native.name).collect();
+        check(&program, &predefined)
+            .iter()
+            .map(|diagnostic| {
+                let severity = match diagnostic.severity {
+                    Severity::Error => "error",
+                    _ => "warning",
+                };
+                let labels: Vec<_> = diagnostic
+                    .labels
+                    .iter()
+                    .map(|label| {
+                        format!(
+                            "`{}`: {}",
+                            &source[label.span.start..label.span.end],
+                            label.message
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{}[{}] {} at {}",
+                    severity,
+                    diagnostic.code.unwrap_or(""),
+                    diagnostic.message,
+                    labels.join(", ")
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undefined_names() {
+        assert_eq!(
+            check_source("print x;\ny = 1;\nfn f() {\n    return z;\n}\nprint f();\n"),
+            [
+                "error[E0201] undefined variable `x` at `x`: not found in this scope",
+                "error[E0213] cannot assign to undeclared variable `y` at `y = 1;`: not declared in any enclosing scope",
+                "error[E0201] undefined variable `z` at `z`: not found in this scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_locals() {
+        assert_eq!(
+            check_source(
+                "fn f() {\n    let a = 1;\n    let b = 2;\n    return b;\n}\nprint f();\n"
+            ),
+            ["warning[] unused variable `a` at `let a = 1;`: never used"]
+        );
+        assert_eq!(
+            check_source("if true {\n    fn g() {}\n}\n"),
+            ["warning[] unused function `g` at `fn g() {}`: never used"]
+        );
+    }
+
+    #[test]
+    fn unreachable_code() {
+        assert_eq!(
+            check_source("fn f() {\n    return 1;\n    print 2;\n    print 3;\n}\nprint f();\n"),
+            ["warning[] unreachable statement at `print 2;`: unreachable statement, `return 1;`: any code following this is unreachable"]
+        );
+        assert_eq!(
+            check_source("while true {\n    break;\n    print 1;\n}\n"),
+            ["warning[] unreachable statement at `print 1;`: unreachable statement, `break;`: any code following this is unreachable"]
+        );
+    }
+
+    #[test]
+    fn ill_typed_literal_operations() {
+        assert_eq!(
+            check_source("print \"a\" - 1;\nprint [1] + 1;\nprint -\"a\";\nprint 1 + 2.5;\nprint \"a\" + \"b\";\n"),
+            [
+                "error[E0202] unsupported operand types for `-`: string and int at `\"a\" - 1`: string and int",
+                "error[E0202] unsupported operand types for `+`: list and int at `[1] + 1`: list and int",
+                "error[E0211] unsupported operand type for `-`: string at `-\"a\"`: this has type string",
+            ]
+        );
+    }
+
+    #[test]
+    fn globals_and_natives_are_defined() {
+        let source = "fn f() {\n    return g() + later;\n}\nfn g() {\n    return len([1]);\n}\nlet later = 1;\nlet unread = 2;\nprint f();\nprint str(later);\n";
+        assert_eq!(check_source(source), Vec::<String>::new());
+    }
+}
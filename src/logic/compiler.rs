@@ -0,0 +1,662 @@
+use std::rc::Rc;
+
+use super::ast::{ASTNode, StringPart, Token};
+use super::bytecode::{Binding, Capture, GlobalNames, Instruction, Prototype};
+use super::exec::RuntimeError;
+use super::span::Span;
+use super::value::Value;
+
+/// Compiles a parsed program into the prototype of its top-level code.
+///
+/// Top-level `let`s and `fn`s become globals, each given a slot in
+/// `globals`, the table of the file the program belongs to. As in the
+/// tree-walking interpreter, whether a global has been defined yet is only
+/// known at run time. Everything declared inside a block or function is
+/// resolved to a stack slot here, and variables of enclosing functions are
+/// reached through upvalues.
+pub fn compile(program: &ASTNode, globals: &mut GlobalNames) -> Result<Prototype, RuntimeError> {
+    let mut compiler = Compiler::new(globals);
+    let statements = match program {
+        ASTNode::Program(statements, _) => statements.as_slice(),
+        statement => std::slice::from_ref(statement),
+    };
+    compiler.statements(statements)?;
+    compiler.emit(Instruction::Nil, program.span());
+    compiler.emit(Instruction::Return, program.span());
+    Ok(compiler.finish())
+}
+
+/// Compiles a single expression into a prototype returning its value.
+pub fn compile_expression(
+    expression: &ASTNode,
+    globals: &mut GlobalNames,
+) -> Result<Prototype, RuntimeError> {
+    let mut compiler = Compiler::new(globals);
+    compiler.expression(expression)?;
+    compiler.emit(Instruction::Return, expression.span());
+    Ok(compiler.finish())
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    /// Whether the declaration itself has been compiled yet. Slots are
+    /// reserved for everything a block declares before its statements, but
+    /// until the declaration runs, the name still refers to any variable of
+    /// that name from an enclosing scope.
+    declared: bool,
+}
+
+struct Loop {
+    /// Scope depth outside the loop; locals deeper than this are popped
+    /// before jumping out of or back to the start of the loop.
+    depth: usize,
+    /// Target of `continue`.
+    start: usize,
+    /// `break` jumps still to be patched to the end of the loop.
+    breaks: Vec<usize>,
+}
+
+/// Compilation state of one function. Its locals mirror the stack slots of
+/// its call frame.
+struct FunctionState {
+    prototype: Prototype,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+struct Compiler<'a> {
+    /// The function being compiled, preceded by the functions enclosing it.
+    functions: Vec<FunctionState>,
+    globals: &'a mut GlobalNames,
+}
+
+impl Compiler<'_> {
+    fn new(globals: &mut GlobalNames) -> Compiler<'_> {
+        Compiler {
+            functions: vec![FunctionState::new("<script>", 0)],
+            globals,
+        }
+    }
+
+    fn finish(mut self) -> Prototype {
+        self.functions
+            .pop()
+            .expect("the script is always compiled")
+            .prototype
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("the script is always compiled")
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.current().prototype.chunk.emit(instruction, span)
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) {
+        let index = self.current().prototype.chunk.add_constant(value);
+        self.emit(Instruction::Constant(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        self.current().prototype.chunk.add_name(name)
+    }
+
+    fn next_offset(&mut self) -> u32 {
+        self.current().prototype.chunk.code.len() as u32
+    }
+
+    /// Points the jump at `offset` to the next instruction to be emitted.
+    fn patch(&mut self, offset: usize) {
+        let target = self.next_offset();
+        let code = &mut self.current().prototype.chunk.code;
+        code[offset] = match code[offset] {
+            Instruction::Jump(_) => Instruction::Jump(target),
+            Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+            Instruction::JumpIfFalseOrPop(_) => Instruction::JumpIfFalseOrPop(target),
+            Instruction::JumpIfTrueOrPop(_) => Instruction::JumpIfTrueOrPop(target),
+            Instruction::Iterate { slot, .. } => Instruction::Iterate { slot, exit: target },
+            instruction => unreachable!("{:?} is not a jump", instruction),
+        };
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        let function = self.current();
+        function.scope_depth -= 1;
+        let depth = function.scope_depth;
+        let count = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .count();
+        function.locals.truncate(function.locals.len() - count);
+        if count > 0 {
+            self.emit(Instruction::PopLocals(count as u32), span);
+        }
+    }
+
+    /// Compiles `statements` in a new scope.
+    fn block(&mut self, statements: &[ASTNode], span: Span) -> Result<(), RuntimeError> {
+        self.begin_scope();
+        self.hoist_declarations(statements, span);
+        self.statements(statements)?;
+        self.end_scope(span);
+        Ok(())
+    }
+
+    /// Reserves slots for the variables, functions and imports declared in
+    /// a scope before any of its statements run, so that functions declared
+    /// in the scope can capture them whatever the order of declaration. As
+    /// in the interpreter, whose scopes are looked up when used, a function
+    /// calling before a declaration has run sees the enclosing variable of
+    /// that name instead; see [`Binding`].
+    fn hoist_declarations(&mut self, statements: &[ASTNode], span: Span) {
+        if self.current().scope_depth == 0 {
+            return;
+        }
+
+        for statement in statements {
+            if let ASTNode::Variable(name, ..)
+            | ASTNode::Function(name, ..)
+            | ASTNode::Import(_, name, _) = statement
+            {
+                if self.local_in_scope(name).is_none() {
+                    self.emit(Instruction::Nil, span);
+                    self.reserve(name, false);
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str) {
+        self.reserve(name, true);
+    }
+
+    fn reserve(&mut self, name: &str, declared: bool) {
+        let function = self.current();
+        let depth = function.scope_depth;
+        function.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            declared,
+        });
+    }
+
+    /// The slot of `name` if it was declared in the innermost scope.
+    fn local_in_scope(&mut self, name: &str) -> Option<u32> {
+        let function = self.current();
+        let depth = function.scope_depth;
+        function
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .take_while(|(_, local)| local.depth == depth)
+            .find(|(_, local)| local.name == name)
+            .map(|(slot, _)| slot as u32)
+    }
+
+    /// Binds the value on top of the stack to `name` in the innermost scope.
+    /// Redeclaring a name in the same scope reuses its slot, which matches
+    /// the interpreter replacing the variable in its environment.
+    fn define(&mut self, name: &str, span: Span) {
+        if self.current().scope_depth == 0 {
+            let slot = self.globals.slot(name);
+            self.emit(Instruction::DefineGlobal(slot), span);
+        } else if let Some(slot) = self.local_in_scope(name) {
+            let local = &mut self.current().locals[slot as usize];
+            let instruction = if local.declared {
+                Instruction::SetLocal(slot)
+            } else {
+                Instruction::DeclareLocal(slot)
+            };
+            local.declared = true;
+            self.emit(instruction, span);
+        } else {
+            self.declare(name);
+        }
+    }
+
+    /// The slot of the variable `name` in the function at `level`. Only
+    /// `hoisted` includes the slots of declarations not compiled yet, which
+    /// nested functions may use as they run later.
+    fn resolve_local(&self, level: usize, name: &str, hoisted: bool) -> Option<u32> {
+        self.functions[level]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name && (local.declared || hoisted))
+            .map(|slot| slot as u32)
+    }
+
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Option<u32> {
+        if level == 0 {
+            return None;
+        }
+
+        let capture = match self.resolve_local(level - 1, name, true) {
+            Some(slot) => Capture {
+                local: true,
+                index: slot,
+                pending: self.pending_binding(level - 1, slot, name),
+            },
+            None => Capture {
+                local: false,
+                index: self.resolve_upvalue(level - 1, name)?,
+                pending: None,
+            },
+        };
+
+        let captures = &mut self.functions[level].prototype.captures;
+        let index = match captures.iter().position(|existing| *existing == capture) {
+            Some(index) => index,
+            None => {
+                captures.push(capture);
+                captures.len() - 1
+            }
+        };
+        Some(index as u32)
+    }
+
+    /// What `name` refers to in the function at `level` while the
+    /// declaration owning `slot` has not been compiled yet, or `None` once
+    /// it has.
+    fn pending_binding(&mut self, level: usize, slot: u32, name: &str) -> Option<Binding> {
+        if self.functions[level].locals[slot as usize].declared {
+            return None;
+        }
+
+        Some(if let Some(slot) = self.resolve_local(level, name, false) {
+            Binding::Local(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name) {
+            Binding::Upvalue(index)
+        } else {
+            Binding::Global(self.globals.slot(name))
+        })
+    }
+
+    fn get_variable(&mut self, name: &str, span: Span) {
+        let level = self.functions.len() - 1;
+        let instruction = if let Some(slot) = self.resolve_local(level, name, false) {
+            Instruction::GetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name) {
+            Instruction::GetUpvalue(index)
+        } else {
+            Instruction::GetGlobal(self.globals.slot(name))
+        };
+        self.emit(instruction, span);
+    }
+
+    fn set_variable(&mut self, name: &str, span: Span) {
+        let level = self.functions.len() - 1;
+        let instruction = if let Some(slot) = self.resolve_local(level, name, false) {
+            Instruction::SetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name) {
+            Instruction::SetUpvalue(index)
+        } else {
+            Instruction::SetGlobal(self.globals.slot(name))
+        };
+        self.emit(instruction, span);
+    }
+
+    fn statements(&mut self, statements: &[ASTNode]) -> Result<(), RuntimeError> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &ASTNode) -> Result<(), RuntimeError> {
+        match statement {
+            ASTNode::Variable(name, value, span) => {
+                self.expression(value)?;
+                self.define(name, *span);
+            }
+            ASTNode::Assignment(name, value, span) => {
+                self.expression(value)?;
+                self.set_variable(name, *span);
+            }
+            ASTNode::Print(expression, span) => {
+                self.expression(expression)?;
+                self.emit(Instruction::Print, *span);
+            }
+            ASTNode::If(branches, else_statements, span) => {
+                let mut end_jumps = Vec::new();
+                for (condition, statements) in branches {
+                    self.expression(condition)?;
+                    let next = self.emit(Instruction::JumpIfFalse(0), condition.span());
+                    self.block(statements, *span)?;
+                    end_jumps.push(self.emit(Instruction::Jump(0), *span));
+                    self.patch(next);
+                }
+                if let Some(else_statements) = else_statements {
+                    self.block(else_statements, *span)?;
+                }
+                for jump in end_jumps {
+                    self.patch(jump);
+                }
+            }
+            ASTNode::Function(name, parameters, body, span) => {
+                self.function(name, parameters, body, *span)?;
+                self.define(name, *span);
+            }
+            ASTNode::Return(value, span) => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => {
+                        self.emit(Instruction::Nil, *span);
+                    }
+                }
+                self.emit(Instruction::Return, *span);
+            }
+            ASTNode::IndexAssignment(target, index, value, span) => {
+                self.expression(target)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.emit(Instruction::SetIndex, *span);
+            }
+            ASTNode::Expression(expression, span) => {
+                self.expression(expression)?;
+                self.emit(Instruction::Pop, *span);
+            }
+            ASTNode::While(condition, body, span) => {
+                let start = self.next_offset() as usize;
+                self.expression(condition)?;
+                let exit = self.emit(Instruction::JumpIfFalse(0), condition.span());
+                self.loop_body(start, |compiler| compiler.block(body, *span))?;
+                self.emit(Instruction::Jump(start as u32), *span);
+                self.patch(exit);
+                self.finish_loop();
+            }
+            ASTNode::For(variable, iterable, body, span) => {
+                self.for_loop(variable, iterable, body, *span)?;
+            }
+            ASTNode::Import(path, name, span) => {
+                let index = self
+                    .current()
+                    .prototype
+                    .chunk
+                    .add_constant(Value::from(path.as_str()));
+                self.emit(Instruction::Import(index), *span);
+                self.define(name, *span);
+            }
+            ASTNode::Export(declaration, _) => self.statement(declaration)?,
+            ASTNode::Break(span) => {
+                self.pop_loop_locals(*span);
+                let jump = self.emit(Instruction::Jump(0), *span);
+                if let Some(innermost) = self.current().loops.last_mut() {
+                    innermost.breaks.push(jump);
+                }
+            }
+            ASTNode::Continue(span) => {
+                self.pop_loop_locals(*span);
+                let start = self
+                    .current()
+                    .loops
+                    .last()
+                    .map_or(0, |innermost| innermost.start);
+                self.emit(Instruction::Jump(start as u32), *span);
+            }
+            _ => {
+                return Err(RuntimeError::UnexpectedNode {
+                    node: "an expression as a statement".to_string(),
+                    span: statement.span(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a `for` loop. Two hidden locals hold its state: the current
+    /// and end values of a range, or a snapshot of the items and the index
+    /// of the next one.
+    fn for_loop(
+        &mut self,
+        variable: &str,
+        iterable: &ASTNode,
+        body: &[ASTNode],
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        self.begin_scope();
+        match iterable {
+            ASTNode::BinaryOperation(start, Token::DotDot, end, range_span) => {
+                self.expression(start)?;
+                self.expression(end)?;
+                self.emit(Instruction::CheckRange, *range_span);
+            }
+            iterable => {
+                self.expression(iterable)?;
+                self.emit(Instruction::IntoIterator, iterable.span());
+            }
+        }
+        // Parentheses keep the hidden locals from clashing with any variable.
+        self.declare("(state)");
+        self.declare("(position)");
+
+        let slot = self.current().locals.len() as u32 - 2;
+        let head = self.emit(Instruction::Iterate { slot, exit: 0 }, span);
+        self.loop_body(head, |compiler| {
+            compiler.begin_scope();
+            compiler.declare(variable);
+            compiler.hoist_declarations(body, span);
+            compiler.statements(body)?;
+            compiler.end_scope(span);
+            Ok(())
+        })?;
+        self.emit(Instruction::Jump(head as u32), span);
+        self.patch(head);
+        self.finish_loop();
+        self.end_scope(span);
+        Ok(())
+    }
+
+    fn loop_body(
+        &mut self,
+        start: usize,
+        body: impl FnOnce(&mut Compiler) -> Result<(), RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let depth = self.current().scope_depth;
+        self.current().loops.push(Loop {
+            depth,
+            start,
+            breaks: Vec::new(),
+        });
+        body(self)
+    }
+
+    /// Patches the `break`s of the innermost loop to jump here.
+    fn finish_loop(&mut self) {
+        if let Some(finished) = self.current().loops.pop() {
+            for jump in finished.breaks {
+                self.patch(jump);
+            }
+        }
+    }
+
+    /// Pops the locals declared inside the innermost loop, before `break` or
+    /// `continue` jumps out of their scope.
+    fn pop_loop_locals(&mut self, span: Span) {
+        let function = self.current();
+        let Some(innermost) = function.loops.last() else {
+            return;
+        };
+        let count = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > innermost.depth)
+            .count();
+        if count > 0 {
+            self.emit(Instruction::PopLocals(count as u32), span);
+        }
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        parameters: &[String],
+        body: &[ASTNode],
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        self.functions
+            .push(FunctionState::new(name, parameters.len()));
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(parameter);
+        }
+        self.hoist_declarations(body, span);
+        self.statements(body)?;
+        self.emit(Instruction::Nil, span);
+        self.emit(Instruction::Return, span);
+
+        let function = self.functions.pop().expect("pushed above");
+        let prototypes = &mut self.current().prototype.chunk.prototypes;
+        prototypes.push(Rc::new(function.prototype));
+        let index = prototypes.len() as u32 - 1;
+        self.emit(Instruction::Closure(index), span);
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &ASTNode) -> Result<(), RuntimeError> {
+        match expression {
+            ASTNode::Number(value, span) => self.emit_constant(Value::Int(*value), *span),
+            ASTNode::Float(value, span) => self.emit_constant(Value::Float(*value), *span),
+            ASTNode::StringLiteral(value, span) => {
+                self.emit_constant(Value::from(value.as_str()), *span)
+            }
+            ASTNode::Bool(value, span) => self.emit_constant(Value::Bool(*value), *span),
+            ASTNode::Nil(span) => {
+                self.emit(Instruction::Nil, *span);
+            }
+            ASTNode::InterpolatedString(parts, span) => {
+                for part in parts {
+                    match part {
+                        StringPart::Text(text) => {
+                            self.emit_constant(Value::from(text.as_str()), *span)
+                        }
+                        StringPart::Expression(expression) => self.expression(expression)?,
+                    }
+                }
+                self.emit(Instruction::Interpolate(parts.len() as u32), *span);
+            }
+            ASTNode::Identifier(name, span) => self.get_variable(name, *span),
+            ASTNode::BinaryOperation(
+                left,
+                operator @ (Token::AndAnd | Token::OrOr),
+                right,
+                span,
+            ) => {
+                self.expression(left)?;
+                let jump = match operator {
+                    Token::AndAnd => Instruction::JumpIfFalseOrPop(0),
+                    _ => Instruction::JumpIfTrueOrPop(0),
+                };
+                let end = self.emit(jump, *span);
+                self.expression(right)?;
+                self.patch(end);
+            }
+            ASTNode::BinaryOperation(left, operator, right, span) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                let instruction = match operator {
+                    Token::Plus => Instruction::Add,
+                    Token::Minus => Instruction::Subtract,
+                    Token::Asterisk => Instruction::Multiply,
+                    Token::Slash => Instruction::Divide,
+                    Token::Modulo => Instruction::Modulo,
+                    Token::EqualEqual => Instruction::Equal,
+                    Token::NotEqual => Instruction::NotEqual,
+                    Token::LessThan => Instruction::Less,
+                    Token::LessThanOrEqual => Instruction::LessEqual,
+                    Token::GreaterThan => Instruction::Greater,
+                    Token::GreaterThanOrEqual => Instruction::GreaterEqual,
+                    Token::In => Instruction::Contains,
+                    Token::DotDot => Instruction::Range,
+                    _ => {
+                        return Err(RuntimeError::UnexpectedNode {
+                            node: format!("the operator {}", operator),
+                            span: *span,
+                        })
+                    }
+                };
+                self.emit(instruction, *span);
+            }
+            ASTNode::UnaryOperation(operator, operand, span) => {
+                self.expression(operand)?;
+                let instruction = match operator {
+                    Token::Minus => Instruction::Negate,
+                    Token::Not => Instruction::Not,
+                    _ => {
+                        return Err(RuntimeError::UnexpectedNode {
+                            node: format!("the operator {}", operator),
+                            span: *span,
+                        })
+                    }
+                };
+                self.emit(instruction, *span);
+            }
+            ASTNode::Call(callee, arguments, span) => {
+                self.expression(callee)?;
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+                self.emit(Instruction::Call(arguments.len() as u32), *span);
+            }
+            ASTNode::List(items, span) => {
+                for item in items {
+                    self.expression(item)?;
+                }
+                self.emit(Instruction::List(items.len() as u32), *span);
+            }
+            ASTNode::Map(entries, span) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.emit(Instruction::Map(entries.len() as u32), *span);
+            }
+            ASTNode::Index(target, index, span) => {
+                self.expression(target)?;
+                self.expression(index)?;
+                self.emit(Instruction::GetIndex, *span);
+            }
+            ASTNode::Member(target, name, span) => {
+                self.expression(target)?;
+                let index = self.name(name);
+                self.emit(Instruction::GetMember(index), *span);
+            }
+            _ => {
+                return Err(RuntimeError::UnexpectedNode {
+                    node: "a statement as an expression".to_string(),
+                    span: expression.span(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize) -> FunctionState {
+        FunctionState {
+            prototype: Prototype {
+                name: name.to_string(),
+                arity,
+                ..Prototype::default()
+            },
+            locals: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use std::fmt;
+use std::rc::Rc;
+
+use super::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+/// A span of source annotated with a message. Primary labels mark the
+/// offending code (`^^^`), secondary labels add related context (`---`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+/// A named piece of source code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: Rc<str>,
+}
+
+/// An error about a piece of source code.
+///
+/// Error codes are grouped by the stage that produces them:
+/// `E00xx` for the tokenizer, `E01xx` for the parser and `E02xx` for the
+/// interpreter. The static checker reports the interpreter's errors ahead
+/// of time under the same codes; its warnings have no code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    /// The file the labels point into, when it is not the one the
+    /// diagnostic is rendered against, such as an imported module.
+    pub file: Option<Rc<SourceFile>>,
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: None,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+            file: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message)
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    pub fn with_secondary_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_file(mut self, file: Rc<SourceFile>) -> Diagnostic {
+        self.file = Some(file);
+        self
+    }
+
+    /// Span of the first primary label, if any.
+    pub fn primary_span(&self) -> Option<Span> {
+        self.labels
+            .iter()
+            .find(|label| label.primary)
+            .map(|label| label.span)
+    }
+
+    /// Renders the diagnostic with the offending source lines and caret
+    /// underlines, in the style of `rustc`. `color` enables ANSI escapes.
+    /// `source` and `file_name` are ignored if the diagnostic has a `file`.
+    pub fn render(&self, source: &str, file_name: &str, color: bool) -> String {
+        let (source, file_name) = match &self.file {
+            Some(file) => (&*file.text, file.name.as_str()),
+            None => (source, file_name),
+        };
+        let paint = |style: &'static str| if color { style } else { "" };
+        let reset = paint(RESET);
+        let mut out = String::new();
+
+        out.push_str(paint(self.severity.color()));
+        out.push_str(self.severity.as_str());
+        if let Some(code) = &self.code {
+            out.push_str(&format!("[{}]", code));
+        }
+        out.push_str(reset);
+        out.push_str(&format!("{}: {}{}\n", paint(BOLD), self.message, reset));
+
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(offset, _)| offset + 1));
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| (label.span.line, label.span.column));
+
+        let gutter = labels
+            .iter()
+            .map(|label| label.span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let blank = " ".repeat(gutter);
+        let pipe = format!("{}{} |{}", paint(BLUE), blank, reset);
+
+        if let Some(span) = self
+            .primary_span()
+            .or(labels.first().map(|label| label.span))
+        {
+            out.push_str(&format!(
+                "{}{}--> {}{}:{}:{}\n",
+                blank,
+                paint(BLUE),
+                reset,
+                file_name,
+                span.line,
+                span.column
+            ));
+        }
+
+        if !labels.is_empty() {
+            out.push_str(&pipe);
+            out.push('\n');
+        }
+
+        let mut previous_line = None;
+        for label in labels {
+            let line_number = label.span.line;
+            let line_start = line_starts
+                .get(line_number.saturating_sub(1))
+                .copied()
+                .unwrap_or(source.len());
+            let line = source[line_start..]
+                .split('\n')
+                .next()
+                .unwrap_or("")
+                .trim_end_matches('\r');
+
+            if previous_line != Some(line_number) {
+                out.push_str(&format!(
+                    "{}{:>width$} |{} {}\n",
+                    paint(BLUE),
+                    line_number,
+                    reset,
+                    line,
+                    width = gutter
+                ));
+                previous_line = Some(line_number);
+            }
+
+            // Only the part of the span on its first line is underlined.
+            let padding: String = line
+                .char_indices()
+                .take_while(|(offset, _)| line_start + offset < label.span.start)
+                .map(|(_, ch)| if ch == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = line
+                .char_indices()
+                .filter(|(offset, _)| {
+                    (label.span.start..label.span.end).contains(&(line_start + offset))
+                })
+                .count();
+            let (marker, style) = if label.primary {
+                ('^', self.severity.color())
+            } else {
+                ('-', BLUE)
+            };
+            let underline: String = std::iter::repeat_n(marker, width.max(1)).collect();
+
+            out.push_str(&format!(
+                "{} {}{}{}",
+                pipe,
+                padding,
+                paint(style),
+                underline
+            ));
+            if !label.message.is_empty() {
+                out.push(' ');
+                out.push_str(&label.message);
+            }
+            out.push_str(reset);
+            out.push('\n');
+        }
+
+        if !self.labels.is_empty() && (!self.notes.is_empty() || self.help.is_some()) {
+            out.push_str(&pipe);
+            out.push('\n');
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!(
+                "{} {}={} {}note{}: {}\n",
+                blank,
+                paint(BLUE),
+                reset,
+                paint(BOLD),
+                reset,
+                note
+            ));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!(
+                "{} {}={} {}help{}: {}\n",
+                blank,
+                paint(BLUE),
+                reset,
+                paint(BOLD),
+                reset,
+                help
+            ));
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.severity.as_str())?;
+        if let Some(code) = &self.code {
+            write!(f, "[{}]", code)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(span) = self.primary_span() {
+            write!(f, " at {}", span)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The span of the first occurrence of `text` in `source`.
+    fn span_of(source: &str, text: &str) -> Span {
+        let start = source.find(text).expect("the text is in the source");
+        let line_start = source[..start].rfind('\n').map_or(0, |offset| offset + 1);
+        let line = source[..start].matches('\n').count() + 1;
+        Span::new(start, start + text.len(), line, start - line_start + 1)
+    }
+
+    #[test]
+    fn renders_the_labelled_line_with_a_help() {
+        let source = "let x = 1;\nprint y;\n";
+        let diagnostic = Diagnostic::error("undefined variable `y`")
+            .with_code("E0201")
+            .with_label(span_of(source, "y"), "not found in this scope")
+            .with_help("declare it first");
+        assert_eq!(
+            diagnostic.render(source, "main.nl", false),
+            "\
+error[E0201]: undefined variable `y`
+ --> main.nl:2:7
+  |
+2 | print y;
+  |       ^ not found in this scope
+  |
+  = help: declare it first
+"
+        );
+    }
+
+    #[test]
+    fn renders_secondary_labels_and_notes_in_line_order() {
+        let source = format!(
+            "{}fn f() {{\n    return 1;\n    print 2;\n}}\n",
+            "\n".repeat(7)
+        );
+        let diagnostic = Diagnostic::error("unreachable statement")
+            .with_label(span_of(&source, "print 2;"), "unreachable")
+            .with_secondary_label(span_of(&source, "return 1;"), "returns here")
+            .with_note("first note")
+            .with_note("second note");
+        assert_eq!(
+            diagnostic.render(&source, "main.nl", false),
+            "\
+error: unreachable statement
+  --> main.nl:10:5
+   |
+ 9 |     return 1;
+   |     --------- returns here
+10 |     print 2;
+   |     ^^^^^^^^ unreachable
+   |
+   = note: first note
+   = note: second note
+"
+        );
+    }
+
+    #[test]
+    fn renders_warnings_under_their_own_heading() {
+        let source = "fn f() {\n    return 1;\n    print 2;\n}\n";
+        let diagnostic = Diagnostic::warning("unreachable statement")
+            .with_label(span_of(source, "print 2;"), "unreachable")
+            .with_secondary_label(span_of(source, "return 1;"), "returns here");
+        assert_eq!(
+            diagnostic.render(source, "main.nl", false),
+            "\
+warning: unreachable statement
+ --> main.nl:3:5
+  |
+2 |     return 1;
+  |     --------- returns here
+3 |     print 2;
+  |     ^^^^^^^^ unreachable
+"
+        );
+    }
+
+    #[test]
+    fn labels_on_one_line_share_it() {
+        let source = "print \"a\" - 1;";
+        let diagnostic = Diagnostic::error("unsupported operand types")
+            .with_label(span_of(source, "\"a\" - 1"), "")
+            .with_secondary_label(span_of(source, "1"), "this is an int");
+        assert_eq!(
+            diagnostic.render(source, "main.nl", false),
+            "\
+error: unsupported operand types
+ --> main.nl:1:7
+  |
+1 | print \"a\" - 1;
+  |       ^^^^^^^
+  |             - this is an int
+"
+        );
+    }
+
+    #[test]
+    fn underlines_keep_tabs_and_stop_at_the_end_of_the_line() {
+        let source = "\tlet s = \"ab\n\tcd\";";
+        let diagnostic =
+            Diagnostic::error("multi-line").with_label(span_of(source, "\"ab\n\tcd\""), "here");
+        assert_eq!(
+            diagnostic.render(source, "main.nl", false),
+            "\
+error: multi-line
+ --> main.nl:1:10
+  |
+1 | \tlet s = \"ab
+  | \t        ^^^ here
+"
+        );
+    }
+
+    #[test]
+    fn renders_only_the_header_and_notes_without_labels() {
+        let diagnostic = Diagnostic::error("cannot read").with_note("no such file");
+        assert_eq!(
+            diagnostic.render("", "main.nl", false),
+            "error: cannot read\n  = note: no such file\n"
+        );
+    }
+
+    #[test]
+    fn renders_the_source_of_the_file_the_diagnostic_belongs_to() {
+        let file = Rc::new(SourceFile {
+            name: "lib.nl".to_string(),
+            text: Rc::from("print 1 / 0;\n"),
+        });
+        let diagnostic = Diagnostic::error("division by zero")
+            .with_label(span_of(&file.text, "1 / 0"), "")
+            .with_file(file);
+        assert_eq!(
+            diagnostic.render("import \"lib\" as lib;", "main.nl", false),
+            "\
+error: division by zero
+ --> lib.nl:1:7
+  |
+1 | print 1 / 0;
+  |       ^^^^^
+"
+        );
+    }
+
+    #[test]
+    fn color_wraps_the_parts_in_ansi_escapes() {
+        let source = "x;";
+        let diagnostic = Diagnostic::error("unused")
+            .with_code("E1")
+            .with_label(span_of(source, "x"), "here");
+        let rendered = diagnostic.render(source, "main.nl", true);
+        assert!(rendered.starts_with(&format!(
+            "{}error[E1]{}{}: unused{}\n",
+            RED, RESET, BOLD, RESET
+        )));
+        assert!(rendered.contains(&format!("{}^ here{}", RED, RESET)));
+        let plain = [RED, BLUE, BOLD, RESET]
+            .iter()
+            .fold(rendered, |text, style| text.replace(style, ""));
+        assert_eq!(plain, diagnostic.render(source, "main.nl", false));
+    }
+
+    #[test]
+    fn warnings_are_colored_yellow() {
+        let source = "x;";
+        let diagnostic = Diagnostic::warning("unused").with_label(span_of(source, "x"), "here");
+        let rendered = diagnostic.render(source, "main.nl", true);
+        assert!(rendered.starts_with(&format!(
+            "{}warning{}{}: unused{}\n",
+            YELLOW, RESET, BOLD, RESET
+        )));
+        assert!(rendered.contains(&format!("{}^ here{}", YELLOW, RESET)));
+        assert!(!rendered.contains(RED));
+    }
+
+    #[test]
+    fn display_is_a_single_line() {
+        let diagnostic = Diagnostic::error("undefined variable `y`")
+            .with_code("E0201")
+            .with_label(Span::new(17, 18, 2, 7), "not found");
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[E0201]: undefined variable `y` at 2:7"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::value::Value;
+
+/// One lexical scope. Lookups and assignments walk outwards through the
+/// parent scopes; `let` always defines in the innermost one, shadowing any
+/// outer variable of the same name.
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    parent: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Rc<RefCell<Environment>> {
+        Rc::new(RefCell::new(Environment::default()))
+    }
+
+    pub fn with_parent(parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        Rc::new(RefCell::new(Environment {
+            values: HashMap::new(),
+            parent: Some(parent),
+        }))
+    }
+
+    pub fn define(&mut self, name: String, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Variables declared directly in this scope, sorted by name.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: Vec<_> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    /// Updates an existing variable in the nearest scope declaring it.
+    /// Returns `false` if no enclosing scope declares `name`.
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        match self.values.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &self.parent {
+                Some(parent) => parent.borrow_mut().assign(name, value),
+                None => false,
+            },
+        }
+    }
+}
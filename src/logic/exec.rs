@@ -1,207 +1,1245 @@
-use super::ast::{ASTNode, Token};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
 
-struct Interpreter {
-    variables: std::collections::HashMap<String, ASTNode>,
+use super::ast::{ASTNode, StringPart, Token};
+use super::builtins;
+use super::diagnostic::{Diagnostic, SourceFile};
+use super::environment::Environment;
+use super::operations;
+use super::span::Span;
+use super::value::{Function, Value};
+use crate::loader::{same_file, Import, Loader};
+use crate::sandbox::Capabilities;
+
+/// An error raised while executing a script. Every variant carries the span
+/// of the code that failed so hosts can report it against the source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    InvalidOperands {
+        operator: Token,
+        left: &'static str,
+        right: &'static str,
+        span: Span,
+    },
+    InvalidOperand {
+        operator: Token,
+        operand: &'static str,
+        span: Span,
+    },
+    DivisionByZero {
+        span: Span,
+    },
+    IntegerOverflow {
+        span: Span,
+    },
+    UnexpectedNode {
+        node: String,
+        span: Span,
+    },
+    NotCallable {
+        type_name: &'static str,
+        span: Span,
+    },
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    StackOverflow {
+        span: Span,
+    },
+    NativeError {
+        name: String,
+        message: String,
+        span: Span,
+    },
+    NotIterable {
+        type_name: &'static str,
+        span: Span,
+    },
+    UndeclaredAssignment {
+        name: String,
+        span: Span,
+    },
+    Output {
+        message: String,
+        span: Span,
+    },
+    IndexOutOfBounds {
+        index: i64,
+        length: usize,
+        span: Span,
+    },
+    NotIndexable {
+        type_name: &'static str,
+        span: Span,
+    },
+    InvalidIndex {
+        container: &'static str,
+        index: &'static str,
+        span: Span,
+    },
+    MissingKey {
+        key: String,
+        span: Span,
+    },
+    InvalidKey {
+        type_name: &'static str,
+        span: Span,
+    },
+    ModuleNotFound {
+        path: String,
+        reason: String,
+        span: Span,
+    },
+    /// The files of an import cycle, starting and ending with the same one.
+    ImportCycle {
+        cycle: Vec<String>,
+        span: Span,
+    },
+    /// An error raised in another file than the code it propagated to,
+    /// already converted to a diagnostic about that file. `module` is the
+    /// file's name, `None` for the main script, and `span` is the import or
+    /// call through which the error arrived.
+    InModule {
+        module: Option<String>,
+        diagnostic: Box<Diagnostic>,
+        span: Span,
+    },
+    MissingExport {
+        module: String,
+        name: String,
+        span: Span,
+    },
+    NoMembers {
+        type_name: &'static str,
+        span: Span,
+    },
+    /// An import that would make more files run at once than `limit`.
+    ImportTooDeep {
+        limit: usize,
+        span: Span,
+    },
+    /// A module outside the directories the script may import from.
+    ImportDenied {
+        path: String,
+        span: Span,
+    },
 }
 
-impl Interpreter {
-    fn new() -> Interpreter {
-        Interpreter {
-            variables: std::collections::HashMap::new(),
+impl RuntimeError {
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::InvalidOperands { span, .. }
+            | RuntimeError::InvalidOperand { span, .. }
+            | RuntimeError::DivisionByZero { span }
+            | RuntimeError::IntegerOverflow { span }
+            | RuntimeError::UnexpectedNode { span, .. }
+            | RuntimeError::NotCallable { span, .. }
+            | RuntimeError::ArityMismatch { span, .. }
+            | RuntimeError::StackOverflow { span }
+            | RuntimeError::NativeError { span, .. }
+            | RuntimeError::NotIterable { span, .. }
+            | RuntimeError::UndeclaredAssignment { span, .. }
+            | RuntimeError::Output { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::NotIndexable { span, .. }
+            | RuntimeError::InvalidIndex { span, .. }
+            | RuntimeError::MissingKey { span, .. }
+            | RuntimeError::InvalidKey { span, .. }
+            | RuntimeError::ModuleNotFound { span, .. }
+            | RuntimeError::ImportCycle { span, .. }
+            | RuntimeError::InModule { span, .. }
+            | RuntimeError::MissingExport { span, .. }
+            | RuntimeError::NoMembers { span, .. }
+            | RuntimeError::ImportTooDeep { span, .. }
+            | RuntimeError::ImportDenied { span, .. } => *span,
         }
     }
 
-    fn interpret(&mut self, ast: ASTNode) {
-        match ast {
-            ASTNode::Program(statements) => {
-                for statement in statements.iter() {
-                    self.interpret(statement.clone());
-                }
+    /// The error code. Errors in imported modules keep the code of the
+    /// original error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::UndefinedVariable { .. } => "E0201",
+            RuntimeError::InvalidOperands { .. } => "E0202",
+            RuntimeError::DivisionByZero { .. } => "E0203",
+            RuntimeError::IntegerOverflow { .. } => "E0204",
+            RuntimeError::UnexpectedNode { .. } => "E0205",
+            RuntimeError::NotCallable { .. } => "E0206",
+            RuntimeError::ArityMismatch { .. } => "E0207",
+            RuntimeError::StackOverflow { .. } => "E0209",
+            RuntimeError::NativeError { .. } => "E0210",
+            RuntimeError::InvalidOperand { .. } => "E0211",
+            RuntimeError::NotIterable { .. } => "E0212",
+            RuntimeError::UndeclaredAssignment { .. } => "E0213",
+            RuntimeError::Output { .. } => "E0214",
+            RuntimeError::IndexOutOfBounds { .. } => "E0215",
+            RuntimeError::NotIndexable { .. } => "E0216",
+            RuntimeError::InvalidIndex { .. } => "E0217",
+            RuntimeError::MissingKey { .. } => "E0218",
+            RuntimeError::InvalidKey { .. } => "E0219",
+            RuntimeError::ModuleNotFound { .. } => "E0220",
+            RuntimeError::ImportCycle { .. } => "E0221",
+            RuntimeError::MissingExport { .. } => "E0222",
+            RuntimeError::NoMembers { .. } => "E0223",
+            RuntimeError::ImportTooDeep { .. } => "E0224",
+            RuntimeError::ImportDenied { .. } => "E0225",
+            RuntimeError::InModule { diagnostic, .. } => diagnostic.code.unwrap_or(""),
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        if let RuntimeError::InModule {
+            module,
+            diagnostic,
+            span,
+        } = self
+        {
+            return (**diagnostic).clone().with_note(format!(
+                "in {}, entered at {}",
+                module_name(module),
+                span
+            ));
+        }
+
+        let label = match self {
+            RuntimeError::UndefinedVariable { .. } => "not found in this scope".to_string(),
+            RuntimeError::InvalidOperands { left, right, .. } => format!("{} and {}", left, right),
+            RuntimeError::InvalidOperand { operand, .. } => format!("this has type {}", operand),
+            RuntimeError::DivisionByZero { .. } => "the divisor evaluates to zero".to_string(),
+            RuntimeError::IntegerOverflow { .. } => "result does not fit in an int".to_string(),
+            RuntimeError::UnexpectedNode { .. } => String::new(),
+            RuntimeError::NotCallable { type_name, .. } => format!("this has type {}", type_name),
+            RuntimeError::ArityMismatch { expected, .. } => {
+                format!("expected {} argument{}", expected, plural(*expected))
             }
-            ASTNode::Variable(identifier, value) => {
-                let evaluated_value = self.evaluate_expression(*value);
-                self.variables.insert(identifier, evaluated_value);
+            RuntimeError::StackOverflow { .. } => "too many nested calls".to_string(),
+            RuntimeError::NativeError { name, .. } => format!("in call to `{}`", name),
+            RuntimeError::NotIterable { type_name, .. } => format!("this has type {}", type_name),
+            RuntimeError::UndeclaredAssignment { .. } => {
+                "not declared in any enclosing scope".to_string()
             }
-            ASTNode::Assignment(identifier, value) => {
-                let evaluated_value = self.evaluate_expression(*value);
-                self.variables.insert(identifier, evaluated_value);
+            RuntimeError::Output { .. } => "while printing this".to_string(),
+            RuntimeError::IndexOutOfBounds { length, .. } => {
+                format!("the length is {}", length)
             }
-            ASTNode::Print(expression) => {
-                let evaluated_expression = self.evaluate_expression(*expression);
-                println!("{}", self.stringify_value(evaluated_expression));
+            RuntimeError::NotIndexable { type_name, .. } => format!("this has type {}", type_name),
+            RuntimeError::InvalidIndex { index, .. } => format!("the index has type {}", index),
+            RuntimeError::MissingKey { .. } => "no such key".to_string(),
+            RuntimeError::InvalidKey { type_name, .. } => format!("this has type {}", type_name),
+            RuntimeError::ModuleNotFound { .. } => "imported here".to_string(),
+            RuntimeError::ImportCycle { .. } => "imports a file that is still running".to_string(),
+            RuntimeError::InModule { .. } => String::new(),
+            RuntimeError::MissingExport { name, .. } => format!("`{}` is not exported", name),
+            RuntimeError::NoMembers { type_name, .. } => format!("this has type {}", type_name),
+            RuntimeError::ImportTooDeep { .. } => "imported here".to_string(),
+            RuntimeError::ImportDenied { .. } => "imported here".to_string(),
+        };
+        let diagnostic = Diagnostic::error(self.to_string())
+            .with_code(self.code())
+            .with_label(self.span(), label);
+
+        match self {
+            RuntimeError::UndefinedVariable { name, .. } => {
+                diagnostic.with_help(format!("declare it first with `let {} = ...;`", name))
             }
-            ASTNode::If(expression, statements) => {
-                if self.evaluate_expression(*expression.clone()) == ASTNode::Number(1) {
-                    for statement in statements.iter() {
-                        self.interpret(*statement.clone());
-                    }
-                }
+            RuntimeError::UndeclaredAssignment { name, .. } => {
+                diagnostic.with_help(format!("use `let {} = ...;` to declare a new variable", name))
             }
-            ASTNode::IfElse(expression, if_statements, else_statements) => {
-                if self.evaluate_expression(*expression.clone()) == ASTNode::Number(1) {
-                    for statement in if_statements.iter() {
-                        self.interpret(*statement.clone());
-                    }
-                } else {
-                    for statement in else_statements.iter() {
-                        self.interpret(*statement.clone());
-                    }
-                }
+            RuntimeError::MissingKey { .. } => {
+                diagnostic.with_help("use `key in map` to check whether a key exists")
+            }
+            RuntimeError::InvalidKey { .. } => {
+                diagnostic.with_note("map keys must be strings, ints or bools")
+            }
+            RuntimeError::ModuleNotFound { .. } => diagnostic
+                .with_note("imports are resolved relative to the directory of the importing file"),
+            RuntimeError::ImportCycle { .. } => diagnostic
+                .with_help("move the code both files need into a separate module"),
+            RuntimeError::MissingExport { .. } => diagnostic
+                .with_note("only declarations marked with `export` can be used from other files"),
+            RuntimeError::NoMembers { .. } => {
+                diagnostic.with_note("only modules have members; use `value[key]` for maps")
             }
-            _ => panic!("Unexpected AST node: {:?}", ast),
+            RuntimeError::ImportTooDeep { .. } => diagnostic
+                .with_note("each import runs before the importing file goes on; shorten the chain of imports"),
+            RuntimeError::ImportDenied { .. } => diagnostic.with_note(
+                "modules can be imported from the script's directory and from directories it may read",
+            ),
+            RuntimeError::StackOverflow { .. } => {
+                diagnostic.with_note("calls nest too deeply to run; check for unbounded recursion")
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl RuntimeError {
+    /// Attributes an error raised in `file` (`None` for the main script)
+    /// to that file, as it propagates through the import or call at `span`
+    /// into code from another file. Errors already attributed to a file
+    /// keep it and gain a note about the way they came.
+    pub fn in_file(self, file: Option<&Rc<SourceFile>>, span: Span) -> RuntimeError {
+        let diagnostic = match (&self, file) {
+            (RuntimeError::InModule { .. }, _) | (_, None) => self.to_diagnostic(),
+            (_, Some(file)) => self.to_diagnostic().with_file(file.clone()),
+        };
+        RuntimeError::InModule {
+            module: file.map(|file| file.name.clone()),
+            diagnostic: Box::new(diagnostic),
+            span,
         }
     }
+}
+
+fn module_name(module: &Option<String>) -> String {
+    match module {
+        // Code without a file of its own, such as a REPL entry.
+        Some(name) if name.starts_with('<') => name.clone(),
+        Some(name) => format!("module `{}`", name),
+        None => "the main script".to_string(),
+    }
+}
 
-    fn evaluate_expression(&mut self, ast: ASTNode) -> ASTNode {
-        match ast {
-            ASTNode::BinaryOperation(left, operator, right) => {
-                let left_value = self.evaluate_expression(*left);
-                let right_value = self.evaluate_expression(*right);
-                self.evaluate_binary_operation(left_value, operator, right_value)
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedVariable { name, .. } => {
+                write!(f, "undefined variable `{}`", name)
+            }
+            RuntimeError::InvalidOperands {
+                operator,
+                left,
+                right,
+                ..
+            } => write!(
+                f,
+                "unsupported operand types for {}: {} and {}",
+                operator, left, right
+            ),
+            RuntimeError::InvalidOperand {
+                operator, operand, ..
+            } => write!(f, "unsupported operand type for {}: {}", operator, operand),
+            RuntimeError::DivisionByZero { .. } => write!(f, "division by zero"),
+            RuntimeError::IntegerOverflow { .. } => write!(f, "integer overflow"),
+            RuntimeError::UnexpectedNode { node, .. } => {
+                write!(f, "cannot evaluate {} here", node)
+            }
+            RuntimeError::NotCallable { type_name, .. } => {
+                write!(f, "cannot call a value of type {}", type_name)
+            }
+            RuntimeError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "`{}` takes {} argument{} but {} {} supplied",
+                name,
+                expected,
+                plural(*expected),
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            RuntimeError::StackOverflow { .. } => write!(f, "stack overflow"),
+            RuntimeError::NativeError { message, .. } => write!(f, "{}", message),
+            RuntimeError::NotIterable { type_name, .. } => {
+                write!(f, "cannot iterate over a value of type {}", type_name)
+            }
+            RuntimeError::UndeclaredAssignment { name, .. } => {
+                write!(f, "cannot assign to undeclared variable `{}`", name)
+            }
+            RuntimeError::Output { message, .. } => write!(f, "cannot write output: {}", message),
+            RuntimeError::IndexOutOfBounds { index, .. } => {
+                write!(f, "index {} is out of bounds", index)
+            }
+            RuntimeError::NotIndexable { type_name, .. } => {
+                write!(f, "cannot index into a value of type {}", type_name)
+            }
+            RuntimeError::InvalidIndex {
+                container, index, ..
+            } => write!(
+                f,
+                "cannot index a {} with a value of type {}",
+                container, index
+            ),
+            RuntimeError::MissingKey { key, .. } => write!(f, "key {} not found in map", key),
+            RuntimeError::InvalidKey { type_name, .. } => {
+                write!(
+                    f,
+                    "a value of type {} cannot be used as a map key",
+                    type_name
+                )
             }
-            ASTNode::Identifier(identifier) => {
-                self.variables
-                    .get(&identifier)
-                    .expect(&format!("Undefined variable: {}", identifier))
-                    .clone()
+            RuntimeError::ModuleNotFound { path, reason, .. } => {
+                write!(f, "cannot import `{}`: {}", path, reason)
+            }
+            RuntimeError::ImportCycle { cycle, .. } => {
+                write!(f, "import cycle: {}", cycle.join(" -> "))
+            }
+            RuntimeError::InModule {
+                module, diagnostic, ..
+            } => write!(f, "{} in {}", diagnostic.message, module_name(module)),
+            RuntimeError::MissingExport { module, name, .. } => {
+                write!(f, "module `{}` has no export `{}`", module, name)
+            }
+            RuntimeError::NoMembers { type_name, .. } => {
+                write!(f, "a value of type {} has no members", type_name)
+            }
+            RuntimeError::ImportTooDeep { limit, .. } => {
+                write!(f, "imports nest more than {} files deep", limit)
+            }
+            RuntimeError::ImportDenied { path, .. } => {
+                write!(f, "permission denied: cannot import `{}`", path)
             }
-            ASTNode::Number(value) => ASTNode::Number(value),
-            ASTNode::StringLiteral(value) => ASTNode::StringLiteral(value),
-            _ => panic!("Unexpected AST node: {:?}", ast),
         }
     }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Maximum number of nested function calls before a script is aborted with
+/// [`RuntimeError::StackOverflow`]. Both backends keep their call frames on
+/// the heap, so this only bounds the memory a runaway recursion can take.
+pub(crate) const MAX_CALL_DEPTH: usize = 100_000;
+
+/// The statements of a block, ready to run. Before running, the syntax tree
+/// is converted into nodes shared by reference count, so that running a
+/// statement or calling a function never copies any code.
+pub(crate) type Block = Rc<[Rc<Statement>]>;
+
+/// A statement as the tree-walker runs it.
+pub(crate) enum Statement {
+    Let(String, Rc<Expression>),
+    Assign(String, Rc<Expression>, Span),
+    Print(Rc<Expression>, Span),
+    If(Vec<(Rc<Expression>, Block)>, Option<Block>),
+    Function(String, Vec<String>, Block),
+    Return(Option<Rc<Expression>>),
+    SetIndex(Rc<Expression>, Rc<Expression>, Rc<Expression>, Span),
+    Expression(Rc<Expression>),
+    While(Rc<Expression>, Block),
+    /// `for` over the values of an expression, written at the span.
+    For(String, Rc<Expression>, Span, Block),
+    /// `for` over a range, which is iterated without building a list.
+    ForRange(String, Rc<Expression>, Rc<Expression>, Span, Block),
+    Break,
+    Continue,
+    Import(String, String, Span),
+}
 
-    fn evaluate_binary_operation(&mut self, left: ASTNode, operator: Token, right: ASTNode) -> ASTNode {
-        match operator {
-            Token::Plus => self.evaluate_addition(left, right),
-            Token::Minus => self.evaluate_subtraction(left, right),
-            Token::Asterisk => self.evaluate_multiplication(left, right),
-            Token::Slash => self.evaluate_division(left, right),
-            Token::Modulo => self.evaluate_modulo(left, right),
-            Token::Equal => self.evaluate_equal(left, right),
-            Token::NotEqual => self.evaluate_not_equal(left, right),
-            Token::LessThan => self.evaluate_less_than(left, right),
-            Token::GreaterThan => self.evaluate_greater_than(left, right),
-            _ => panic!("Unexpected operator: {:?}", operator),
+/// An expression as the tree-walker evaluates it.
+pub(crate) enum Expression {
+    Value(Value),
+    Variable(String, Span),
+    Binary(Rc<Expression>, Token, Rc<Expression>, Span),
+    /// `&&` or `||`, which only evaluate their right operand when the left
+    /// one does not decide the result.
+    Logical(Rc<Expression>, Token, Rc<Expression>),
+    Range(Rc<Expression>, Rc<Expression>, Span),
+    Unary(Token, Rc<Expression>, Span),
+    Call(Rc<Expression>, Vec<Rc<Expression>>, Span),
+    List(Vec<Rc<Expression>>),
+    Map(Vec<(Rc<Expression>, Rc<Expression>)>, Span),
+    Index(Rc<Expression>, Rc<Expression>, Span),
+    Member(Rc<Expression>, String, Span),
+    Interpolation(Vec<Rc<Expression>>),
+}
+
+/// Work the interpreter has left to do. Instead of recursing into the code
+/// it runs, the interpreter keeps a stack of tasks, so scripts can nest
+/// calls as deeply as they can on the VM without using up the host's stack.
+enum Task {
+    /// Runs the statements of a block from the given position on.
+    Run(Block, usize),
+    /// Evaluates an expression, pushing its value.
+    Evaluate(Rc<Expression>),
+    /// Finishes an expression whose operands are on top of the value stack.
+    Apply(Rc<Expression>),
+    /// Finishes a statement whose expressions are on top of the value stack.
+    Complete(Rc<Statement>),
+    /// Runs the body of the given branch of an `if` if its condition, on
+    /// top of the value stack, holds, or tries the next branch.
+    Branch(Rc<Statement>, usize),
+    /// Evaluates the condition of a `while` loop before a pass.
+    Repeat(Rc<Statement>),
+    /// Runs a pass of a `while` loop if its condition, on top of the value
+    /// stack, holds.
+    Pass(Rc<Statement>),
+    /// Runs the next pass of a `for` loop, if there are items left.
+    Iterate(Rc<Statement>, Items),
+    /// Makes a scope the innermost one again once a block has run.
+    Restore(Rc<RefCell<Environment>>),
+    /// Returns from a function to its caller, in the given file, which
+    /// called it at the span.
+    Return(Option<Rc<SourceFile>>, Span),
+}
+
+/// The items a `for` loop has yet to go through. Ranges are iterated
+/// lazily; lists and strings over a snapshot taken before the first pass.
+enum Items {
+    Range(std::ops::Range<i64>),
+    Values(std::vec::IntoIter<Value>),
+}
+
+impl Iterator for Items {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Items::Range(range) => range.next().map(Value::Int),
+            Items::Values(values) => values.next(),
+        }
+    }
+}
+
+/// Tree-walking interpreter. Globals persist across calls to [`run`], so one
+/// interpreter can execute several programs against the same state.
+///
+/// [`run`]: Interpreter::run
+pub struct Interpreter {
+    /// The module scope of the running program.
+    globals: Rc<RefCell<Environment>>,
+    /// The innermost scope of the code currently executing.
+    environment: Rc<RefCell<Environment>>,
+    call_depth: usize,
+    /// Where `print` writes to; stdout unless replaced by the host.
+    output: Box<dyn Write>,
+    loader: Loader,
+    /// The file of the code currently executing; `None` for the main script.
+    file: Option<Rc<SourceFile>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let globals = Environment::new();
+        for native in builtins::natives() {
+            let name = native.name.clone();
+            globals
+                .borrow_mut()
+                .define(name, Value::Native(Rc::new(native)));
+        }
+        Interpreter {
+            environment: globals.clone(),
+            globals,
+            call_depth: 0,
+            output: Box::new(io::stdout()),
+            loader: Loader::new(),
+            file: None,
         }
     }
 
-    fn evaluate_less_than(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                if left_value < right_value {
-                    ASTNode::Number(1)
-                } else {
-                    ASTNode::Number(0)
+    /// Sets the file being run, which imports are resolved against.
+    pub fn set_script_path(&mut self, path: &Path) {
+        self.loader.set_script_path(path);
+    }
+
+    /// Sets the file the code run next comes from, `None` for the main
+    /// script. Functions it declares keep the file.
+    pub fn set_file(&mut self, file: Option<Rc<SourceFile>>) {
+        self.file = file;
+    }
+
+    /// Shares the capabilities that decide which modules may be imported.
+    pub fn set_capabilities(&mut self, capabilities: Rc<RefCell<Capabilities>>) {
+        self.loader.set_capabilities(capabilities);
+    }
+
+    /// Redirects the output of `print` statements.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().define(name.to_string(), value);
+    }
+
+    /// Executes a parsed program in the global scope.
+    pub fn run(&mut self, program: ASTNode) -> Result<(), RuntimeError> {
+        let result = prepare_program(&program).and_then(|block| self.run_block(block));
+        self.output.flush().map_err(|error| RuntimeError::Output {
+            message: error.to_string(),
+            span: program.span(),
+        })?;
+        result
+    }
+
+    /// Evaluates a single expression in the global scope.
+    pub fn evaluate(&mut self, expression: ASTNode) -> Result<Value, RuntimeError> {
+        let expression = prepare_expression(&expression)?;
+        let value = self.execute(Task::Evaluate(expression))?;
+        Ok(value.expect("an expression leaves its value"))
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get(name)
+    }
+
+    /// All global variables, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.globals.borrow().variables()
+    }
+
+    /// Runs the statements of a program or module in the global scope.
+    fn run_block(&mut self, block: Block) -> Result<(), RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, self.globals.clone());
+        let result = self.execute(Task::Run(block, 0));
+        self.environment = previous;
+        result.map(|_| ())
+    }
+
+    /// Carries out `task` and all the work it leads to, returning the value
+    /// it leaves if it is an expression. On failure the scope, file and call
+    /// depth are restored to what they were on entry.
+    fn execute(&mut self, task: Task) -> Result<Option<Value>, RuntimeError> {
+        let environment = self.environment.clone();
+        let call_depth = self.call_depth;
+        let mut tasks = vec![task];
+        let mut values = Vec::new();
+
+        while let Some(task) = tasks.pop() {
+            if let Err(mut error) = self.step(task, &mut tasks, &mut values) {
+                // Attribute the error to the file of the code raising it as
+                // it propagates through calls from code in other files.
+                for task in tasks.into_iter().rev() {
+                    if let Task::Return(caller_file, span) = task {
+                        if !same_file(&self.file, &caller_file) {
+                            error = error.in_file(self.file.as_ref(), span);
+                        }
+                        self.file = caller_file;
+                    }
                 }
+                self.environment = environment;
+                self.call_depth = call_depth;
+                return Err(error);
             }
-            _ => panic!("Cannot compare {:?} and {:?}", left, right),
         }
+
+        Ok(values.pop())
     }
 
-    fn evaluate_greater_than(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                if left_value > right_value {
-                    ASTNode::Number(1)
-                } else {
-                    ASTNode::Number(0)
+    fn step(
+        &mut self,
+        task: Task,
+        tasks: &mut Vec<Task>,
+        values: &mut Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        match task {
+            Task::Run(block, position) => {
+                let Some(statement) = block.get(position).cloned() else {
+                    return Ok(());
+                };
+                if position + 1 < block.len() {
+                    tasks.push(Task::Run(block, position + 1));
                 }
+                self.start_statement(statement, tasks, values)?;
+            }
+            Task::Evaluate(expression) => self.start_expression(expression, tasks, values)?,
+            Task::Apply(expression) => self.apply(&expression, tasks, values)?,
+            Task::Complete(statement) => self.complete(statement, tasks, values)?,
+            Task::Branch(statement, branch) => {
+                let Statement::If(branches, otherwise) = &*statement else {
+                    unreachable!("only an `if` has branches");
+                };
+                if operations::is_true(&pop(values)) {
+                    self.enter(branches[branch].1.clone(), self.child_scope(), tasks);
+                } else if let Some((condition, _)) = branches.get(branch + 1) {
+                    let condition = condition.clone();
+                    tasks.push(Task::Branch(statement.clone(), branch + 1));
+                    tasks.push(Task::Evaluate(condition));
+                } else if let Some(otherwise) = otherwise {
+                    self.enter(otherwise.clone(), self.child_scope(), tasks);
+                }
+            }
+            Task::Repeat(statement) => {
+                let Statement::While(condition, _) = &*statement else {
+                    unreachable!("only a `while` loop repeats");
+                };
+                let condition = condition.clone();
+                tasks.push(Task::Pass(statement));
+                tasks.push(Task::Evaluate(condition));
+            }
+            Task::Pass(statement) => {
+                let Statement::While(_, body) = &*statement else {
+                    unreachable!("only a `while` loop repeats");
+                };
+                if operations::is_true(&pop(values)) {
+                    let body = body.clone();
+                    tasks.push(Task::Repeat(statement));
+                    self.enter(body, self.child_scope(), tasks);
+                }
+            }
+            Task::Iterate(statement, mut items) => {
+                let (Statement::For(variable, .., body) | Statement::ForRange(variable, .., body)) =
+                    &*statement
+                else {
+                    unreachable!("only a `for` loop iterates");
+                };
+                if let Some(item) = items.next() {
+                    let scope = self.child_scope();
+                    scope.borrow_mut().define(variable.clone(), item);
+                    let body = body.clone();
+                    tasks.push(Task::Iterate(statement, items));
+                    self.enter(body, scope, tasks);
+                }
+            }
+            Task::Restore(environment) => self.environment = environment,
+            Task::Return(caller_file, _) => {
+                // The function ended without a `return`.
+                self.file = caller_file;
+                self.call_depth -= 1;
+                values.push(Value::Nil);
             }
-            _ => panic!("Cannot compare {:?} and {:?}", left, right),
         }
+
+        Ok(())
     }
 
-    fn evaluate_addition(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                ASTNode::Number(left_value + right_value)
+    /// Starts executing a statement, scheduling the evaluation of its
+    /// expressions before [`Task::Complete`] finishes it.
+    fn start_statement(
+        &mut self,
+        statement: Rc<Statement>,
+        tasks: &mut Vec<Task>,
+        values: &mut Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        let operands = match &*statement {
+            Statement::Let(_, value)
+            | Statement::Assign(_, value, _)
+            | Statement::Print(value, _)
+            | Statement::Return(Some(value))
+            | Statement::Expression(value)
+            | Statement::For(_, value, _, _) => vec![value.clone()],
+            Statement::SetIndex(target, index, value, _) => {
+                vec![target.clone(), index.clone(), value.clone()]
             }
-            (ASTNode::StringLiteral(left_value), ASTNode::StringLiteral(right_value)) => {
-                ASTNode::StringLiteral(format!("{}{}", left_value, right_value))
+            Statement::ForRange(_, start, end, _, _) => vec![start.clone(), end.clone()],
+            Statement::If(branches, _) => {
+                let condition = branches[0].0.clone();
+                tasks.push(Task::Branch(statement, 0));
+                tasks.push(Task::Evaluate(condition));
+                return Ok(());
             }
-            (ASTNode::StringLiteral(left_value), ASTNode::Number(right_value)) => {
-                ASTNode::StringLiteral(format!("{}{}", left_value, right_value))
+            Statement::While(..) => {
+                tasks.push(Task::Repeat(statement));
+                return Ok(());
             }
-            (ASTNode::Number(left_value), ASTNode::StringLiteral(right_value)) => {
-                ASTNode::StringLiteral(format!("{}{}", left_value, right_value))
+            Statement::Function(name, parameters, body) => {
+                let function = Function {
+                    name: name.clone(),
+                    parameters: parameters.clone(),
+                    body: body.clone(),
+                    closure: self.environment.clone(),
+                    file: self.file.clone(),
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(name.clone(), Value::Function(Rc::new(function)));
+                return Ok(());
             }
-            _ => panic!("Cannot add {:?} and {:?}", left, right),
-        }
+            Statement::Return(None) => {
+                self.return_value(Value::Nil, tasks, values);
+                return Ok(());
+            }
+            Statement::Break => {
+                self.leave_loop(false, tasks);
+                return Ok(());
+            }
+            Statement::Continue => {
+                self.leave_loop(true, tasks);
+                return Ok(());
+            }
+            Statement::Import(path, name, span) => {
+                let module = self.import(path, *span)?;
+                self.environment.borrow_mut().define(name.clone(), module);
+                return Ok(());
+            }
+        };
+
+        tasks.push(Task::Complete(statement));
+        tasks.extend(operands.into_iter().rev().map(Task::Evaluate));
+        Ok(())
     }
 
-    fn evaluate_subtraction(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                ASTNode::Number(left_value - right_value)
+    /// Finishes a statement with the values of its expressions.
+    fn complete(
+        &mut self,
+        statement: Rc<Statement>,
+        tasks: &mut Vec<Task>,
+        values: &mut Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        match &*statement {
+            Statement::Let(name, _) => {
+                let value = pop(values);
+                self.environment.borrow_mut().define(name.clone(), value);
+            }
+            Statement::Assign(name, _, span) => {
+                if !self.environment.borrow_mut().assign(name, pop(values)) {
+                    return Err(RuntimeError::UndeclaredAssignment {
+                        name: name.clone(),
+                        span: *span,
+                    });
+                }
+            }
+            Statement::Print(_, span) => {
+                // One write per `print` so line-oriented sinks see whole lines.
+                let line = format!("{}\n", pop(values));
+                self.output
+                    .write_all(line.as_bytes())
+                    .map_err(|error| RuntimeError::Output {
+                        message: error.to_string(),
+                        span: *span,
+                    })?;
+            }
+            Statement::Return(_) => {
+                let value = pop(values);
+                self.return_value(value, tasks, values);
+            }
+            Statement::SetIndex(_, _, _, span) => {
+                let value = pop(values);
+                let index = pop(values);
+                let target = pop(values);
+                operations::set_index(&target, &index, value, *span)?;
+            }
+            Statement::Expression(_) => {
+                pop(values);
+            }
+            Statement::For(_, _, span, _) => {
+                let iterable = pop(values);
+                let items = operations::iteration_items(&iterable, *span)?;
+                tasks.push(Task::Iterate(statement, Items::Values(items.into_iter())));
+            }
+            Statement::ForRange(_, _, _, span, _) => {
+                let end = pop(values);
+                let start = pop(values);
+                let (start, end) = operations::range_bounds(&start, &end, *span)?;
+                tasks.push(Task::Iterate(statement, Items::Range(start..end)));
             }
-            _ => panic!("Cannot subtract {:?} and {:?}", left, right),
+            _ => unreachable!("the statement has no expressions to complete it with"),
         }
+
+        Ok(())
     }
 
-    fn evaluate_multiplication(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                ASTNode::Number(left_value * right_value)
+    /// Starts evaluating an expression. Values and variables are pushed
+    /// right away; other expressions schedule the evaluation of their
+    /// operands before [`Task::Apply`] combines them.
+    fn start_expression(
+        &mut self,
+        expression: Rc<Expression>,
+        tasks: &mut Vec<Task>,
+        values: &mut Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        let operands = match &*expression {
+            Expression::Value(value) => {
+                values.push(value.clone());
+                return Ok(());
             }
-            _ => panic!("Cannot multiply {:?} and {:?}", left, right),
-        }
+            Expression::Variable(name, span) => {
+                let value = self.environment.borrow().get(name);
+                match value {
+                    Some(value) => values.push(value),
+                    None => {
+                        return Err(RuntimeError::UndefinedVariable {
+                            name: name.clone(),
+                            span: *span,
+                        })
+                    }
+                }
+                return Ok(());
+            }
+            Expression::Logical(left, _, _)
+            | Expression::Unary(_, left, _)
+            | Expression::Member(left, _, _) => {
+                vec![left.clone()]
+            }
+            Expression::Binary(left, _, right, _)
+            | Expression::Range(left, right, _)
+            | Expression::Index(left, right, _) => vec![left.clone(), right.clone()],
+            Expression::Call(callee, arguments, _) => {
+                std::iter::once(callee).chain(arguments).cloned().collect()
+            }
+            Expression::List(items) | Expression::Interpolation(items) => items.clone(),
+            Expression::Map(entries, _) => entries
+                .iter()
+                .flat_map(|(key, value)| [key.clone(), value.clone()])
+                .collect(),
+        };
+
+        tasks.push(Task::Apply(expression));
+        tasks.extend(operands.into_iter().rev().map(Task::Evaluate));
+        Ok(())
     }
 
-    fn evaluate_division(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                ASTNode::Number(left_value / right_value)
+    /// Finishes an expression with the values of its operands.
+    fn apply(
+        &mut self,
+        expression: &Expression,
+        tasks: &mut Vec<Task>,
+        values: &mut Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        let value = match expression {
+            Expression::Binary(_, operator, _, span) => {
+                let right = pop(values);
+                let left = pop(values);
+                operations::binary(&left, operator, &right, *span)?
             }
-            _ => panic!("Cannot divide {:?} and {:?}", left, right),
-        }
+            Expression::Logical(_, operator, right) => {
+                let left = values.last().expect("the left operand was evaluated");
+                if operations::is_true(left) != (*operator == Token::OrOr) {
+                    pop(values);
+                    tasks.push(Task::Evaluate(right.clone()));
+                }
+                return Ok(());
+            }
+            Expression::Range(_, _, span) => {
+                let end = pop(values);
+                let start = pop(values);
+                let (start, end) = operations::range_bounds(&start, &end, *span)?;
+                Value::from((start..end).map(Value::Int).collect::<Vec<_>>())
+            }
+            Expression::Unary(operator, _, span) => {
+                operations::unary(operator, &pop(values), *span)?
+            }
+            Expression::Call(_, arguments, span) => {
+                let arguments = values.split_off(values.len() - arguments.len());
+                let callee = pop(values);
+                return self.call(callee, arguments, *span, tasks, values);
+            }
+            Expression::List(items) => Value::from(values.split_off(values.len() - items.len())),
+            Expression::Map(entries, span) => {
+                let mut operands = values
+                    .split_off(values.len() - 2 * entries.len())
+                    .into_iter();
+                let mut pairs = Vec::with_capacity(entries.len());
+                while let (Some(key), Some(value)) = (operands.next(), operands.next()) {
+                    pairs.push((key, value));
+                }
+                operations::map(pairs, *span)?
+            }
+            Expression::Index(_, _, span) => {
+                let index = pop(values);
+                let target = pop(values);
+                operations::index(&target, &index, *span)?
+            }
+            Expression::Member(_, name, span) => operations::member(&pop(values), name, *span)?,
+            Expression::Interpolation(parts) => {
+                operations::interpolate(&values.split_off(values.len() - parts.len()))
+            }
+            Expression::Value(_) | Expression::Variable(..) => {
+                unreachable!("the expression has no operands to apply")
+            }
+        };
+
+        values.push(value);
+        Ok(())
     }
 
-    fn evaluate_modulo(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                ASTNode::Number(left_value % right_value)
+    /// Calls `callee`. Natives run right away; the body of a script function
+    /// is scheduled to run in a new scope, followed by [`Task::Return`].
+    fn call(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        span: Span,
+        tasks: &mut Vec<Task>,
+        values: &mut Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        let function = match &callee {
+            Value::Function(function) => function.clone(),
+            Value::Native(native) => {
+                values.push(operations::call_native(native, &arguments, span)?);
+                return Ok(());
+            }
+            value => {
+                return Err(RuntimeError::NotCallable {
+                    type_name: value.type_name(),
+                    span,
+                })
             }
-            _ => panic!("Cannot modulo {:?} and {:?}", left, right),
+        };
+
+        if function.parameters.len() != arguments.len() {
+            return Err(RuntimeError::ArityMismatch {
+                name: function.name.clone(),
+                expected: function.parameters.len(),
+                found: arguments.len(),
+                span,
+            });
+        }
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow { span });
+        }
+
+        let scope = Environment::with_parent(function.closure.clone());
+        for (parameter, value) in function.parameters.iter().zip(arguments) {
+            scope.borrow_mut().define(parameter.clone(), value);
         }
+
+        self.call_depth += 1;
+        let caller_file = std::mem::replace(&mut self.file, function.file.clone());
+        tasks.push(Task::Return(caller_file, span));
+        self.enter(function.body.clone(), scope, tasks);
+        Ok(())
     }
 
-    fn evaluate_equal(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                if left_value == right_value {
-                    ASTNode::Number(1)
-                } else {
-                    ASTNode::Number(0)
+    /// Schedules `block` to run with `environment` as the innermost scope,
+    /// restoring the current one afterwards.
+    fn enter(
+        &mut self,
+        block: Block,
+        environment: Rc<RefCell<Environment>>,
+        tasks: &mut Vec<Task>,
+    ) {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        tasks.push(Task::Restore(previous));
+        tasks.push(Task::Run(block, 0));
+    }
+
+    /// Skips the rest of the innermost loop's pass. With `continuing` the
+    /// loop goes on with its next pass; otherwise it ends.
+    fn leave_loop(&mut self, continuing: bool, tasks: &mut Vec<Task>) {
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Restore(environment) => self.environment = environment,
+                Task::Repeat(_) | Task::Iterate(..) => {
+                    if continuing {
+                        tasks.push(task);
+                    }
+                    return;
                 }
+                _ => {}
             }
-            _ => panic!("Cannot compare {:?} and {:?}", left, right),
         }
     }
 
-    fn evaluate_not_equal(&mut self, left: ASTNode, right: ASTNode) -> ASTNode {
-        match (left.clone(), right.clone()) {
-            (ASTNode::Number(left_value), ASTNode::Number(right_value)) => {
-                if left_value != right_value {
-                    ASTNode::Number(1)
-                } else {
-                    ASTNode::Number(0)
+    /// Skips the rest of the running function and gives `value` to its
+    /// caller.
+    fn return_value(&mut self, value: Value, tasks: &mut Vec<Task>, values: &mut Vec<Value>) {
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Restore(environment) => self.environment = environment,
+                Task::Return(caller_file, _) => {
+                    self.file = caller_file;
+                    self.call_depth -= 1;
+                    values.push(value);
+                    return;
                 }
+                _ => {}
             }
-            _ => panic!("Cannot compare {:?} and {:?}", left, right),
         }
     }
 
-    fn stringify_value(&mut self, ast: ASTNode) -> String {
-        match ast {
-            ASTNode::Number(value) => value.to_string(),
-            ASTNode::StringLiteral(value) => value,
-            _ => panic!("Unexpected AST node: {:?}", ast),
+    /// Runs a module the first time it is imported, in a global scope of its
+    /// own that only shares the native functions with the importer.
+    fn import(&mut self, path: &str, span: Span) -> Result<Value, RuntimeError> {
+        let module = match self.loader.begin(path, span)? {
+            Import::Loaded(module) => return Ok(module),
+            Import::Pending(module) => module,
+        };
+
+        let globals = Environment::new();
+        for (name, value) in self.globals.borrow().variables() {
+            if let Value::Native(_) = value {
+                globals.borrow_mut().define(name, value);
+            }
         }
+        let previous = std::mem::replace(&mut self.globals, globals);
+        let importer_file = self.file.replace(module.file.clone());
+        let result = prepare_program(&module.program).and_then(|block| self.run_block(block));
+        self.file = importer_file;
+        let globals = std::mem::replace(&mut self.globals, previous);
+
+        self.loader
+            .finish(module, result, |name| globals.borrow().get(name), span)
     }
+
+    fn child_scope(&self) -> Rc<RefCell<Environment>> {
+        Environment::with_parent(self.environment.clone())
+    }
+}
+
+fn pop(values: &mut Vec<Value>) -> Value {
+    values
+        .pop()
+        .expect("every operand is evaluated before it is used")
 }
 
-pub fn interpret(ast: ASTNode) {
-    let mut interpreter = Interpreter::new();
-    interpreter.interpret(ast);
-}
\ No newline at end of file
+/// Prepares a parsed program for running: the statements of a `Program`
+/// node, or a single statement.
+fn prepare_program(program: &ASTNode) -> Result<Block, RuntimeError> {
+    match program {
+        ASTNode::Program(statements, _) => prepare_block(statements),
+        statement => prepare_block(std::slice::from_ref(statement)),
+    }
+}
+
+fn prepare_block(statements: &[ASTNode]) -> Result<Block, RuntimeError> {
+    statements
+        .iter()
+        .map(|statement| prepare_statement(statement).map(Rc::new))
+        .collect()
+}
+
+fn prepare_statement(statement: &ASTNode) -> Result<Statement, RuntimeError> {
+    Ok(match statement {
+        ASTNode::Variable(name, value, _) => {
+            Statement::Let(name.clone(), prepare_expression(value)?)
+        }
+        ASTNode::Assignment(name, value, span) => {
+            Statement::Assign(name.clone(), prepare_expression(value)?, *span)
+        }
+        ASTNode::Print(expression, span) => {
+            Statement::Print(prepare_expression(expression)?, *span)
+        }
+        ASTNode::If(branches, otherwise, _) => Statement::If(
+            branches
+                .iter()
+                .map(|(condition, body)| Ok((prepare_expression(condition)?, prepare_block(body)?)))
+                .collect::<Result<_, RuntimeError>>()?,
+            otherwise.as_deref().map(prepare_block).transpose()?,
+        ),
+        ASTNode::Function(name, parameters, body, _) => {
+            Statement::Function(name.clone(), parameters.clone(), prepare_block(body)?)
+        }
+        ASTNode::Return(value, _) => {
+            Statement::Return(value.as_deref().map(prepare_expression).transpose()?)
+        }
+        ASTNode::IndexAssignment(target, index, value, span) => Statement::SetIndex(
+            prepare_expression(target)?,
+            prepare_expression(index)?,
+            prepare_expression(value)?,
+            *span,
+        ),
+        ASTNode::Expression(expression, _) => {
+            Statement::Expression(prepare_expression(expression)?)
+        }
+        ASTNode::While(condition, body, _) => {
+            Statement::While(prepare_expression(condition)?, prepare_block(body)?)
+        }
+        ASTNode::For(variable, iterable, body, _) => match &**iterable {
+            ASTNode::BinaryOperation(start, Token::DotDot, end, span) => Statement::ForRange(
+                variable.clone(),
+                prepare_expression(start)?,
+                prepare_expression(end)?,
+                *span,
+                prepare_block(body)?,
+            ),
+            iterable => Statement::For(
+                variable.clone(),
+                prepare_expression(iterable)?,
+                iterable.span(),
+                prepare_block(body)?,
+            ),
+        },
+        ASTNode::Break(_) => Statement::Break,
+        ASTNode::Continue(_) => Statement::Continue,
+        ASTNode::Import(path, name, span) => Statement::Import(path.clone(), name.clone(), *span),
+        ASTNode::Export(declaration, _) => prepare_statement(declaration)?,
+        _ => {
+            return Err(RuntimeError::UnexpectedNode {
+                node: "an expression as a statement".to_string(),
+                span: statement.span(),
+            })
+        }
+    })
+}
+
+fn prepare_expressions(expressions: &[ASTNode]) -> Result<Vec<Rc<Expression>>, RuntimeError> {
+    expressions.iter().map(prepare_expression).collect()
+}
+
+fn prepare_expression(expression: &ASTNode) -> Result<Rc<Expression>, RuntimeError> {
+    let prepared = match expression {
+        ASTNode::BinaryOperation(start, Token::DotDot, end, span) => {
+            Expression::Range(prepare_expression(start)?, prepare_expression(end)?, *span)
+        }
+        ASTNode::BinaryOperation(left, operator @ (Token::AndAnd | Token::OrOr), right, _) => {
+            Expression::Logical(
+                prepare_expression(left)?,
+                operator.clone(),
+                prepare_expression(right)?,
+            )
+        }
+        ASTNode::BinaryOperation(left, operator, right, span) => Expression::Binary(
+            prepare_expression(left)?,
+            operator.clone(),
+            prepare_expression(right)?,
+            *span,
+        ),
+        ASTNode::UnaryOperation(operator, operand, span) => {
+            Expression::Unary(operator.clone(), prepare_expression(operand)?, *span)
+        }
+        ASTNode::Identifier(name, span) => Expression::Variable(name.clone(), *span),
+        ASTNode::Call(callee, arguments, span) => Expression::Call(
+            prepare_expression(callee)?,
+            prepare_expressions(arguments)?,
+            *span,
+        ),
+        ASTNode::List(items, _) => Expression::List(prepare_expressions(items)?),
+        ASTNode::Map(entries, span) => Expression::Map(
+            entries
+                .iter()
+                .map(|(key, value)| Ok((prepare_expression(key)?, prepare_expression(value)?)))
+                .collect::<Result<_, RuntimeError>>()?,
+            *span,
+        ),
+        ASTNode::Index(target, index, span) => Expression::Index(
+            prepare_expression(target)?,
+            prepare_expression(index)?,
+            *span,
+        ),
+        ASTNode::Member(target, name, span) => {
+            Expression::Member(prepare_expression(target)?, name.clone(), *span)
+        }
+        ASTNode::Number(value, _) => Expression::Value(Value::Int(*value)),
+        ASTNode::Float(value, _) => Expression::Value(Value::Float(*value)),
+        ASTNode::StringLiteral(value, _) => Expression::Value(Value::from(value.as_str())),
+        ASTNode::Bool(value, _) => Expression::Value(Value::Bool(*value)),
+        ASTNode::Nil(_) => Expression::Value(Value::Nil),
+        ASTNode::InterpolatedString(parts, _) => Expression::Interpolation(
+            parts
+                .iter()
+                .map(|part| match part {
+                    StringPart::Text(text) => {
+                        Ok(Rc::new(Expression::Value(Value::from(text.as_str()))))
+                    }
+                    StringPart::Expression(expression) => prepare_expression(expression),
+                })
+                .collect::<Result<_, RuntimeError>>()?,
+        ),
+        _ => {
+            return Err(RuntimeError::UnexpectedNode {
+                node: "a statement as an expression".to_string(),
+                span: expression.span(),
+            })
+        }
+    };
+
+    Ok(Rc::new(prepared))
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
@@ -0,0 +1,474 @@
+//! The canonical layout of netl source, as written by `netl fmt`.
+//!
+//! The formatter parses a script and prints the syntax tree back with four
+//! spaces of indentation per block, opening braces on the line of the
+//! statement they belong to, one space around binary operators and after
+//! commas, and parentheses only where precedence needs them. Literals are
+//! copied as written, so `0xff`, `1_000` and raw strings keep their form.
+//!
+//! Comments are not part of the syntax tree; each is put back before the
+//! statement it precedes, or after the one it ends the line of. Comments
+//! between the branches of an `if` stay after the `}` they follow. Blank
+//! lines between statements are kept, collapsed to one.
+//!
+//! Before returning, the result is checked to parse to the same tree with the
+//! same comments and to format to itself, so formatting never changes what a
+//! script means and formatting twice changes nothing.
+
+use std::path::Path;
+
+use super::ast::{self, ASTNode, SpannedToken, Token};
+use super::diagnostic::Diagnostic;
+use super::tokenizer::{tokenize, tokenize_with_comments, Comment, CommentKind};
+
+const INDENT: &str = "    ";
+
+/// Formats `source`, failing with the parse error if it does not parse.
+pub fn format(source: &str) -> Result<String, Diagnostic> {
+    let (formatted, program, comments) = format_unchecked(source)?;
+
+    let changed = || {
+        Diagnostic::error("formatting would change the script")
+            .with_note("this is a bug in the formatter; the script was left as it is")
+    };
+    let (reformatted, new_program, new_comments) =
+        format_unchecked(&formatted).map_err(|_| changed())?;
+    if !new_program.same_shape(&program)
+        || texts(&new_comments) != texts(&comments)
+        || reformatted != formatted
+    {
+        return Err(changed());
+    }
+
+    Ok(formatted)
+}
+
+fn format_unchecked(source: &str) -> Result<(String, ASTNode, Vec<Comment>), Diagnostic> {
+    let (tokens, comments) = tokenize_with_comments(source);
+    let program = ast::parse(tokens.clone())?;
+    // Comments inside `${...}` are copied along with their string.
+    let placed = comments
+        .iter()
+        .filter(|comment| {
+            !tokens.iter().any(|token| {
+                matches!(token.token, Token::InterpolatedString(_))
+                    && token.span.start <= comment.span.start
+                    && comment.span.end <= token.span.end
+            })
+        })
+        .cloned()
+        .collect();
+    let formatted = Formatter {
+        source,
+        tokens,
+        comments: placed,
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        last_end: 0,
+        block_start: true,
+    }
+    .program(&program);
+
+    Ok((formatted, program, comments))
+}
+
+fn texts(comments: &[Comment]) -> Vec<&str> {
+    comments
+        .iter()
+        .map(|comment| comment.text.as_str())
+        .collect()
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    tokens: Vec<SpannedToken>,
+    comments: Vec<Comment>,
+    /// The first comment not written yet.
+    next_comment: usize,
+    out: String,
+    indent: usize,
+    /// The source offset just past the last statement or comment written,
+    /// for finding blank lines and comments on the same line.
+    last_end: usize,
+    /// Whether nothing has been written in the current block yet.
+    block_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn program(mut self, program: &ASTNode) -> String {
+        if let ASTNode::Program(statements, _) = program {
+            for statement in statements {
+                self.statement(statement);
+            }
+        }
+        self.comments_before(usize::MAX);
+        self.out
+    }
+
+    fn statement(&mut self, statement: &ASTNode) {
+        let span = statement.span();
+        let compound = match statement {
+            ASTNode::Export(declaration, _) => matches!(**declaration, ASTNode::Function(..)),
+            _ => matches!(
+                statement,
+                ASTNode::If(..) | ASTNode::Function(..) | ASTNode::While(..) | ASTNode::For(..)
+            ),
+        };
+        // Comments inside a one-line statement, such as between the items
+        // of a list spread over several lines, go before it.
+        self.comments_before(if compound { span.start } else { span.end });
+        self.line_start(span.start);
+
+        match statement {
+            ASTNode::Export(declaration, _) => {
+                self.out.push_str("export ");
+                self.declaration(declaration);
+            }
+            statement => self.declaration(statement),
+        }
+        self.last_end = self.last_end.max(span.end);
+        self.trailing_comment();
+        self.out.push('\n');
+    }
+
+    fn declaration(&mut self, statement: &ASTNode) {
+        match statement {
+            ASTNode::Variable(name, value, _) => {
+                let value = self.expression(value);
+                self.out.push_str(&format!("let {} = {};", name, value));
+            }
+            ASTNode::Assignment(name, value, _) => {
+                let value = self.expression(value);
+                self.out.push_str(&format!("{} = {};", name, value));
+            }
+            ASTNode::IndexAssignment(target, index, value, _) => {
+                let target = self.postfix_target(target);
+                let (index, value) = (self.expression(index), self.expression(value));
+                let target = statement_start(format!("{}[{}]", target, index));
+                self.out.push_str(&format!("{} = {};", target, value));
+            }
+            ASTNode::Print(value, _) => {
+                let value = self.expression(value);
+                self.out.push_str(&format!("print {};", value));
+            }
+            ASTNode::Expression(expression, _) => {
+                let expression = statement_start(self.expression(expression));
+                self.out.push_str(&format!("{};", expression));
+            }
+            ASTNode::Return(value, _) => match value {
+                Some(value) => {
+                    let value = self.expression(value);
+                    self.out.push_str(&format!("return {};", value));
+                }
+                None => self.out.push_str("return;"),
+            },
+            ASTNode::Break(_) => self.out.push_str("break;"),
+            ASTNode::Continue(_) => self.out.push_str("continue;"),
+            ASTNode::Import(path, name, span) => {
+                let index = self.token_at(span.start) + 1;
+                let written = self.tokens[index].clone();
+                let default_name = match &written.token {
+                    Token::Identifier(name) => Some(name.clone()),
+                    _ => Path::new(path)
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned()),
+                };
+                self.out.push_str("import ");
+                self.out
+                    .push_str(self.text(written.span.start, written.span.end));
+                if default_name.as_ref() != Some(name) {
+                    self.out.push_str(&format!(" as {}", name));
+                }
+                self.out.push(';');
+            }
+            ASTNode::Function(name, parameters, body, span) => {
+                self.out
+                    .push_str(&format!("fn {}({}) ", name, parameters.join(", ")));
+                self.block(body, span.start);
+            }
+            ASTNode::While(condition, body, _) => {
+                let condition_text = self.expression(condition);
+                self.out.push_str(&format!("while {} ", condition_text));
+                self.block(body, condition.span().end);
+            }
+            ASTNode::For(variable, iterable, body, _) => {
+                let iterable_text = self.expression(iterable);
+                self.out
+                    .push_str(&format!("for {} in {} ", variable, iterable_text));
+                self.block(body, iterable.span().end);
+            }
+            ASTNode::If(branches, otherwise, _) => {
+                let mut end = 0;
+                for (position, (condition, body)) in branches.iter().enumerate() {
+                    if position == 0 {
+                        self.out.push_str("if");
+                    } else {
+                        self.continue_if(end, "elif");
+                    }
+                    let condition_text = self.expression(condition);
+                    self.out.push_str(&format!(" {} ", condition_text));
+                    end = self.block(body, condition.span().end);
+                }
+                if let Some(body) = otherwise {
+                    self.continue_if(end, "else");
+                    self.out.push(' ');
+                    self.block(body, end);
+                }
+            }
+            node => {
+                let expression = self.expression(node);
+                self.out.push_str(&expression);
+            }
+        }
+    }
+
+    /// Writes the block whose `{` is the first one at or after `from`,
+    /// returning the offset just past its `}`.
+    fn block(&mut self, statements: &[ASTNode], from: usize) -> usize {
+        let open = self.token_at(from)
+            + self.tokens[self.token_at(from)..]
+                .iter()
+                .position(|token| token.token == Token::LeftBrace)
+                .expect("the parser found the block");
+        let mut depth = 0;
+        let close = open
+            + self.tokens[open..]
+                .iter()
+                .position(|token| {
+                    match token.token {
+                        Token::LeftBrace => depth += 1,
+                        Token::RightBrace => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .expect("the parser found the block's end");
+        let (open, close) = (self.tokens[open].span, self.tokens[close].span);
+
+        self.out.push('{');
+        self.last_end = open.end;
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|comment| comment.span.start < close.start);
+        if statements.is_empty() && !has_comments {
+            self.out.push('}');
+            self.last_end = close.end;
+            return close.end;
+        }
+
+        self.trailing_comment();
+        self.out.push('\n');
+        self.indent += 1;
+        self.block_start = true;
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.comments_before(close.start);
+        self.indent -= 1;
+        self.out.push_str(&INDENT.repeat(self.indent));
+        self.out.push('}');
+        self.last_end = close.end;
+        close.end
+    }
+
+    /// Writes `keyword`, which continues an `if` after the block ending at
+    /// `end`. Comments between the two stay after the block's `}`; the
+    /// keyword follows on the same line unless one of them ends it.
+    fn continue_if(&mut self, end: usize, keyword: &str) {
+        let keyword_start = self.tokens[self.token_at(end)].span.start;
+        let mut line_open = true;
+        while let Some(comment) = self.comments.get(self.next_comment).cloned() {
+            if comment.span.start >= keyword_start {
+                break;
+            }
+            self.next_comment += 1;
+            if line_open {
+                self.out.push(' ');
+            } else {
+                self.out.push_str(&INDENT.repeat(self.indent));
+            }
+            self.out.push_str(&comment.text);
+            line_open = comment.kind == CommentKind::Block;
+            if !line_open {
+                self.out.push('\n');
+            }
+            self.last_end = comment.span.end;
+        }
+
+        if line_open {
+            self.out.push(' ');
+        } else {
+            self.out.push_str(&INDENT.repeat(self.indent));
+        }
+        self.out.push_str(keyword);
+    }
+
+    /// Writes the comments that start before `offset`, each on a line of
+    /// its own.
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).cloned() {
+            if comment.span.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            self.line_start(comment.span.start);
+            self.out.push_str(&comment.text);
+            self.out.push('\n');
+            self.last_end = self.last_end.max(comment.span.end);
+        }
+    }
+
+    /// Writes the next comment after what was just written if nothing but
+    /// spaces separate them and the comment fits on the line.
+    fn trailing_comment(&mut self) {
+        let Some(comment) = self.comments.get(self.next_comment) else {
+            return;
+        };
+        let between = self.text(self.last_end, comment.span.start.max(self.last_end));
+        if comment.span.start >= self.last_end
+            && between.chars().all(|ch| ch == ' ' || ch == '\t')
+            && !comment.text.contains('\n')
+        {
+            self.next_comment += 1;
+            self.out.push(' ');
+            self.out.push_str(&comment.text);
+            self.last_end = comment.span.end;
+        }
+    }
+
+    /// Starts a line for something found at `offset`, keeping one blank
+    /// line before it if the source had any.
+    fn line_start(&mut self, offset: usize) {
+        let blank_line = offset > self.last_end && {
+            let lines: Vec<&str> = self.text(self.last_end, offset).split('\n').collect();
+            lines.len() > 2
+                && lines[1..lines.len() - 1]
+                    .iter()
+                    .any(|line| line.trim().is_empty())
+        };
+        if blank_line && !self.block_start {
+            self.out.push('\n');
+        }
+        self.block_start = false;
+        self.out.push_str(&INDENT.repeat(self.indent));
+    }
+
+    fn expression(&self, node: &ASTNode) -> String {
+        match node {
+            // Only `i64::MIN` is negative, written as a minus and a literal.
+            ASTNode::Number(value, span) if *value < 0 => {
+                format!("-{}", self.text(span.start + 1, span.end).trim_start())
+            }
+            ASTNode::Number(_, span)
+            | ASTNode::Float(_, span)
+            | ASTNode::StringLiteral(_, span)
+            | ASTNode::InterpolatedString(_, span) => self.text(span.start, span.end).to_string(),
+            ASTNode::Bool(value, _) => value.to_string(),
+            ASTNode::Nil(_) => "nil".to_string(),
+            ASTNode::Identifier(name, _) => name.clone(),
+            ASTNode::List(items, _) => format!("[{}]", self.expressions(items)),
+            ASTNode::Map(entries, _) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!("{}: {}", self.expression(key), self.expression(value))
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            ASTNode::Call(callee, arguments, _) => {
+                format!(
+                    "{}({})",
+                    self.postfix_target(callee),
+                    self.expressions(arguments)
+                )
+            }
+            ASTNode::Index(target, index, _) => {
+                format!(
+                    "{}[{}]",
+                    self.postfix_target(target),
+                    self.expression(index)
+                )
+            }
+            ASTNode::Member(target, name, _) => format!("{}.{}", self.postfix_target(target), name),
+            ASTNode::UnaryOperation(operator, operand, _) => {
+                let operand_text = self.expression(operand);
+                match **operand {
+                    ASTNode::BinaryOperation(..) => {
+                        format!("{}({})", operator_text(operator), operand_text)
+                    }
+                    _ => format!("{}{}", operator_text(operator), operand_text),
+                }
+            }
+            ASTNode::BinaryOperation(left, operator, right, _) => {
+                let precedence = ast::binary_precedence(operator).unwrap_or(0);
+                // Operators are left-associative, so only a right operand of
+                // the same precedence needs parentheses.
+                let left = self.operand(left, precedence);
+                let right = self.operand(right, precedence + 1);
+                match operator {
+                    Token::DotDot => format!("{}..{}", left, right),
+                    operator => format!("{} {} {}", left, operator_text(operator), right),
+                }
+            }
+            _ => unreachable!("statements are formatted by `declaration`"),
+        }
+    }
+
+    fn expressions(&self, nodes: &[ASTNode]) -> String {
+        nodes
+            .iter()
+            .map(|node| self.expression(node))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// An operand of a binary operator, in parentheses if it binds less
+    /// tightly than `minimum_precedence`.
+    fn operand(&self, node: &ASTNode, minimum_precedence: u8) -> String {
+        let text = self.expression(node);
+        match node {
+            ASTNode::BinaryOperation(_, operator, _, _)
+                if ast::binary_precedence(operator).unwrap_or(0) < minimum_precedence =>
+            {
+                format!("({})", text)
+            }
+            _ => text,
+        }
+    }
+
+    /// The target of a call, index or member access, in parentheses if it
+    /// is an operation.
+    fn postfix_target(&self, node: &ASTNode) -> String {
+        let text = self.expression(node);
+        match node {
+            ASTNode::BinaryOperation(..) | ASTNode::UnaryOperation(..) => format!("({})", text),
+            _ => text,
+        }
+    }
+
+    fn text(&self, start: usize, end: usize) -> &'a str {
+        &self.source[start..end]
+    }
+
+    /// The index of the first token starting at or after `offset`.
+    fn token_at(&self, offset: usize) -> usize {
+        self.tokens
+            .partition_point(|token| token.span.start < offset)
+    }
+}
+
+/// An expression statement must start with a name or `(` to be read as
+/// one, so anything else is put in parentheses.
+fn statement_start(expression: String) -> String {
+    match tokenize(&expression).first().map(|token| &token.token) {
+        Some(Token::Identifier(_) | Token::LeftParenthesis) => expression,
+        _ => format!("({})", expression),
+    }
+}
+
+fn operator_text(operator: &Token) -> String {
+    operator.to_string().trim_matches('`').to_string()
+}
@@ -1,3 +1,20 @@
-pub mod tokenizer;
+// The parser returns a whole `Diagnostic` from every level of its recursive
+// descent. One is only built once parsing has failed, so its size costs
+// nothing while parsing succeeds; boxing it would buy nothing but noise.
+#[allow(clippy::result_large_err)]
 pub mod ast;
-pub mod exec;
\ No newline at end of file
+pub mod builtins;
+pub mod bytecode;
+pub mod checker;
+pub mod compiler;
+pub mod diagnostic;
+pub mod environment;
+pub mod exec;
+// Passes the parser's diagnostics on; see `ast`.
+#[allow(clippy::result_large_err)]
+pub mod formatter;
+pub mod operations;
+pub mod span;
+pub mod tokenizer;
+pub mod value;
+pub mod vm;
@@ -0,0 +1,328 @@
+//! Semantics of the language's operators, shared by the tree-walking
+//! interpreter and the bytecode VM so that both backends agree on every
+//! result and every error.
+
+use std::cmp::Ordering;
+
+use super::ast::Token;
+use super::exec::RuntimeError;
+use super::span::Span;
+use super::value::{Map, MapKey, NativeFunction, Value};
+
+/// Applies a binary operator. `span` is the span of the whole operation.
+///
+/// Arithmetic on two ints stays in ints, failing on overflow, and `/`
+/// truncates. If either operand is a float, the other is converted and the
+/// result is a float.
+pub fn binary(
+    left: &Value,
+    operator: &Token,
+    right: &Value,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    let result = match operator {
+        Token::Plus => addition(left, right, span)?,
+        Token::Minus => arithmetic(left, right, span, i64::checked_sub, |a, b| a - b)?,
+        Token::Asterisk => arithmetic(left, right, span, i64::checked_mul, |a, b| a * b)?,
+        Token::Slash => division(left, right, span, i64::checked_div, |a, b| a / b)?,
+        Token::Modulo => division(left, right, span, i64::checked_rem, |a, b| a % b)?,
+        Token::EqualEqual => Some(Value::Bool(left == right)),
+        Token::NotEqual => Some(Value::Bool(left != right)),
+        Token::LessThan => comparison(left, right, Ordering::is_lt),
+        Token::LessThanOrEqual => comparison(left, right, Ordering::is_le),
+        Token::GreaterThan => comparison(left, right, Ordering::is_gt),
+        Token::GreaterThanOrEqual => comparison(left, right, Ordering::is_ge),
+        Token::In => contains(left, right),
+        _ => None,
+    };
+
+    result.ok_or_else(|| RuntimeError::InvalidOperands {
+        operator: operator.clone(),
+        left: left.type_name(),
+        right: right.type_name(),
+        span,
+    })
+}
+
+pub fn unary(operator: &Token, value: &Value, span: Span) -> Result<Value, RuntimeError> {
+    match (operator, value) {
+        (Token::Minus, Value::Int(value)) => Ok(Value::Int(checked(value.checked_neg(), span)?)),
+        (Token::Minus, Value::Float(value)) => Ok(Value::Float(-value)),
+        (Token::Not, value) => Ok(Value::Bool(!is_true(value))),
+        _ => Err(RuntimeError::InvalidOperand {
+            operator: operator.clone(),
+            operand: value.type_name(),
+            span,
+        }),
+    }
+}
+
+/// Whether a value counts as true in a condition: for `if`, `elif`,
+/// `while`, `!`, `&&` and `||`.
+///
+/// `false` and `nil` are false, as are the "empty" values of the other
+/// kinds: `0`, `0.0`, `""`, `[]` and `{}`. Everything else is true,
+/// including every function and module.
+pub fn is_true(value: &Value) -> bool {
+    match value {
+        Value::Bool(value) => *value,
+        Value::Nil => false,
+        Value::Int(value) => *value != 0,
+        Value::Float(value) => *value != 0.0,
+        Value::String(string) => !string.is_empty(),
+        Value::List(items) => !items.borrow().is_empty(),
+        Value::Map(map) => !map.borrow().is_empty(),
+        Value::Function(_) | Value::Closure(_) | Value::Native(_) | Value::Module(_) => true,
+    }
+}
+
+/// Checks the bounds of `start..end`, which must both be ints.
+pub fn range_bounds(start: &Value, end: &Value, span: Span) -> Result<(i64, i64), RuntimeError> {
+    match (start, end) {
+        (Value::Int(start), Value::Int(end)) => Ok((*start, *end)),
+        (start, end) => Err(RuntimeError::InvalidOperands {
+            operator: Token::DotDot,
+            left: start.type_name(),
+            right: end.type_name(),
+            span,
+        }),
+    }
+}
+
+/// The items a `for` loop visits, snapshotted before the first pass:
+/// the elements of a list, the characters of a string or the keys of a map
+/// in insertion order.
+pub fn iteration_items(value: &Value, span: Span) -> Result<Vec<Value>, RuntimeError> {
+    match value {
+        Value::List(items) => Ok(items.borrow().clone()),
+        Value::String(string) => Ok(string
+            .chars()
+            .map(|ch| Value::from(ch.to_string()))
+            .collect()),
+        Value::Map(map) => Ok(map.borrow().iter().map(|(key, _)| key.to_value()).collect()),
+        value => Err(RuntimeError::NotIterable {
+            type_name: value.type_name(),
+            span,
+        }),
+    }
+}
+
+/// Joins the parts of an interpolated string, showing each value as
+/// `print` would.
+pub fn interpolate(parts: &[Value]) -> Value {
+    let text: String = parts.iter().map(|part| part.to_string()).collect();
+    Value::from(text)
+}
+
+/// Reads `target.name`, an export of a module.
+pub fn member(target: &Value, name: &str, span: Span) -> Result<Value, RuntimeError> {
+    match target {
+        Value::Module(module) => {
+            module
+                .exports
+                .get(name)
+                .cloned()
+                .ok_or_else(|| RuntimeError::MissingExport {
+                    module: module.name.clone(),
+                    name: name.to_string(),
+                    span,
+                })
+        }
+        value => Err(RuntimeError::NoMembers {
+            type_name: value.type_name(),
+            span,
+        }),
+    }
+}
+
+/// Builds a map from evaluated `key: value` pairs; later duplicates win.
+pub fn map(entries: Vec<(Value, Value)>, span: Span) -> Result<Value, RuntimeError> {
+    let mut map = Map::new();
+    for (key, value) in entries {
+        map.insert(map_key(&key, span)?, value);
+    }
+    Ok(Value::from(map))
+}
+
+pub fn map_key(key: &Value, span: Span) -> Result<MapKey, RuntimeError> {
+    MapKey::from_value(key).ok_or(RuntimeError::InvalidKey {
+        type_name: key.type_name(),
+        span,
+    })
+}
+
+/// Reads `target[index]`. Negative indices count from the end of a list.
+pub fn index(target: &Value, index: &Value, span: Span) -> Result<Value, RuntimeError> {
+    match target {
+        Value::List(items) => {
+            let items = items.borrow();
+            let position = list_position(items.len(), target, index, span)?;
+            Ok(items[position].clone())
+        }
+        Value::Map(map) => match map.borrow().get(&map_key(index, span)?) {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeError::MissingKey {
+                key: index.repr(),
+                span,
+            }),
+        },
+        target => Err(RuntimeError::NotIndexable {
+            type_name: target.type_name(),
+            span,
+        }),
+    }
+}
+
+/// Performs `target[index] = value`.
+pub fn set_index(
+    target: &Value,
+    index: &Value,
+    value: Value,
+    span: Span,
+) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(items) => {
+            let mut items = items.borrow_mut();
+            let position = list_position(items.len(), target, index, span)?;
+            items[position] = value;
+            Ok(())
+        }
+        Value::Map(map) => {
+            map.borrow_mut().insert(map_key(index, span)?, value);
+            Ok(())
+        }
+        target => Err(RuntimeError::NotIndexable {
+            type_name: target.type_name(),
+            span,
+        }),
+    }
+}
+
+/// Resolves a possibly negative list index against `length`.
+fn list_position(
+    length: usize,
+    list: &Value,
+    index: &Value,
+    span: Span,
+) -> Result<usize, RuntimeError> {
+    let Value::Int(index) = *index else {
+        return Err(RuntimeError::InvalidIndex {
+            container: list.type_name(),
+            index: index.type_name(),
+            span,
+        });
+    };
+
+    let position = if index < 0 {
+        index.checked_add(length as i64)
+    } else {
+        Some(index)
+    };
+    match position {
+        Some(position) if (0..length as i64).contains(&position) => Ok(position as usize),
+        _ => Err(RuntimeError::IndexOutOfBounds {
+            index,
+            length,
+            span,
+        }),
+    }
+}
+
+/// Calls a native function, checking its arity first. `span` is the span of
+/// the call expression.
+pub fn call_native(
+    native: &NativeFunction,
+    arguments: &[Value],
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    if let Some(arity) = native.arity.filter(|&arity| arity != arguments.len()) {
+        return Err(RuntimeError::ArityMismatch {
+            name: native.name.clone(),
+            expected: arity,
+            found: arguments.len(),
+            span,
+        });
+    }
+
+    (native.function)(arguments, span)
+}
+
+/// `needle in haystack`: a key of a map, an element of a list or a
+/// substring of a string.
+pub fn contains(needle: &Value, haystack: &Value) -> Option<Value> {
+    let found = match (needle, haystack) {
+        (needle, Value::Map(map)) => {
+            MapKey::from_value(needle).is_some_and(|key| map.borrow().contains_key(&key))
+        }
+        (needle, Value::List(items)) => items.borrow().contains(needle),
+        (Value::String(needle), Value::String(haystack)) => haystack.contains(&**needle),
+        _ => return None,
+    };
+    Some(Value::Bool(found))
+}
+
+fn comparison(left: &Value, right: &Value, test: fn(Ordering) -> bool) -> Option<Value> {
+    left.partial_cmp(right)
+        .map(|ordering| Value::Bool(test(ordering)))
+}
+
+fn addition(left: &Value, right: &Value, span: Span) -> Result<Option<Value>, RuntimeError> {
+    Ok(match (left, right) {
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            arithmetic(left, right, span, i64::checked_add, |a, b| a + b)?
+        }
+        (Value::String(_), Value::String(_) | Value::Int(_) | Value::Float(_) | Value::Bool(_))
+        | (Value::Int(_) | Value::Float(_) | Value::Bool(_), Value::String(_)) => {
+            Some(Value::from(format!("{}{}", left, right)))
+        }
+        _ => None,
+    })
+}
+
+fn arithmetic(
+    left: &Value,
+    right: &Value,
+    span: Span,
+    int_operation: fn(i64, i64) -> Option<i64>,
+    float_operation: fn(f64, f64) -> f64,
+) -> Result<Option<Value>, RuntimeError> {
+    Ok(match (left, right) {
+        (Value::Int(left_value), Value::Int(right_value)) => Some(Value::Int(checked(
+            int_operation(*left_value, *right_value),
+            span,
+        )?)),
+        _ => match (as_float(left), as_float(right)) {
+            (Some(left_value), Some(right_value)) => {
+                Some(Value::Float(float_operation(left_value, right_value)))
+            }
+            _ => None,
+        },
+    })
+}
+
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(value) => Some(*value as f64),
+        Value::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Division and remainder, which additionally reject a zero divisor, int
+/// or float.
+fn division(
+    left: &Value,
+    right: &Value,
+    span: Span,
+    int_operation: fn(i64, i64) -> Option<i64>,
+    float_operation: fn(f64, f64) -> f64,
+) -> Result<Option<Value>, RuntimeError> {
+    if as_float(left).is_some() && as_float(right) == Some(0.0) {
+        return Err(RuntimeError::DivisionByZero { span });
+    }
+    arithmetic(left, right, span, int_operation, float_operation)
+}
+
+fn checked(value: Option<i64>, span: Span) -> Result<i64, RuntimeError> {
+    value.ok_or(RuntimeError::IntegerOverflow { span })
+}
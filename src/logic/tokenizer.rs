@@ -1,47 +1,400 @@
-use super::ast::Token;
+use std::iter::Peekable;
+use std::str::CharIndices;
 
-pub fn tokenize(code: &str) -> Vec<Token> {
+use super::ast::{SpannedToken, StringSegment, Token, MAX_NESTING};
+use super::diagnostic::Diagnostic;
+use super::span::Span;
+
+struct Cursor<'a> {
+    code: &'a str,
+    iter: Peekable<CharIndices<'a>>,
+    line: usize,
+    column: usize,
+    comments: Vec<Comment>,
+    /// How many interpolations enclose the cursor.
+    interpolations: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(code: &'a str) -> Cursor<'a> {
+        Cursor {
+            code,
+            iter: code.char_indices().peekable(),
+            line: 1,
+            column: 1,
+            comments: Vec::new(),
+            interpolations: 0,
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let (_, ch) = self.iter.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.iter.peek().map(|(_, ch)| ch)
+    }
+
+    /// Byte offset of the next character.
+    fn offset(&mut self) -> usize {
+        self.iter
+            .peek()
+            .map_or(self.code.len(), |&(offset, _)| offset)
+    }
+
+    /// Span from a previously recorded start position up to the cursor.
+    fn span_from(&mut self, start: (usize, usize, usize)) -> Span {
+        let (offset, line, column) = start;
+        Span::new(offset, self.offset(), line, column)
+    }
+
+    fn position(&mut self) -> (usize, usize, usize) {
+        (self.offset(), self.line, self.column)
+    }
+
+    /// The character after the next one.
+    fn peek_second(&self) -> Option<char> {
+        self.iter.clone().nth(1).map(|(_, ch)| ch)
+    }
+
+    /// Whether the next `count` characters are all `ch`.
+    fn peek_repeated(&self, ch: char, count: usize) -> bool {
+        self.iter
+            .clone()
+            .take(count)
+            .filter(|&(_, next)| next == ch)
+            .count()
+            == count
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentKind {
+    /// `// ...` up to the end of the line.
+    Line,
+    /// `/* ... */`, which may nest.
+    Block,
+    /// `/// ...`, documenting the item that follows it.
+    Doc,
+}
+
+/// A comment skipped by the tokenizer. `text` is the comment's source,
+/// including its delimiters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub text: String,
+    pub span: Span,
+}
+
+/// A malformed token. Tokenizing never fails; malformed input becomes a
+/// `Token::Invalid` which the parser reports when it is handed the tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    /// A well-formed number literal whose value does not fit its type.
+    NumberOutOfRange { literal: String, float: bool },
+    /// A number literal that cannot be read, such as `0b102` or `1e`.
+    InvalidNumber { literal: String, reason: String },
+    /// A string, raw string or interpolation that is still open at the end
+    /// of the source.
+    UnterminatedString,
+    /// A `/*` comment without its matching `*/`.
+    UnterminatedComment,
+    /// Interpolations nested more than [`MAX_NESTING`] levels deep.
+    TooDeeplyNested,
+    /// A backslash escape that is not recognised, such as `\q`. The span
+    /// covers just the escape rather than the whole string.
+    InvalidEscape {
+        sequence: String,
+        reason: String,
+        span: Span,
+    },
+}
+
+impl LexError {
+    /// Whether this is an int literal of 2^63, which is out of range on its
+    /// own but is `i64::MIN` when negated.
+    pub(crate) fn is_negatable(&self) -> bool {
+        let LexError::NumberOutOfRange {
+            literal,
+            float: false,
+        } = self
+        else {
+            return false;
+        };
+        let digits = literal.replace('_', "");
+        let (body, radix) = match digits.get(..2) {
+            Some("0x" | "0X") => (&digits[2..], 16),
+            Some("0b" | "0B") => (&digits[2..], 2),
+            Some("0o" | "0O") => (&digits[2..], 8),
+            _ => (&digits[..], 10),
+        };
+        u64::from_str_radix(body, radix) == Ok(i64::MIN.unsigned_abs())
+    }
+
+    pub fn to_diagnostic(&self, span: Span) -> Diagnostic {
+        match self {
+            LexError::NumberOutOfRange { literal, float } => {
+                let diagnostic =
+                    Diagnostic::error(format!("number literal `{}` is out of range", literal))
+                        .with_code("E0001");
+                if *float {
+                    diagnostic.with_label(span, "too large for a float")
+                } else {
+                    diagnostic
+                        .with_label(span, "does not fit in an int")
+                        .with_note(format!("ints are 64-bit, from {} to {}", i64::MIN, i64::MAX))
+                }
+            }
+            LexError::InvalidNumber { literal, reason } => {
+                Diagnostic::error(format!("invalid number literal `{}`", literal))
+                    .with_code("E0002")
+                    .with_label(span, reason.clone())
+            }
+            LexError::UnterminatedString => {
+                let quote = Span::new(span.start, span.start + 1, span.line, span.column);
+                Diagnostic::error("unterminated string literal")
+                    .with_code("E0003")
+                    .with_label(quote, "string starts here")
+                    .with_help("add a closing `\"`")
+            }
+            LexError::UnterminatedComment => {
+                let opening = Span::new(span.start, span.start + 2, span.line, span.column);
+                Diagnostic::error("unterminated block comment")
+                    .with_code("E0005")
+                    .with_label(opening, "comment starts here")
+                    .with_note("block comments nest, so every `/*` needs its own `*/`")
+            }
+            LexError::TooDeeplyNested => Diagnostic::error("string interpolations are nested too deeply")
+                .with_code("E0006")
+                .with_label(span, format!("more than {} levels deep", MAX_NESTING))
+                .with_help("move part of it into a variable"),
+            LexError::InvalidEscape {
+                sequence,
+                reason,
+                span,
+            } => Diagnostic::error(format!("invalid escape sequence `{}`", sequence))
+                .with_code("E0004")
+                .with_label(*span, reason.clone())
+                .with_note(
+                    "valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"`, `\\$` and `\\u{...}`",
+                ),
+        }
+    }
+}
+
+/// Reads a number literal: decimal, `0x` hex, `0b` binary or `0o` octal
+/// ints, or decimal floats with a fraction and/or exponent. Underscores may
+/// separate digits anywhere after the first one.
+fn number_literal(literal: &str) -> Result<Token, LexError> {
+    let digits = literal.replace('_', "");
+    let invalid = |reason: String| LexError::InvalidNumber {
+        literal: literal.to_string(),
+        reason,
+    };
+    let out_of_range = |float| LexError::NumberOutOfRange {
+        literal: literal.to_string(),
+        float,
+    };
+
+    let radix = match digits.get(..2) {
+        Some("0x" | "0X") => Some((16, "hexadecimal")),
+        Some("0b" | "0B") => Some((2, "binary")),
+        Some("0o" | "0O") => Some((8, "octal")),
+        _ => None,
+    };
+    if let Some((radix, name)) = radix {
+        let body = &digits[2..];
+        if body.is_empty() {
+            return Err(invalid(format!("{} literal has no digits", name)));
+        }
+        if let Some(digit) = body.chars().find(|ch| !ch.is_digit(radix)) {
+            return Err(invalid(format!(
+                "invalid digit `{}` in {} literal",
+                digit, name
+            )));
+        }
+        return i64::from_str_radix(body, radix)
+            .map(Token::Number)
+            .map_err(|_| out_of_range(false));
+    }
+
+    if let Some(ch) = digits
+        .chars()
+        .find(|ch| !ch.is_ascii_digit() && !matches!(ch, '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return Err(invalid(format!("invalid digit `{}` in number literal", ch)));
+    }
+
+    if digits.contains(['.', 'e', 'E']) {
+        if !digits.ends_with(|ch: char| ch.is_ascii_digit()) {
+            return Err(invalid("expected digits in the exponent".to_string()));
+        }
+        return match digits.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(Token::Float(value)),
+            Ok(_) => Err(out_of_range(true)),
+            Err(_) => Err(invalid("malformed float literal".to_string())),
+        };
+    }
+
+    digits
+        .parse()
+        .map(Token::Number)
+        .map_err(|_| out_of_range(false))
+}
+
+pub fn tokenize(code: &str) -> Vec<SpannedToken> {
+    tokenize_with_comments(code).0
+}
+
+/// Tokenizes like `tokenize`, also returning the skipped comments in source
+/// order for tools that need them, such as the formatter.
+pub fn tokenize_with_comments(code: &str) -> (Vec<SpannedToken>, Vec<Comment>) {
+    let mut iter = Cursor::new(code);
+    let (tokens, _) = scan(&mut iter, false);
+    (tokens, iter.comments)
+}
+
+/// The text of the `///` comments on the lines directly above `item`, with
+/// the `///` and one following space removed from each line.
+pub fn doc_comment(comments: &[Comment], item: Span) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut line = item.line;
+    for comment in comments
+        .iter()
+        .rev()
+        .skip_while(|comment| comment.span.start >= item.start)
+    {
+        if comment.kind != CommentKind::Doc || comment.span.line + 1 != line {
+            break;
+        }
+        let text = &comment.text[3..];
+        lines.push(text.strip_prefix(' ').unwrap_or(text));
+        line = comment.span.line;
+    }
+
+    if lines.is_empty() {
+        return None;
+    }
+    lines.reverse();
+    Some(lines.join("\n"))
+}
+
+/// Tokenizes from the cursor to the end of the source or, when `nested` is
+/// set, up to and including the `}` that closes an interpolation. The
+/// returned tokens end with `Token::EndOfFile`; the flag tells whether the
+/// closing brace was found.
+fn scan(iter: &mut Cursor, nested: bool) -> (Vec<SpannedToken>, bool) {
     let mut tokens = Vec::new();
-    let mut iter = code.chars().peekable();
+    let mut depth = 0;
+
+    loop {
+        let start = iter.position();
+        let Some(ch) = iter.next() else {
+            break;
+        };
 
-    while let Some(ch) = iter.next() {
-        match ch {
+        let token = match ch {
             ' ' | '\t' | '\n' | '\r' => continue,
-            '=' => tokens.push(Token::Equal),
+            '=' => {
+                if let Some(&'=') = iter.peek() {
+                    iter.next();
+                    Token::EqualEqual
+                } else {
+                    Token::Equal
+                }
+            }
             '!' => {
                 if let Some(&'=') = iter.peek() {
                     iter.next();
-                    tokens.push(Token::NotEqual);
+                    Token::NotEqual
                 } else {
-                    tokens.push(Token::Unknown);
-                }
-            }
-            '+' => tokens.push(Token::Plus),
-            '-' => tokens.push(Token::Minus),
-            '*' => tokens.push(Token::Asterisk),
-            '/' => tokens.push(Token::Slash),
-            '%' => tokens.push(Token::Modulo),
-            '(' => tokens.push(Token::LeftParenthesis),
-            ')' => tokens.push(Token::RightParenthesis),
-            '{' => tokens.push(Token::LeftBrace),
-            '}' => tokens.push(Token::RightBrace),
-            '[' => tokens.push(Token::LeftBracket),
-            ']' => tokens.push(Token::RightBracket),
-            '<' => tokens.push(Token::LessThan),
-            '>' => tokens.push(Token::GreaterThan),
-            ';' => tokens.push(Token::SemiColon),
-            ',' => tokens.push(Token::Comma),
-            '"' => {
-                let mut string_literal = String::new();
-                while let Some(ch) = iter.next() {
-                    if ch == '"' {
-                        break;
-                    } else {
-                        string_literal.push(ch);
-                    }
+                    Token::Not
+                }
+            }
+            '&' if iter.peek() == Some(&'&') => {
+                iter.next();
+                Token::AndAnd
+            }
+            '|' if iter.peek() == Some(&'|') => {
+                iter.next();
+                Token::OrOr
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Asterisk,
+            '/' => match iter.peek() {
+                Some('/') => {
+                    line_comment(iter, start);
+                    continue;
                 }
-                tokens.push(Token::StringLiteral(string_literal));
+                Some('*') => match block_comment(iter, start) {
+                    Ok(()) => continue,
+                    Err(error) => Token::Invalid(Box::new(error)),
+                },
+                _ => Token::Slash,
+            },
+            '%' => Token::Modulo,
+            '(' => Token::LeftParenthesis,
+            ')' => Token::RightParenthesis,
+            '{' => {
+                depth += 1;
+                Token::LeftBrace
+            }
+            '}' if nested && depth == 0 => {
+                tokens.push(SpannedToken {
+                    token: Token::RightBrace,
+                    span: iter.span_from(start),
+                });
+                let end = iter.position();
+                tokens.push(SpannedToken {
+                    token: Token::EndOfFile,
+                    span: iter.span_from(end),
+                });
+                return (tokens, true);
             }
+            '}' => {
+                depth -= 1;
+                Token::RightBrace
+            }
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            '<' => {
+                if let Some(&'=') = iter.peek() {
+                    iter.next();
+                    Token::LessThanOrEqual
+                } else {
+                    Token::LessThan
+                }
+            }
+            '>' => {
+                if let Some(&'=') = iter.peek() {
+                    iter.next();
+                    Token::GreaterThanOrEqual
+                } else {
+                    Token::GreaterThan
+                }
+            }
+            ';' => Token::SemiColon,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '.' => {
+                if let Some(&'.') = iter.peek() {
+                    iter.next();
+                    Token::DotDot
+                } else {
+                    Token::Dot
+                }
+            }
+            '"' => string_literal(iter),
+            'r' if matches!(iter.peek(), Some('"' | '#')) => raw_string(iter),
             c if c.is_alphabetic() => {
                 let mut identifier = String::new();
                 identifier.push(c);
@@ -56,34 +409,581 @@ pub fn tokenize(code: &str) -> Vec<Token> {
                 }
 
                 match identifier.as_str() {
-                    "let" => tokens.push(Token::Let),
-                    "print" => tokens.push(Token::Print),
-                    "fn" => tokens.push(Token::Fn),
-                    "if" => tokens.push(Token::If),
-                    "else" => tokens.push(Token::Else),
-                    "elif" => tokens.push(Token::IfElse),
-                    _ => tokens.push(Token::Identifier(identifier)),
+                    "let" => Token::Let,
+                    "print" => Token::Print,
+                    "fn" => Token::Fn,
+                    "return" => Token::Return,
+                    "while" => Token::While,
+                    "for" => Token::For,
+                    "in" => Token::In,
+                    "break" => Token::Break,
+                    "continue" => Token::Continue,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "elif" => Token::IfElse,
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    "nil" => Token::Nil,
+                    "import" => Token::Import,
+                    "export" => Token::Export,
+                    _ => Token::Identifier(identifier),
                 }
             }
-            c if c.is_digit(10) => {
-                let mut number = String::new();
-                number.push(c);
+            c if c.is_ascii_digit() => {
+                let mut literal = String::new();
+                literal.push(c);
+                let radix =
+                    matches!(iter.peek(), Some('x' | 'X' | 'b' | 'B' | 'o' | 'O')) && c == '0';
 
                 while let Some(&next_ch) = iter.peek() {
-                    if next_ch.is_digit(10) {
-                        number.push(next_ch);
-                        iter.next();
-                    } else {
+                    let previous = literal.chars().last();
+                    let continues = next_ch.is_alphanumeric()
+                        || next_ch == '_'
+                        // A fraction needs a digit after the dot, so `0..10`
+                        // stays a range.
+                        || (next_ch == '.'
+                            && !radix
+                            && !literal.contains(['.', 'e', 'E'])
+                            && iter.peek_second().is_some_and(|ch| ch.is_ascii_digit()))
+                        || (matches!(next_ch, '+' | '-')
+                            && !radix
+                            && matches!(previous, Some('e' | 'E')));
+                    if !continues {
                         break;
                     }
+                    literal.push(next_ch);
+                    iter.next();
                 }
 
-                tokens.push(Token::Number(number.parse().unwrap()));
+                number_literal(&literal).unwrap_or_else(|error| Token::Invalid(Box::new(error)))
             }
-            _ => tokens.push(Token::Unknown),
+            _ => Token::Unknown,
+        };
+
+        tokens.push(SpannedToken {
+            token,
+            span: iter.span_from(start),
+        });
+    }
+
+    let end = iter.position();
+    tokens.push(SpannedToken {
+        token: Token::EndOfFile,
+        span: iter.span_from(end),
+    });
+    (tokens, false)
+}
+
+/// Skips a `//` or `///` comment after its first slash, up to the end of
+/// the line.
+fn line_comment(iter: &mut Cursor, start: (usize, usize, usize)) {
+    while iter.peek().is_some_and(|&ch| ch != '\n') {
+        iter.next();
+    }
+    let span = iter.span_from(start);
+    let text = iter.code[span.start..span.end].trim_end().to_string();
+    // `////...` is an ordinary comment, like a line of slashes.
+    let kind = if text.starts_with("///") && !text.starts_with("////") {
+        CommentKind::Doc
+    } else {
+        CommentKind::Line
+    };
+    iter.comments.push(Comment { kind, text, span });
+}
+
+/// Skips a `/* */` comment after its first slash. Nested `/* */` pairs must
+/// balance.
+fn block_comment(iter: &mut Cursor, start: (usize, usize, usize)) -> Result<(), LexError> {
+    iter.next();
+    let mut depth = 1;
+    while depth > 0 {
+        match iter.next() {
+            None => return Err(LexError::UnterminatedComment),
+            Some('/') if iter.peek() == Some(&'*') => {
+                iter.next();
+                depth += 1;
+            }
+            Some('*') if iter.peek() == Some(&'/') => {
+                iter.next();
+                depth -= 1;
+            }
+            Some(_) => {}
+        }
+    }
+
+    let span = iter.span_from(start);
+    iter.comments.push(Comment {
+        kind: CommentKind::Block,
+        text: iter.code[span.start..span.end].to_string(),
+        span,
+    });
+    Ok(())
+}
+
+/// Reads a string literal after its opening quote, handling escapes and
+/// `${...}` interpolations. An invalid escape is reported once the whole
+/// string has been read, so the rest of the source still lines up.
+fn string_literal(iter: &mut Cursor) -> Token {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut error = None;
+
+    loop {
+        let start = iter.position();
+        match iter.next() {
+            None => return Token::Invalid(Box::new(LexError::UnterminatedString)),
+            Some('"') => break,
+            Some('\\') => match escape(iter, start) {
+                Ok(ch) => text.push(ch),
+                Err(LexError::UnterminatedString) => {
+                    return Token::Invalid(Box::new(LexError::UnterminatedString))
+                }
+                Err(invalid) => {
+                    error.get_or_insert(invalid);
+                }
+            },
+            Some('$') if iter.peek() == Some(&'{') => {
+                iter.next();
+                if iter.interpolations >= MAX_NESTING {
+                    return Token::Invalid(Box::new(LexError::TooDeeplyNested));
+                }
+                if !text.is_empty() {
+                    segments.push(StringSegment::Text(std::mem::take(&mut text)));
+                }
+                iter.interpolations += 1;
+                let (tokens, closed) = scan(iter, true);
+                iter.interpolations -= 1;
+                if !closed {
+                    return Token::Invalid(Box::new(LexError::UnterminatedString));
+                }
+                segments.push(StringSegment::Code(tokens));
+            }
+            Some(ch) => text.push(ch),
+        }
+    }
+
+    if let Some(error) = error {
+        return Token::Invalid(Box::new(error));
+    }
+    if segments.is_empty() {
+        return Token::StringLiteral(text);
+    }
+    if !text.is_empty() {
+        segments.push(StringSegment::Text(text));
+    }
+    Token::InterpolatedString(segments)
+}
+
+/// Reads the escape after a backslash that started at `start`.
+fn escape(iter: &mut Cursor, start: (usize, usize, usize)) -> Result<char, LexError> {
+    let ch = match iter.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('0') => '\0',
+        Some('\\') => '\\',
+        Some('"') => '"',
+        Some('$') => '$',
+        Some('u') => return unicode_escape(iter, start),
+        Some(other) => {
+            return Err(LexError::InvalidEscape {
+                sequence: format!("\\{}", other),
+                reason: "unknown escape sequence".to_string(),
+                span: iter.span_from(start),
+            })
+        }
+        None => return Err(LexError::UnterminatedString),
+    };
+    Ok(ch)
+}
+
+/// Reads the `{...}` of a `\u{...}` escape: one to six hex digits naming a
+/// Unicode scalar value.
+fn unicode_escape(iter: &mut Cursor, start: (usize, usize, usize)) -> Result<char, LexError> {
+    let mut sequence = String::from("\\u");
+    let invalid = |iter: &mut Cursor, sequence: &str, reason: &str| LexError::InvalidEscape {
+        sequence: sequence.to_string(),
+        reason: reason.to_string(),
+        span: iter.span_from(start),
+    };
+
+    if iter.peek() != Some(&'{') {
+        return Err(invalid(iter, &sequence, "expected `{` after `\\u`"));
+    }
+    iter.next();
+    sequence.push('{');
+
+    let mut digits = String::new();
+    while let Some(&ch) = iter.peek() {
+        if !ch.is_ascii_hexdigit() {
+            break;
+        }
+        digits.push(ch);
+        iter.next();
+    }
+    sequence.push_str(&digits);
+
+    if iter.peek() != Some(&'}') {
+        return Err(invalid(
+            iter,
+            &sequence,
+            "expected hex digits and a closing `}`",
+        ));
+    }
+    iter.next();
+    sequence.push('}');
+
+    if digits.is_empty() || digits.len() > 6 {
+        return Err(invalid(iter, &sequence, "expected one to six hex digits"));
+    }
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| invalid(iter, &sequence, "not a Unicode scalar value"))
+}
+
+/// Reads a raw string after its `r`: `r"..."`, or `r#"..."#` with any number
+/// of `#`s so the text can contain quotes. Raw strings have no escapes or
+/// interpolation.
+fn raw_string(iter: &mut Cursor) -> Token {
+    let mut hashes = 0;
+    while iter.peek() == Some(&'#') {
+        iter.next();
+        hashes += 1;
+    }
+    if iter.peek() != Some(&'"') {
+        return Token::Unknown;
+    }
+    iter.next();
+
+    let mut text = String::new();
+    loop {
+        match iter.next() {
+            None => return Token::Invalid(Box::new(LexError::UnterminatedString)),
+            Some('"') if iter.peek_repeated('#', hashes) => {
+                for _ in 0..hashes {
+                    iter.next();
+                }
+                return Token::StringLiteral(text);
+            }
+            Some(ch) => text.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens of `code`, without the final `EndOfFile`.
+    fn tokens(code: &str) -> Vec<Token> {
+        let mut tokens: Vec<_> = tokenize(code)
+            .into_iter()
+            .map(|token| token.token)
+            .collect();
+        assert_eq!(tokens.pop(), Some(Token::EndOfFile));
+        tokens
+    }
+
+    fn invalid_number(literal: &str, reason: &str) -> Token {
+        Token::Invalid(Box::new(LexError::InvalidNumber {
+            literal: literal.to_string(),
+            reason: reason.to_string(),
+        }))
+    }
+
+    fn out_of_range(literal: &str, float: bool) -> Token {
+        Token::Invalid(Box::new(LexError::NumberOutOfRange {
+            literal: literal.to_string(),
+            float,
+        }))
+    }
+
+    #[test]
+    fn int_literals_in_every_radix() {
+        assert_eq!(
+            tokens("0 42 0x1F 0XfF 0b101 0B1 0o17 0O7"),
+            [0, 42, 31, 255, 5, 1, 15, 7].map(Token::Number)
+        );
+        assert_eq!(tokens("9223372036854775807"), [Token::Number(i64::MAX)]);
+        assert_eq!(tokens("0x7fffffffffffffff"), [Token::Number(i64::MAX)]);
+    }
+
+    #[test]
+    fn underscores_separate_digits() {
+        assert_eq!(
+            tokens("1_000_000 0xFF_FF 0b1010_1010 1_0.5_0 1e1_0"),
+            [
+                Token::Number(1_000_000),
+                Token::Number(0xFFFF),
+                Token::Number(0b1010_1010),
+                Token::Float(10.5),
+                Token::Float(1e10),
+            ]
+        );
+        assert_eq!(tokens("0x_1 1__0 1_"), [1, 10, 1].map(Token::Number));
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(
+            tokens("1.5 0.25 1e3 2E-2 1.5e+2 3."),
+            [
+                Token::Float(1.5),
+                Token::Float(0.25),
+                Token::Float(1e3),
+                Token::Float(2e-2),
+                Token::Float(150.0),
+                Token::Number(3),
+                Token::Dot,
+            ]
+        );
+        assert_eq!(
+            tokens("0..10"),
+            [Token::Number(0), Token::DotDot, Token::Number(10)]
+        );
+        assert_eq!(
+            tokens("1.5.2"),
+            [Token::Float(1.5), Token::Dot, Token::Number(2)]
+        );
+        assert_eq!(
+            tokens("1-2"),
+            [Token::Number(1), Token::Minus, Token::Number(2)]
+        );
+    }
+
+    #[test]
+    fn malformed_number_literals() {
+        assert_eq!(
+            tokens("0x"),
+            [invalid_number("0x", "hexadecimal literal has no digits")]
+        );
+        assert_eq!(
+            tokens("0b102"),
+            [invalid_number(
+                "0b102",
+                "invalid digit `2` in binary literal"
+            )]
+        );
+        assert_eq!(
+            tokens("0o8"),
+            [invalid_number("0o8", "invalid digit `8` in octal literal")]
+        );
+        assert_eq!(
+            tokens("12ab"),
+            [invalid_number(
+                "12ab",
+                "invalid digit `a` in number literal"
+            )]
+        );
+        assert_eq!(
+            tokens("1e"),
+            [invalid_number("1e", "expected digits in the exponent")]
+        );
+        assert_eq!(
+            tokens("1e+"),
+            [invalid_number("1e+", "expected digits in the exponent")]
+        );
+    }
+
+    #[test]
+    fn number_literals_out_of_range() {
+        assert_eq!(
+            tokens("9223372036854775808"),
+            [out_of_range("9223372036854775808", false)]
+        );
+        assert_eq!(
+            tokens("0x8000000000000000"),
+            [out_of_range("0x8000000000000000", false)]
+        );
+        assert_eq!(tokens("1e309"), [out_of_range("1e309", true)]);
+    }
+
+    fn string(text: &str) -> Token {
+        Token::StringLiteral(text.to_string())
+    }
+
+    fn invalid_escape(sequence: &str, reason: &str, span: Span) -> Token {
+        Token::Invalid(Box::new(LexError::InvalidEscape {
+            sequence: sequence.to_string(),
+            reason: reason.to_string(),
+            span,
+        }))
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            tokens(r#""a\nb\tc\rd\0e\\f\"g\$h""#),
+            [string("a\nb\tc\rd\0e\\f\"g$h")]
+        );
+        assert_eq!(tokens(r#""\${x}""#), [string("${x}")]);
+        assert_eq!(
+            tokens(r#""\u{41}\u{e9}\u{1F600}\u{0}""#),
+            [string("Aé😀\0")]
+        );
+    }
+
+    #[test]
+    fn invalid_escapes_cover_just_the_escape() {
+        assert_eq!(
+            tokens(r#""ab\qc" 1"#),
+            [
+                invalid_escape("\\q", "unknown escape sequence", Span::new(3, 5, 1, 4)),
+                Token::Number(1)
+            ]
+        );
+        assert_eq!(
+            tokens(r#""\u41""#),
+            [invalid_escape(
+                "\\u",
+                "expected `{` after `\\u`",
+                Span::new(1, 3, 1, 2)
+            )]
+        );
+        assert_eq!(
+            tokens(r#""\u{12x}""#),
+            [invalid_escape(
+                "\\u{12",
+                "expected hex digits and a closing `}`",
+                Span::new(1, 6, 1, 2)
+            )]
+        );
+        assert_eq!(
+            tokens(r#""\u{}" "\u{1234567}""#),
+            [
+                invalid_escape(
+                    "\\u{}",
+                    "expected one to six hex digits",
+                    Span::new(1, 5, 1, 2)
+                ),
+                invalid_escape(
+                    "\\u{1234567}",
+                    "expected one to six hex digits",
+                    Span::new(8, 19, 1, 9)
+                ),
+            ]
+        );
+        assert_eq!(
+            tokens(r#""\u{D800}""#),
+            [invalid_escape(
+                "\\u{D800}",
+                "not a Unicode scalar value",
+                Span::new(1, 9, 1, 2)
+            )]
+        );
+        // Only the first invalid escape is reported.
+        assert_eq!(
+            tokens(r#""\q\w""#),
+            [invalid_escape(
+                "\\q",
+                "unknown escape sequence",
+                Span::new(1, 3, 1, 2)
+            )]
+        );
+    }
+
+    #[test]
+    fn raw_strings_have_no_escapes_or_interpolation() {
+        assert_eq!(tokens(r#"r"a\nb${c}""#), [string("a\\nb${c}")]);
+        assert_eq!(
+            tokens(r###"r#"say "hi""# r##"a "# b"##"###),
+            [string("say \"hi\""), string("a \"# b")]
+        );
+        assert_eq!(tokens("r\"two\nlines\""), [string("two\nlines")]);
+        assert_eq!(tokens("r"), [Token::Identifier("r".to_string())]);
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        for code in [
+            "\"abc",
+            "\"abc\\",
+            "\"a${b\"",
+            "r\"abc",
+            "r#\"abc\"",
+            "\"\\u{41",
+        ] {
+            assert_eq!(
+                tokens(code),
+                [Token::Invalid(Box::new(LexError::UnterminatedString))],
+                "{}",
+                code
+            );
         }
     }
 
-    tokens.push(Token::EndOfFile);
-    tokens
-}
\ No newline at end of file
+    /// The kind and text of each comment in `code`.
+    fn comments(code: &str) -> Vec<(CommentKind, String)> {
+        let (_, comments) = tokenize_with_comments(code);
+        comments
+            .into_iter()
+            .map(|comment| (comment.kind, comment.text))
+            .collect()
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        assert_eq!(
+            tokens("1 /* a /* b */ c */ 2 /**/ 3 /* / * */ 4"),
+            [1, 2, 3, 4].map(Token::Number)
+        );
+        assert_eq!(
+            comments("/* a /* b */ c */ x"),
+            [(CommentKind::Block, "/* a /* b */ c */".to_string())]
+        );
+        let unterminated = Token::Invalid(Box::new(LexError::UnterminatedComment));
+        assert_eq!(tokens("1 /* a /* b */"), [Token::Number(1), unterminated]);
+    }
+
+    #[test]
+    fn comments_are_kept_in_order_with_their_kind() {
+        assert_eq!(
+            comments("// line  \n/// doc\n//// rule\nx /* block */ // end"),
+            [
+                (CommentKind::Line, "// line".to_string()),
+                (CommentKind::Doc, "/// doc".to_string()),
+                (CommentKind::Line, "//// rule".to_string()),
+                (CommentKind::Block, "/* block */".to_string()),
+                (CommentKind::Line, "// end".to_string()),
+            ]
+        );
+        // Comment markers inside strings are text.
+        assert_eq!(
+            tokens(r#""// /* x""#),
+            [Token::StringLiteral("// /* x".to_string())]
+        );
+    }
+
+    #[test]
+    fn doc_comments_are_the_lines_directly_above_an_item() {
+        let code = "/// Unrelated.\n\n/// Adds one.\n///\n///  Indented.\nfn f(x) {}\n// Plain.\nfn g() {}\n";
+        let (tokens, comments) = tokenize_with_comments(code);
+        let item = |name: &str| {
+            let index = tokens
+                .iter()
+                .position(|token| token.token == Token::Identifier(name.to_string()))
+                .expect("the item exists");
+            tokens[index - 1].span
+        };
+
+        assert_eq!(
+            doc_comment(&comments, item("f")).as_deref(),
+            Some("Adds one.\n\n Indented.")
+        );
+        assert_eq!(doc_comment(&comments, item("g")), None);
+    }
+
+    #[test]
+    fn a_line_between_the_comment_and_the_item_detaches_it() {
+        let code = "/// Detached.\n\nlet x = 1;\n/// Before a block comment.\n/* */\nlet y = 2;";
+        let (tokens, comments) = tokenize_with_comments(code);
+        let lets: Vec<_> = tokens
+            .iter()
+            .filter(|token| token.token == Token::Let)
+            .map(|token| token.span)
+            .collect();
+        assert_eq!(doc_comment(&comments, lets[0]), None);
+        assert_eq!(doc_comment(&comments, lets[1]), None);
+    }
+}
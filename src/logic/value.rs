@@ -0,0 +1,597 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::diagnostic::SourceFile;
+use super::environment::Environment;
+use super::exec::{Block, RuntimeError};
+use super::span::Span;
+use super::vm::Closure;
+
+/// A runtime value produced by evaluating an expression.
+///
+/// Lists and maps are reference types: copies of a value share the same
+/// underlying storage, so mutation through one is visible through all.
+/// A list or map can therefore contain itself, which is displayed as
+/// `[...]` or `{...}` where it recurs. Displaying, comparing and dropping
+/// nested lists and maps does not recurse, so any depth can be handled.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(Rc<str>),
+    Nil,
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    Native(Rc<NativeFunction>),
+    Module(Rc<Module>),
+}
+
+/// The types of value that can be used as map keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Bool(bool),
+    Int(i64),
+    String(Rc<str>),
+}
+
+impl MapKey {
+    /// Converts a value into a key, or `None` if its type cannot be a key.
+    pub fn from_value(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::Bool(value) => Some(MapKey::Bool(*value)),
+            Value::Int(value) => Some(MapKey::Int(*value)),
+            Value::String(value) => Some(MapKey::String(value.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Bool(value) => Value::Bool(*value),
+            MapKey::Int(value) => Value::Int(*value),
+            MapKey::String(value) => Value::String(value.clone()),
+        }
+    }
+}
+
+/// A map that remembers the order its keys were first inserted in, which is
+/// the order it is displayed and iterated in.
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(MapKey, Value)>,
+    positions: HashMap<MapKey, usize>,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        let position = *self.positions.get(key)?;
+        Some(&self.entries[position].1)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Inserts or replaces the value for `key`. A replaced key keeps its
+    /// original position.
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.positions.get(&key) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let position = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+        for later in self.positions.values_mut() {
+            if *later > position {
+                *later -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    /// Entries in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+/// Maps are equal if they have the same entries, regardless of order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl FromIterator<(MapKey, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (MapKey, Value)>>(entries: I) -> Map {
+        let mut map = Map::new();
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+/// A function declared in a script with `fn`, together with the scope it
+/// was declared in.
+pub struct Function {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub(crate) body: Block,
+    pub closure: Rc<RefCell<Environment>>,
+    /// The file declaring the function; `None` for the main script.
+    pub file: Option<Rc<SourceFile>>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("parameters", &self.parameters)
+            .finish()
+    }
+}
+
+/// An imported file. Only the names it declared with `export` are visible
+/// to the importer, with the values they had when the file finished running.
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub exports: HashMap<String, Value>,
+}
+
+/// Signature of a native function. It is given the span of the call, where
+/// any error it returns is raised.
+pub type NativeFn = dyn Fn(&[Value], Span) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust and callable from scripts. `arity` is
+/// `None` for functions taking any number of arguments.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Option<usize>,
+    pub function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    /// Wraps a function whose `Err` is a message, which is reported to the
+    /// script as a [`RuntimeError::NativeError`].
+    pub fn new<F>(name: &str, arity: Option<usize>, function: F) -> NativeFunction
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let owner = name.to_string();
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(move |arguments, span| {
+                function(arguments).map_err(|message| RuntimeError::NativeError {
+                    name: owner.clone(),
+                    message,
+                    span,
+                })
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Nil => "nil",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) | Value::Closure(_) => "function",
+            Value::Native(_) => "native function",
+            Value::Module(_) => "module",
+        }
+    }
+
+    /// Formats the value the way it appears inside a collection or in the
+    /// REPL, where strings are quoted.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::String(value) => format!("{:?}", value),
+            value => value.to_string(),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+}
+
+impl From<Map> for Value {
+    fn from(map: Map) -> Value {
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(Rc::from(value))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(Rc::from(value))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => {
+                if *value != 0.0 && (value.abs() >= 1e16 || value.abs() < 1e-5) {
+                    write!(f, "{:e}", value)
+                } else if value.fract() == 0.0 {
+                    write!(f, "{:.1}", value)
+                } else {
+                    write!(f, "{}", value)
+                }
+            }
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Nil => write!(f, "nil"),
+            Value::List(_) | Value::Map(_) => write_collection(self, f),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.prototype.name),
+            Value::Native(function) => write!(f, "<native fn {}>", function.name),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::List(_), Value::List(_)) | (Value::Map(_), Value::Map(_)) => {
+                collections_equal(self, other)
+            }
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Numbers compare numerically, strings lexicographically and lists element
+/// by element. Values of other or mismatched types are unordered.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::List(a), Value::List(b)) => compare_lists(a, b),
+            _ => None,
+        }
+    }
+}
+
+/// Lists and maps are freed one at a time rather than recursively, so that
+/// dropping a deeply nested value needs no more stack than a flat one.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut orphans = Vec::new();
+        take_contents(self, &mut orphans);
+        while let Some(mut value) = orphans.pop() {
+            take_contents(&mut value, &mut orphans);
+        }
+    }
+}
+
+/// Moves the items of a list or map into `orphans` if `value` is the last
+/// reference to it.
+fn take_contents(value: &mut Value, orphans: &mut Vec<Value>) {
+    match value {
+        Value::List(items) => {
+            if let Some(items) = Rc::get_mut(items) {
+                orphans.append(items.get_mut());
+            }
+        }
+        Value::Map(map) => {
+            if let Some(map) = Rc::get_mut(map) {
+                let map = map.get_mut();
+                map.positions.clear();
+                orphans.extend(map.entries.drain(..).map(|(_, value)| value));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// What is left to write of a list or map being displayed.
+enum Part {
+    Value(Value),
+    Text(&'static str),
+    /// Closes the list or map at the address, which is no longer being
+    /// written.
+    Close(usize, &'static str),
+}
+
+/// Writes a list or map and the values in it, keeping what is left to write
+/// on a stack of its own instead of recursing. A list or map inside itself
+/// is written as `[...]` or `{...}`.
+fn write_collection(collection: &Value, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut parts = vec![Part::Value(collection.clone())];
+    let mut open = HashSet::new();
+
+    while let Some(part) = parts.pop() {
+        let value = match part {
+            Part::Value(value) => value,
+            Part::Text(text) => {
+                f.write_str(text)?;
+                continue;
+            }
+            Part::Close(address, text) => {
+                open.remove(&address);
+                f.write_str(text)?;
+                continue;
+            }
+        };
+
+        match &value {
+            Value::List(items) => {
+                let address = Rc::as_ptr(items) as usize;
+                if !open.insert(address) {
+                    f.write_str("[...]")?;
+                    continue;
+                }
+                f.write_str("[")?;
+                parts.push(Part::Close(address, "]"));
+                for (index, item) in items.borrow().iter().enumerate().rev() {
+                    parts.push(Part::Value(item.clone()));
+                    if index > 0 {
+                        parts.push(Part::Text(", "));
+                    }
+                }
+            }
+            Value::Map(entries) => {
+                let address = Rc::as_ptr(entries) as usize;
+                if !open.insert(address) {
+                    f.write_str("{...}")?;
+                    continue;
+                }
+                f.write_str("{")?;
+                parts.push(Part::Close(address, "}"));
+                for (index, (key, value)) in entries.borrow().entries.iter().enumerate().rev() {
+                    parts.push(Part::Value(value.clone()));
+                    parts.push(Part::Text(": "));
+                    parts.push(Part::Value(key.to_value()));
+                    if index > 0 {
+                        parts.push(Part::Text(", "));
+                    }
+                }
+            }
+            value => f.write_str(&value.repr())?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares two lists or two maps and the values in them, keeping the pairs
+/// left to compare on a stack instead of recursing. A pair of lists or maps
+/// met again is not compared twice, so values containing themselves are
+/// equal when they have the same shape.
+fn collections_equal(a: &Value, b: &Value) -> bool {
+    let mut pending = vec![(a.clone(), b.clone())];
+    let mut compared = HashSet::new();
+
+    while let Some((a, b)) = pending.pop() {
+        match (&a, &b) {
+            (Value::List(a), Value::List(b)) => {
+                if Rc::ptr_eq(a, b) || !compared.insert(addresses(a, b)) {
+                    continue;
+                }
+                let (a, b) = (a.borrow(), b.borrow());
+                if a.len() != b.len() {
+                    return false;
+                }
+                pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                if Rc::ptr_eq(a, b) || !compared.insert(addresses(a, b)) {
+                    continue;
+                }
+                let (a, b) = (a.borrow(), b.borrow());
+                if a.len() != b.len() {
+                    return false;
+                }
+                for (key, value) in a.iter() {
+                    match b.get(key) {
+                        Some(other) => pending.push((value.clone(), other.clone())),
+                        None => return false,
+                    }
+                }
+            }
+            _ => {
+                if a != b {
+                    return false;
+                }
+            }
+        }
+    }
+
+    true
+}
+
+/// Orders two lists element by element, descending into nested lists
+/// without recursing. A pair of lists met again inside itself counts as
+/// equal.
+fn compare_lists(a: &Rc<RefCell<Vec<Value>>>, b: &Rc<RefCell<Vec<Value>>>) -> Option<Ordering> {
+    let mut frames = vec![(a.clone(), b.clone(), 0)];
+    let mut open = HashSet::from([addresses(a, b)]);
+
+    while let Some((a, b, index)) = frames.last_mut() {
+        let (left, right) = (
+            a.borrow().get(*index).cloned(),
+            b.borrow().get(*index).cloned(),
+        );
+        *index += 1;
+        match (&left, &right) {
+            (None, None) => {
+                let (a, b, _) = frames.pop().expect("a frame is being compared");
+                open.remove(&addresses(&a, &b));
+            }
+            (None, Some(_)) => return Some(Ordering::Less),
+            (Some(_), None) => return Some(Ordering::Greater),
+            (Some(Value::List(a)), Some(Value::List(b))) => {
+                if open.insert(addresses(a, b)) {
+                    frames.push((a.clone(), b.clone(), 0));
+                }
+            }
+            (Some(left), Some(right)) => match left.partial_cmp(right) {
+                Some(Ordering::Equal) => {}
+                ordering => return ordering,
+            },
+        }
+    }
+
+    Some(Ordering::Equal)
+}
+
+fn addresses<T>(a: &Rc<T>, b: &Rc<T>) -> (usize, usize) {
+    (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `depth` lists, each containing the next, around an empty one.
+    fn nested_list(depth: usize) -> Value {
+        (0..depth).fold(Value::from(Vec::new()), |inner, _| Value::from(vec![inner]))
+    }
+
+    #[test]
+    fn deeply_nested_lists_are_displayed_compared_and_dropped() {
+        let depth = 200_000;
+        let (list, copy, shallower) = (
+            nested_list(depth),
+            nested_list(depth),
+            nested_list(depth - 1),
+        );
+
+        let text = list.to_string();
+        assert_eq!(text.len(), 2 * depth + 2);
+        assert!(text.starts_with("[[[") && text.ends_with("]]]"));
+
+        assert_eq!(list, copy);
+        assert_ne!(list, shallower);
+        assert_eq!(list.partial_cmp(&copy), Some(Ordering::Equal));
+        assert_eq!(shallower.partial_cmp(&list), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn deeply_nested_maps_are_displayed_and_compared() {
+        let depth = 100_000;
+        let nested_map = || {
+            (0..depth).fold(Value::from(Map::new()), |inner, _| {
+                Value::from(Map::from_iter([(MapKey::String(Rc::from("m")), inner)]))
+            })
+        };
+        let (map, copy) = (nested_map(), nested_map());
+
+        assert_eq!(map.to_string().len(), "{\"m\": }".len() * depth + 2);
+        assert_eq!(map, copy);
+    }
+
+    #[test]
+    fn lists_order_element_by_element() {
+        let list = |items: &[i64]| {
+            Value::from(
+                items
+                    .iter()
+                    .map(|&item| Value::Int(item))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            list(&[1, 2]).partial_cmp(&list(&[1, 3])),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            list(&[1, 2, 0]).partial_cmp(&list(&[1, 2])),
+            Some(Ordering::Greater)
+        );
+        let nested = Value::from(vec![list(&[1]), Value::Int(5)]);
+        let other = Value::from(vec![list(&[1]), Value::Int(4)]);
+        assert_eq!(nested.partial_cmp(&other), Some(Ordering::Greater));
+        assert_eq!(nested.partial_cmp(&list(&[1])), None);
+    }
+}
@@ -0,0 +1,832 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use super::ast::{ASTNode, Token};
+use super::builtins;
+use super::bytecode::{Binding, Capture, GlobalNames, Instruction, Prototype};
+use super::compiler;
+use super::diagnostic::SourceFile;
+use super::exec::{RuntimeError, MAX_CALL_DEPTH};
+use super::operations;
+use super::span::Span;
+use super::value::Value;
+use crate::loader::{same_file, Import, Loader};
+use crate::sandbox::Capabilities;
+
+/// The global variables of the main program or of one module, and the file
+/// its code comes from: `None` for the main program. Pieces of the main
+/// program run from different sources, such as REPL entries, each have a
+/// scope of their own sharing the same globals.
+struct Scope {
+    globals: Rc<RefCell<Globals>>,
+    file: Option<Rc<SourceFile>>,
+}
+
+/// The slots the compiler gave the globals of a file, and their values:
+/// `None` for a global that the code refers to but has not defined yet.
+#[derive(Default)]
+struct Globals {
+    names: GlobalNames,
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    fn get(&self, slot: u32) -> Option<&Value> {
+        self.values.get(slot as usize)?.as_ref()
+    }
+
+    fn get_mut(&mut self, slot: u32) -> Option<&mut Value> {
+        self.values.get_mut(slot as usize)?.as_mut()
+    }
+
+    fn define(&mut self, slot: u32, value: Value) {
+        let slot = slot as usize;
+        if slot >= self.values.len() {
+            self.values.resize_with(slot + 1, || None);
+        }
+        self.values[slot] = Some(value);
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.get(self.names.get(name)?)
+    }
+
+    fn define_named(&mut self, name: &str, value: Value) {
+        let slot = self.names.slot(name);
+        self.define(slot, value);
+    }
+
+    /// Defined globals with their names, in the order of their slots.
+    fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.names
+            .names()
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| Some((name, value.as_ref()?)))
+    }
+
+    /// The error for a use of the undefined global in `slot`.
+    fn undefined(&self, slot: u32, span: Span) -> RuntimeError {
+        RuntimeError::UndefinedVariable {
+            name: self.names.name(slot).to_string(),
+            span,
+        }
+    }
+
+    /// The error for an assignment to the undefined global in `slot`.
+    fn undeclared(&self, slot: u32, span: Span) -> RuntimeError {
+        RuntimeError::UndeclaredAssignment {
+            name: self.names.name(slot).to_string(),
+            span,
+        }
+    }
+}
+
+/// A function compiled for the VM together with the variables it captured
+/// and the scope of the file it was declared in.
+pub struct Closure {
+    pub prototype: Rc<Prototype>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    scope: Rc<Scope>,
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("name", &self.prototype.name)
+            .field("arity", &self.prototype.arity)
+            .finish()
+    }
+}
+
+/// A captured variable. It refers to a stack slot while the scope declaring
+/// it is live, and holds the value itself once that scope has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+    /// A slot captured before the declaration it is reserved for has run.
+    /// Until then the enclosing variable of the same name stands in for it,
+    /// and for good if the scope ends without the declaration running.
+    Pending(Option<usize>, Outer),
+}
+
+/// The variable standing in for a pending upvalue: another upvalue or the
+/// global in a slot.
+#[derive(Debug)]
+pub enum Outer {
+    Upvalue(Rc<RefCell<Upvalue>>),
+    Global(u32),
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of the frame's first local; the callee sits just below.
+    base: usize,
+}
+
+/// Stack-based virtual machine running code produced by the compiler.
+/// Like the tree-walking [`Interpreter`], it keeps its globals across runs.
+///
+/// [`Interpreter`]: super::exec::Interpreter
+pub struct Vm {
+    /// The scope of the main program.
+    scope: Rc<Scope>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// Upvalues still pointing into the stack.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Where `print` writes to; stdout unless replaced by the host.
+    output: Box<dyn Write>,
+    loader: Loader,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let mut globals = Globals::default();
+        for native in builtins::natives() {
+            let name = native.name.clone();
+            globals.define_named(&name, Value::Native(Rc::new(native)));
+        }
+        Vm {
+            scope: Rc::new(Scope {
+                globals: Rc::new(RefCell::new(globals)),
+                file: None,
+            }),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            output: Box::new(io::stdout()),
+            loader: Loader::new(),
+        }
+    }
+
+    /// Sets the file being run, which imports are resolved against.
+    pub fn set_script_path(&mut self, path: &Path) {
+        self.loader.set_script_path(path);
+    }
+
+    /// Sets the file the code run next comes from, `None` for the main
+    /// script. Functions it declares keep the file.
+    pub fn set_file(&mut self, file: Option<Rc<SourceFile>>) {
+        self.scope = Rc::new(Scope {
+            globals: self.scope.globals.clone(),
+            file,
+        });
+    }
+
+    /// Shares the capabilities that decide which modules may be imported.
+    pub fn set_capabilities(&mut self, capabilities: Rc<RefCell<Capabilities>>) {
+        self.loader.set_capabilities(capabilities);
+    }
+
+    /// Redirects the output of `print` statements.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.scope.globals.borrow_mut().define_named(name, value);
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.scope.globals.borrow().lookup(name).cloned()
+    }
+
+    /// All global variables, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self
+            .scope
+            .globals
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Compiles and executes a parsed program in the global scope.
+    pub fn run(&mut self, program: ASTNode) -> Result<(), RuntimeError> {
+        let prototype = compiler::compile(&program, &mut self.scope.globals.borrow_mut().names)?;
+        let result = self.execute(prototype, self.scope.clone());
+        self.output.flush().map_err(|error| RuntimeError::Output {
+            message: error.to_string(),
+            span: program.span(),
+        })?;
+        result?;
+        Ok(())
+    }
+
+    /// Compiles and evaluates a single expression in the global scope.
+    pub fn evaluate(&mut self, expression: ASTNode) -> Result<Value, RuntimeError> {
+        let prototype =
+            compiler::compile_expression(&expression, &mut self.scope.globals.borrow_mut().names)?;
+        self.execute(prototype, self.scope.clone())
+    }
+
+    /// Runs a compiled program in `scope`. On failure the frames and stack
+    /// are unwound to where they were on entry, leaving those of an outer
+    /// run, such as the one importing a module, to their own dispatch loop.
+    fn execute(&mut self, prototype: Prototype, scope: Rc<Scope>) -> Result<Value, RuntimeError> {
+        let depth = self.frames.len();
+        let height = self.stack.len();
+        let frame = Frame {
+            closure: Rc::new(Closure {
+                prototype: Rc::new(prototype),
+                upvalues: Vec::new(),
+                scope,
+            }),
+            ip: 0,
+            base: height,
+        };
+
+        let result = self.dispatch(frame);
+        if result.is_err() {
+            // Closures that escaped keep working with the values they saw.
+            self.close_upvalues(height);
+            self.stack.truncate(height);
+            self.frames.truncate(depth);
+        }
+        result
+    }
+
+    /// Runs `frame` until it returns. Frames of outer dispatch loops, such
+    /// as the importer of a module, stay below the floor.
+    fn dispatch(&mut self, mut frame: Frame) -> Result<Value, RuntimeError> {
+        let floor = self.frames.len();
+        match self.run_frames(&mut frame, floor) {
+            Err(error) => Err(self.attribute(error, &frame, floor)),
+            result => result,
+        }
+    }
+
+    fn run_frames(&mut self, frame: &mut Frame, floor: usize) -> Result<Value, RuntimeError> {
+        loop {
+            let instruction = frame.closure.prototype.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match instruction {
+                Instruction::Constant(index) => {
+                    let value = frame.closure.prototype.chunk.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Instruction::Nil => self.stack.push(Value::Nil),
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::PopLocals(count) => {
+                    let height = self.stack.len() - count as usize;
+                    self.close_upvalues(height);
+                    self.stack.truncate(height);
+                }
+                Instruction::GetLocal(slot) => {
+                    let value = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Instruction::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[frame.base + slot as usize] = value;
+                }
+                Instruction::DeclareLocal(slot) => {
+                    let slot = frame.base + slot as usize;
+                    self.stack[slot] = self.pop();
+                    for upvalue in &self.open_upvalues {
+                        let mut upvalue = upvalue.borrow_mut();
+                        if matches!(*upvalue, Upvalue::Pending(Some(pending), _) if pending == slot)
+                        {
+                            *upvalue = Upvalue::Open(slot);
+                        }
+                    }
+                }
+                Instruction::GetUpvalue(index) => {
+                    let value =
+                        self.get_upvalue(frame.closure.upvalues[index as usize].clone(), frame)?;
+                    self.stack.push(value);
+                }
+                Instruction::SetUpvalue(index) => {
+                    let value = self.pop();
+                    self.set_upvalue(frame.closure.upvalues[index as usize].clone(), value, frame)?;
+                }
+                Instruction::GetGlobal(slot) => {
+                    let globals = frame.closure.scope.globals.borrow();
+                    match globals.get(slot) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(globals.undefined(slot, span(frame))),
+                    }
+                }
+                Instruction::SetGlobal(slot) => {
+                    let value = self.pop();
+                    let mut globals = frame.closure.scope.globals.borrow_mut();
+                    match globals.get_mut(slot) {
+                        Some(global) => *global = value,
+                        None => return Err(globals.undeclared(slot, span(frame))),
+                    }
+                }
+                Instruction::DefineGlobal(slot) => {
+                    let value = self.pop();
+                    frame.closure.scope.globals.borrow_mut().define(slot, value);
+                }
+                Instruction::Add => {
+                    self.arithmetic(Token::Plus, frame, i64::checked_add, |a, b| Some(a + b))?
+                }
+                Instruction::Subtract => {
+                    self.arithmetic(Token::Minus, frame, i64::checked_sub, |a, b| Some(a - b))?
+                }
+                Instruction::Multiply => {
+                    self.arithmetic(Token::Asterisk, frame, i64::checked_mul, |a, b| Some(a * b))?
+                }
+                Instruction::Divide => {
+                    self.arithmetic(Token::Slash, frame, i64::checked_div, |a, b| {
+                        (b != 0.0).then(|| a / b)
+                    })?
+                }
+                Instruction::Modulo => {
+                    self.arithmetic(Token::Modulo, frame, i64::checked_rem, |a, b| {
+                        (b != 0.0).then(|| a % b)
+                    })?
+                }
+                Instruction::Equal => self.comparison(Token::EqualEqual, frame, Ordering::is_eq)?,
+                Instruction::NotEqual => {
+                    self.comparison(Token::NotEqual, frame, Ordering::is_ne)?
+                }
+                Instruction::Less => self.comparison(Token::LessThan, frame, Ordering::is_lt)?,
+                Instruction::LessEqual => {
+                    self.comparison(Token::LessThanOrEqual, frame, Ordering::is_le)?
+                }
+                Instruction::Greater => {
+                    self.comparison(Token::GreaterThan, frame, Ordering::is_gt)?
+                }
+                Instruction::GreaterEqual => {
+                    self.comparison(Token::GreaterThanOrEqual, frame, Ordering::is_ge)?
+                }
+                Instruction::Contains => self.binary(Token::In, frame)?,
+                Instruction::Negate => self.unary(Token::Minus, frame)?,
+                Instruction::Not => self.unary(Token::Not, frame)?,
+                Instruction::List(count) => {
+                    let items = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Value::from(items));
+                }
+                Instruction::Interpolate(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(operations::interpolate(&parts));
+                }
+                Instruction::Map(count) => {
+                    let values = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut values = values.into_iter();
+                    let mut entries = Vec::with_capacity(count as usize);
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        entries.push((key, value));
+                    }
+                    self.stack.push(operations::map(entries, span(frame))?);
+                }
+                Instruction::GetMember(index) => {
+                    let name = &frame.closure.prototype.chunk.names[index as usize];
+                    let target = self.pop();
+                    self.stack
+                        .push(operations::member(&target, name, span(frame))?);
+                }
+                Instruction::Import(index) => {
+                    let Value::String(path) =
+                        &frame.closure.prototype.chunk.constants[index as usize]
+                    else {
+                        unreachable!("the compiler emits imports with a string constant");
+                    };
+                    let module = self.import(&path.clone(), frame)?;
+                    self.stack.push(module);
+                }
+                Instruction::GetIndex => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    self.stack
+                        .push(operations::index(&target, &index, span(frame))?);
+                }
+                Instruction::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let target = self.pop();
+                    operations::set_index(&target, &index, value, span(frame))?;
+                }
+                Instruction::Range => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let (start, end) = operations::range_bounds(&start, &end, span(frame))?;
+                    self.stack.push(Value::from(
+                        (start..end).map(Value::Int).collect::<Vec<_>>(),
+                    ));
+                }
+                Instruction::CheckRange => {
+                    let [start, end] = &self.stack[self.stack.len() - 2..] else {
+                        unreachable!("a range has two bounds");
+                    };
+                    operations::range_bounds(start, end, span(frame))?;
+                }
+                Instruction::IntoIterator => {
+                    let iterable = self.pop();
+                    let items = operations::iteration_items(&iterable, span(frame))?;
+                    self.stack.push(Value::from(items));
+                    self.stack.push(Value::Int(0));
+                }
+                Instruction::Iterate { slot, exit } => {
+                    let state = frame.base + slot as usize;
+                    match self.next_item(state) {
+                        Some(item) => self.stack.push(item),
+                        None => frame.ip = exit as usize,
+                    }
+                }
+                Instruction::Jump(target) => frame.ip = target as usize,
+                Instruction::JumpIfFalse(target) => {
+                    if !operations::is_true(&self.pop()) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Instruction::JumpIfFalseOrPop(target) => {
+                    if operations::is_true(self.peek()) {
+                        self.pop();
+                    } else {
+                        frame.ip = target as usize;
+                    }
+                }
+                Instruction::JumpIfTrueOrPop(target) => {
+                    if operations::is_true(self.peek()) {
+                        frame.ip = target as usize;
+                    } else {
+                        self.pop();
+                    }
+                }
+                Instruction::Call(count) => {
+                    let callee_index = self.stack.len() - count as usize - 1;
+                    let callee = self.stack[callee_index].clone();
+                    match &callee {
+                        Value::Closure(closure) => {
+                            if closure.prototype.arity != count as usize {
+                                return Err(RuntimeError::ArityMismatch {
+                                    name: closure.prototype.name.clone(),
+                                    expected: closure.prototype.arity,
+                                    found: count as usize,
+                                    span: span(frame),
+                                });
+                            }
+                            if self.frames.len() >= MAX_CALL_DEPTH {
+                                return Err(RuntimeError::StackOverflow { span: span(frame) });
+                            }
+
+                            let callee = Frame {
+                                closure: closure.clone(),
+                                ip: 0,
+                                base: callee_index + 1,
+                            };
+                            self.frames.push(std::mem::replace(frame, callee));
+                        }
+                        Value::Native(native) => {
+                            let arguments = &self.stack[callee_index + 1..];
+                            let result = operations::call_native(native, arguments, span(frame))?;
+                            self.stack.truncate(callee_index);
+                            self.stack.push(result);
+                        }
+                        value => {
+                            return Err(RuntimeError::NotCallable {
+                                type_name: value.type_name(),
+                                span: span(frame),
+                            })
+                        }
+                    }
+                }
+                Instruction::Closure(index) => {
+                    let prototype =
+                        frame.closure.prototype.chunk.prototypes[index as usize].clone();
+                    let upvalues = prototype
+                        .captures
+                        .iter()
+                        .map(|capture| self.capture_for(capture, frame))
+                        .collect();
+                    self.stack.push(Value::Closure(Rc::new(Closure {
+                        prototype,
+                        upvalues,
+                        scope: frame.closure.scope.clone(),
+                    })));
+                }
+                Instruction::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(frame.base);
+                    if self.frames.len() == floor {
+                        self.stack.truncate(frame.base);
+                        return Ok(result);
+                    }
+                    // Drop the callee along with the arguments and locals.
+                    self.stack.truncate(frame.base - 1);
+                    self.stack.push(result);
+                    *frame = self.frames.pop().expect("checked against the floor");
+                }
+                Instruction::Print => {
+                    // One write per `print` so line-oriented sinks see whole lines.
+                    let line = format!("{}\n", self.pop());
+                    self.output.write_all(line.as_bytes()).map_err(|error| {
+                        RuntimeError::Output {
+                            message: error.to_string(),
+                            span: span(frame),
+                        }
+                    })?;
+                }
+            }
+        }
+    }
+
+    /// Attributes an error raised in `frame` to the file of the code raising
+    /// it as it propagates through calls from code in other files.
+    fn attribute(&self, mut error: RuntimeError, frame: &Frame, floor: usize) -> RuntimeError {
+        let mut file = &frame.closure.scope.file;
+        for caller in self.frames[floor..].iter().rev() {
+            let caller_file = &caller.closure.scope.file;
+            if !same_file(file, caller_file) {
+                error = error.in_file(file.as_ref(), span(caller));
+            }
+            file = caller_file;
+        }
+        error
+    }
+
+    /// Runs a module the first time it is imported, with globals of its own
+    /// that only share the native functions with the importer.
+    fn import(&mut self, path: &str, frame: &Frame) -> Result<Value, RuntimeError> {
+        let span = span(frame);
+        let module = match self.loader.begin(path, span)? {
+            Import::Loaded(module) => return Ok(module),
+            Import::Pending(module) => module,
+        };
+
+        let mut globals = Globals::default();
+        for (name, value) in frame.closure.scope.globals.borrow().iter() {
+            if matches!(value, Value::Native(_)) {
+                globals.define_named(name, value.clone());
+            }
+        }
+        let scope = Rc::new(Scope {
+            globals: Rc::new(RefCell::new(globals)),
+            file: Some(module.file.clone()),
+        });
+        let compiled = compiler::compile(&module.program, &mut scope.globals.borrow_mut().names);
+        let result = compiled
+            .and_then(|prototype| self.execute(prototype, scope.clone()))
+            .map(|_| ());
+        self.loader.finish(
+            module,
+            result,
+            |name| scope.globals.borrow().lookup(name).cloned(),
+            span,
+        )
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack
+            .last()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn binary(&mut self, operator: Token, frame: &Frame) -> Result<(), RuntimeError> {
+        let right = self.pop();
+        let left = self.pop();
+        let result = operations::binary(&left, &operator, &right, span(frame))?;
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Applies an arithmetic operator, computing the result in place when
+    /// both operands are ints or both are floats. Other operands, and
+    /// operations that fail, such as on overflow or division by zero, take
+    /// the general path through [`operations::binary`] with its errors.
+    fn arithmetic(
+        &mut self,
+        operator: Token,
+        frame: &Frame,
+        ints: fn(i64, i64) -> Option<i64>,
+        floats: fn(f64, f64) -> Option<f64>,
+    ) -> Result<(), RuntimeError> {
+        let result = match self.operands() {
+            (Value::Int(left), Value::Int(right)) => ints(*left, *right).map(Value::Int),
+            (Value::Float(left), Value::Float(right)) => floats(*left, *right).map(Value::Float),
+            _ => None,
+        };
+        match result {
+            Some(result) => {
+                self.replace_operands(result);
+                Ok(())
+            }
+            None => self.binary(operator, frame),
+        }
+    }
+
+    /// Applies a comparison, like [`arithmetic`](Vm::arithmetic) in place
+    /// for two ints or two ordered floats.
+    fn comparison(
+        &mut self,
+        operator: Token,
+        frame: &Frame,
+        test: fn(Ordering) -> bool,
+    ) -> Result<(), RuntimeError> {
+        let result = match self.operands() {
+            (Value::Int(left), Value::Int(right)) => Some(test(left.cmp(right))),
+            (Value::Float(left), Value::Float(right)) => left.partial_cmp(right).map(test),
+            _ => None,
+        };
+        match result {
+            Some(result) => {
+                self.replace_operands(Value::Bool(result));
+                Ok(())
+            }
+            None => self.binary(operator, frame),
+        }
+    }
+
+    /// The two operands of a binary operator on top of the stack.
+    fn operands(&self) -> (&Value, &Value) {
+        match &self.stack[..] {
+            [.., left, right] => (left, right),
+            _ => unreachable!("the compiler keeps the stack balanced"),
+        }
+    }
+
+    /// Replaces the two operands on top of the stack with `result`.
+    fn replace_operands(&mut self, result: Value) {
+        self.pop();
+        *self
+            .stack
+            .last_mut()
+            .expect("the compiler keeps the stack balanced") = result;
+    }
+
+    fn unary(&mut self, operator: Token, frame: &Frame) -> Result<(), RuntimeError> {
+        let value = self.pop();
+        let result = operations::unary(&operator, &value, span(frame))?;
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Advances the `for` loop state stored at `state` and `state + 1`.
+    fn next_item(&mut self, state: usize) -> Option<Value> {
+        let (item, advanced) = match (&self.stack[state], &self.stack[state + 1]) {
+            (Value::Int(current), Value::Int(end)) if current < end => {
+                (Value::Int(*current), (state, Value::Int(current + 1)))
+            }
+            (Value::List(items), Value::Int(position)) => {
+                let item = items.borrow().get(*position as usize).cloned()?;
+                (item, (state + 1, Value::Int(position + 1)))
+            }
+            _ => return None,
+        };
+        self.stack[advanced.0] = advanced.1;
+        Some(item)
+    }
+
+    /// Reads a captured variable, following pending upvalues to the
+    /// variable standing in for them.
+    fn get_upvalue(
+        &self,
+        mut upvalue: Rc<RefCell<Upvalue>>,
+        frame: &Frame,
+    ) -> Result<Value, RuntimeError> {
+        loop {
+            let next = match &*upvalue.borrow() {
+                Upvalue::Open(slot) => return Ok(self.stack[*slot].clone()),
+                Upvalue::Closed(value) => return Ok(value.clone()),
+                Upvalue::Pending(_, Outer::Upvalue(next)) => next.clone(),
+                Upvalue::Pending(_, Outer::Global(slot)) => {
+                    let globals = frame.closure.scope.globals.borrow();
+                    return match globals.get(*slot) {
+                        Some(value) => Ok(value.clone()),
+                        None => Err(globals.undefined(*slot, span(frame))),
+                    };
+                }
+            };
+            upvalue = next;
+        }
+    }
+
+    /// Assigns to a captured variable, following pending upvalues to the
+    /// variable standing in for them.
+    fn set_upvalue(
+        &mut self,
+        mut upvalue: Rc<RefCell<Upvalue>>,
+        value: Value,
+        frame: &Frame,
+    ) -> Result<(), RuntimeError> {
+        loop {
+            let next = match &mut *upvalue.borrow_mut() {
+                Upvalue::Open(slot) => {
+                    self.stack[*slot] = value;
+                    return Ok(());
+                }
+                Upvalue::Closed(closed) => {
+                    *closed = value;
+                    return Ok(());
+                }
+                Upvalue::Pending(_, Outer::Upvalue(next)) => next.clone(),
+                Upvalue::Pending(_, Outer::Global(slot)) => {
+                    let mut globals = frame.closure.scope.globals.borrow_mut();
+                    return match globals.get_mut(*slot) {
+                        Some(global) => {
+                            *global = value;
+                            Ok(())
+                        }
+                        None => Err(globals.undeclared(*slot, span(frame))),
+                    };
+                }
+            };
+            upvalue = next;
+        }
+    }
+
+    /// Finds the upvalue a closure created in `frame` gets for `capture`.
+    fn capture_for(&mut self, capture: &Capture, frame: &Frame) -> Rc<RefCell<Upvalue>> {
+        if !capture.local {
+            return frame.closure.upvalues[capture.index as usize].clone();
+        }
+
+        let slot = frame.base + capture.index as usize;
+        let outer = match capture.pending {
+            None => return self.capture(slot),
+            Some(Binding::Local(outer)) => {
+                Outer::Upvalue(self.capture(frame.base + outer as usize))
+            }
+            Some(Binding::Upvalue(index)) => {
+                Outer::Upvalue(frame.closure.upvalues[index as usize].clone())
+            }
+            Some(Binding::Global(slot)) => Outer::Global(slot),
+        };
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Pending(Some(pending), _) if pending == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Pending(Some(slot), outer)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Returns the upvalue for a stack slot, sharing it with any closure that
+    /// already captured the same slot.
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of all captured slots from `height` upwards off the
+    /// stack and into their upvalues. Pending upvalues among them keep
+    /// standing for the enclosing variable.
+    fn close_upvalues(&mut self, height: usize) {
+        if self.open_upvalues.is_empty() {
+            return;
+        }
+
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match &mut *upvalue {
+                Upvalue::Open(slot) if *slot >= height => {
+                    let value = stack[*slot].clone();
+                    *upvalue = Upvalue::Closed(value);
+                    false
+                }
+                Upvalue::Pending(slot, _) if slot.is_some_and(|slot| slot >= height) => {
+                    *slot = None;
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+}
+
+/// The span of the instruction `frame` is executing.
+fn span(frame: &Frame) -> Span {
+    frame.closure.prototype.chunk.spans[frame.ip - 1]
+}
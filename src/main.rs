@@ -1,19 +1,224 @@
-use logic::exec::interpret;
+use std::io::IsTerminal;
+use std::process::ExitCode;
 
-use crate::logic::ast::parse;
+use cli::{Command, FmtOptions, RunOptions};
+use netl::logic::ast::parse;
+use netl::logic::bytecode::GlobalNames;
+use netl::logic::checker;
+use netl::logic::compiler::compile;
+use netl::logic::diagnostic::Severity;
+use netl::logic::formatter;
+use netl::{Backend, Capabilities, Engine, Value};
 
-mod logic;
+mod cli;
+mod repl;
 
-fn main() {
-    let script = std::fs::read_to_string("./examples/test.nl").unwrap();
+/// Exit codes follow the BSD `sysexits.h` conventions.
+const EXIT_USAGE: u8 = 64;
+const EXIT_PARSE_ERROR: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_RUNTIME_ERROR: u8 = 70;
 
-    let tokens = logic::tokenizer::tokenize(&script);
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = if args.is_empty() && std::io::stdin().is_terminal() {
+        Ok(Command::Repl(Backend::default()))
+    } else {
+        cli::parse_args(args)
+    };
 
-    println!("{:?}", tokens);
+    match command {
+        Ok(Command::Run(options)) => run(options),
+        Ok(Command::Repl(backend)) => {
+            let mut engine = Engine::with_backend(backend);
+            engine.set_global("args", Value::from(Vec::new()));
+            match repl::Repl::new(engine).run() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("netl: {}", error);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Ok(Command::Fmt(options)) => fmt(options),
+        Ok(Command::Check(sources)) => check(sources),
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            ExitCode::SUCCESS
+        }
+        Ok(Command::Version) => {
+            println!("netl {}", env!("CARGO_PKG_VERSION"));
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("netl: {}\n\n{}", message, cli::USAGE);
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
+
+/// Formats each file in place, or stdin to stdout. With `--check`, lists
+/// the files that are not formatted instead and fails if there are any.
+fn fmt(options: FmtOptions) -> ExitCode {
+    let color = std::io::stderr().is_terminal();
+    let sources = if options.files.is_empty() {
+        vec![cli::Source::Stdin]
+    } else {
+        options.files.into_iter().map(cli::Source::File).collect()
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for source in sources {
+        let name = source.name();
+        let script = match source.read() {
+            Ok(script) => script,
+            Err(error) => {
+                eprintln!("netl: cannot read {}: {}", name, error);
+                status = ExitCode::from(EXIT_NO_INPUT);
+                continue;
+            }
+        };
+        let formatted = match formatter::format(&script) {
+            Ok(formatted) => formatted,
+            Err(diagnostic) => {
+                eprint!("{}", diagnostic.render(&script, name, color));
+                status = ExitCode::from(EXIT_PARSE_ERROR);
+                continue;
+            }
+        };
+
+        if options.check {
+            if formatted != script {
+                println!("{} is not formatted", name);
+                status = ExitCode::FAILURE;
+            }
+        } else if let cli::Source::File(path) = &source {
+            if formatted != script {
+                if let Err(error) = std::fs::write(path, formatted) {
+                    eprintln!("netl: cannot write {}: {}", name, error);
+                    status = ExitCode::FAILURE;
+                }
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+
+    status
+}
+
+/// Reports the diagnostics of the static checker for each script, or
+/// stdin, failing if any of them is an error.
+fn check(mut sources: Vec<cli::Source>) -> ExitCode {
+    let color = std::io::stderr().is_terminal();
+    if sources.is_empty() {
+        sources.push(cli::Source::Stdin);
+    }
+
+    // The names every script starts out with, as `run` defines them.
+    let mut engine = Engine::new();
+    engine.set_global("args", Value::from(Vec::new()));
+    let predefined: Vec<String> = engine.globals().into_iter().map(|(name, _)| name).collect();
+
+    let mut status = ExitCode::SUCCESS;
+    for source in sources {
+        let name = source.name();
+        let script = match source.read() {
+            Ok(script) => script,
+            Err(error) => {
+                eprintln!("netl: cannot read {}: {}", name, error);
+                status = ExitCode::from(EXIT_NO_INPUT);
+                continue;
+            }
+        };
+        let diagnostics = match parse(netl::tokenize(&script)) {
+            Ok(program) => checker::check(&program, &predefined),
+            Err(diagnostic) => vec![diagnostic],
+        };
+
+        for diagnostic in diagnostics {
+            eprint!("{}", diagnostic.render(&script, name, color));
+            if diagnostic.severity == Severity::Error {
+                status = ExitCode::from(EXIT_PARSE_ERROR);
+            }
+        }
+    }
+
+    status
+}
+
+fn run(options: RunOptions) -> ExitCode {
+    let mut capabilities = Capabilities::new();
+    for dir in &options.allow_read {
+        if let Err(error) = capabilities.allow_read(dir) {
+            eprintln!("netl: cannot allow reading {}: {}", dir, error);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+    for dir in &options.allow_write {
+        if let Err(error) = capabilities.allow_write(dir) {
+            eprintln!("netl: cannot allow writing {}: {}", dir, error);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+
+    let name = options.source.name();
+    let script = match options.source.read() {
+        Ok(script) => script,
+        Err(error) => {
+            eprintln!("netl: cannot read {}: {}", name, error);
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    };
+    let color = std::io::stderr().is_terminal();
+
+    let tokens = netl::tokenize(&script);
+
+    if options.dump_tokens {
+        for token in &tokens {
+            println!("{}\t{:?}", token.span, token.token);
+        }
+    }
+
+    let program = match parse(tokens) {
+        Ok(program) => program,
+        Err(diagnostic) => {
+            eprint!("{}", diagnostic.render(&script, name, color));
+            return ExitCode::from(EXIT_PARSE_ERROR);
+        }
+    };
+
+    if options.dump_ast {
+        println!("{:#?}", program);
+    }
+
+    if options.dump_bytecode {
+        let mut globals = GlobalNames::default();
+        match compile(&program, &mut globals) {
+            Ok(prototype) => print!("{}", prototype.disassemble(&globals)),
+            Err(error) => {
+                eprint!("{}", error.to_diagnostic().render(&script, name, color));
+                return ExitCode::from(EXIT_RUNTIME_ERROR);
+            }
+        }
+    }
 
-    let result = parse(tokens);
+    let args = options
+        .args
+        .into_iter()
+        .map(Value::from)
+        .collect::<Vec<_>>();
+    let mut engine = Engine::with_backend(options.backend);
+    engine.set_capabilities(capabilities);
+    engine.set_global("args", Value::from(args));
+    if let cli::Source::File(path) = &options.source {
+        engine.set_script_path(path);
+    }
 
-    println!("{:?}", result);
+    if let Err(error) = engine.execute(program) {
+        eprint!("{}", error.to_diagnostic().render(&script, name, color));
+        return ExitCode::from(EXIT_RUNTIME_ERROR);
+    }
 
-    interpret(result.unwrap());
+    ExitCode::SUCCESS
 }
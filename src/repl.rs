@@ -0,0 +1,314 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use netl::logic::ast::{parse, parse_expression, Token};
+use netl::logic::tokenizer::LexError;
+use netl::{tokenize, Diagnostic, Engine, Value};
+
+const HELP: &str = "\
+Enter statements or expressions; expression results are printed.
+Input continues on the next line while brackets are left open.
+
+Commands:
+    :tokens <code>   show the tokens of <code>
+    :ast <code>      show the syntax tree of <code>
+    :vars            list global variables, except native functions
+    :load <file>     run a script in this session
+    :history         show previous inputs
+    :help            show this help
+    :quit            leave the REPL (also Ctrl-D)";
+
+const SOURCE_NAME: &str = "<repl>";
+
+/// An interactive session. The engine, and with it every global defined so
+/// far, lives as long as the session.
+pub struct Repl {
+    engine: Engine,
+    history: Vec<String>,
+    /// Where entries are saved for later sessions; only interactive sessions
+    /// have one.
+    history_file: Option<PathBuf>,
+    color: bool,
+}
+
+impl Repl {
+    pub fn new(engine: Engine) -> Repl {
+        let mut repl = Repl {
+            engine,
+            history: Vec::new(),
+            history_file: None,
+            color: io::stderr().is_terminal(),
+        };
+        if io::stdin().is_terminal() {
+            repl.load_history();
+        }
+        repl
+    }
+
+    /// Loads the entries of earlier sessions from `~/.netl_history`, which
+    /// new entries are then appended to.
+    fn load_history(&mut self) {
+        let Some(home) = std::env::var_os("HOME") else {
+            eprintln!("netl: HOME is not set; history will not be saved");
+            return;
+        };
+        let path = PathBuf::from(home).join(".netl_history");
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => self.history = contents.lines().map(unescape_history).collect(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                eprintln!(
+                    "netl: cannot read {}: {}; history will not be saved",
+                    path.display(),
+                    error
+                );
+                return;
+            }
+        }
+        self.history_file = Some(path);
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        println!(
+            "netl {} (type :help for help, :quit to exit)",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        loop {
+            let Some(input) = read_entry(&mut lines)? else {
+                println!();
+                return Ok(());
+            };
+            let trimmed = input.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            self.record(&input);
+
+            if let Some(command) = trimmed.strip_prefix(':') {
+                if !self.command(command) {
+                    return Ok(());
+                }
+            } else {
+                self.evaluate(&input);
+            }
+        }
+    }
+
+    /// Runs a meta-command. Returns `false` if the session should end.
+    fn command(&mut self, command: &str) -> bool {
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, argument)| (name, argument.trim()));
+
+        match name {
+            "q" | "quit" | "exit" => return false,
+            "h" | "help" => println!("{}", HELP),
+            "tokens" => {
+                for token in tokenize(argument) {
+                    println!("{}\t{:?}", token.span, token.token);
+                }
+            }
+            "ast" => match parse(tokenize(argument)) {
+                Ok(program) => println!("{:#?}", program),
+                Err(diagnostic) => match parse_expression(tokenize(argument)) {
+                    Ok(expression) => println!("{:#?}", expression),
+                    Err(_) => self.report(&diagnostic, argument, SOURCE_NAME),
+                },
+            },
+            "vars" => {
+                let globals = self.engine.globals().into_iter();
+                for (name, value) in globals.filter(|(_, value)| !matches!(value, Value::Native(_)))
+                {
+                    println!("{} = {}", name, value.repr());
+                }
+            }
+            "load" => match std::fs::read_to_string(argument) {
+                Ok(source) => self.execute(&source, argument),
+                Err(error) => eprintln!("cannot read {}: {}", argument, error),
+            },
+            "history" => {
+                for (index, entry) in self.history.iter().enumerate() {
+                    println!("{:>4}  {}", index + 1, entry);
+                }
+            }
+            _ => eprintln!("unknown command `:{}`; type :help for a list", name),
+        }
+
+        true
+    }
+
+    /// Evaluates a line of input. A lone expression has its value echoed;
+    /// anything else is run as statements, where a missing final `;` is
+    /// forgiven.
+    fn evaluate(&mut self, input: &str) {
+        if let Ok(expression) = parse_expression(tokenize(input)) {
+            self.engine.set_source(SOURCE_NAME, input);
+            match self.engine.evaluate(expression) {
+                Ok(value) => println!("{}", value.repr()),
+                Err(error) => self.report(&error.to_diagnostic(), input, SOURCE_NAME),
+            }
+            return;
+        }
+
+        let terminated = format!("{};", input.trim_end());
+        if parse(tokenize(input)).is_err() && parse(tokenize(&terminated)).is_ok() {
+            self.execute(&terminated, SOURCE_NAME);
+        } else {
+            self.execute(input, SOURCE_NAME);
+        }
+    }
+
+    fn execute(&mut self, source: &str, name: &str) {
+        let program = match parse(tokenize(source)) {
+            Ok(program) => program,
+            Err(diagnostic) => return self.report(&diagnostic, source, name),
+        };
+
+        self.engine.set_source(name, source);
+        if let Err(error) = self.engine.execute(program) {
+            self.report(&error.to_diagnostic(), source, name);
+        }
+    }
+
+    fn report(&self, diagnostic: &Diagnostic, source: &str, name: &str) {
+        eprint!("{}", diagnostic.render(source, name, self.color));
+    }
+
+    fn record(&mut self, input: &str) {
+        let entry = input.trim_end().to_string();
+        if self.history.last() == Some(&entry) {
+            return;
+        }
+
+        if let Some(path) = &self.history_file {
+            let saved = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", escape_history(&entry)));
+            // History is a convenience: the session goes on without it.
+            if let Err(error) = saved {
+                eprintln!("netl: cannot save history to {}: {}", path.display(), error);
+                self.history_file = None;
+            }
+        }
+        self.history.push(entry);
+    }
+}
+
+/// Reads one entry, prompting for more lines while brackets are unbalanced.
+/// An empty continuation line submits the entry as it is.
+fn read_entry(lines: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<Option<String>> {
+    let mut input = String::new();
+
+    loop {
+        print!("{}", if input.is_empty() { ">> " } else { ".. " });
+        io::stdout().flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(if input.is_empty() { None } else { Some(input) });
+        };
+
+        if !input.is_empty() && line.trim().is_empty() {
+            return Ok(Some(input));
+        }
+        input.push_str(&line);
+        input.push('\n');
+
+        if input.trim_start().starts_with(':') || open_brackets(&input) <= 0 {
+            return Ok(Some(input));
+        }
+    }
+}
+
+/// How many brackets, strings and comments the input leaves open; a positive
+/// count means it continues on the next line.
+fn open_brackets(input: &str) -> i32 {
+    tokenize(input)
+        .iter()
+        .map(|token| match &token.token {
+            Token::LeftBrace | Token::LeftParenthesis | Token::LeftBracket => 1,
+            Token::Invalid(error)
+                if matches!(
+                    **error,
+                    LexError::UnterminatedString | LexError::UnterminatedComment
+                ) =>
+            {
+                1
+            }
+            Token::RightBrace | Token::RightParenthesis | Token::RightBracket => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// History entries may span several lines; they are stored one per line.
+fn escape_history(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_history(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(ch),
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The entries `read_entry` splits `input` into.
+    fn entries(input: &str) -> Vec<String> {
+        let mut lines = input.lines().map(|line| Ok(line.to_string()));
+        std::iter::from_fn(|| read_entry(&mut lines).unwrap()).collect()
+    }
+
+    #[test]
+    fn entries_continue_while_brackets_are_open() {
+        assert_eq!(
+            entries("let a = 1\nfn f(x) {\n  return [x,\n 2];\n}\na"),
+            ["let a = 1\n", "fn f(x) {\n  return [x,\n 2];\n}\n", "a\n",]
+        );
+        assert_eq!(
+            entries("print \"a\n b\"\n/* c\n*/ 1"),
+            ["print \"a\n b\"\n", "/* c\n*/ 1\n"]
+        );
+    }
+
+    #[test]
+    fn an_empty_line_or_the_end_of_input_submits_an_open_entry() {
+        assert_eq!(entries("f(\n\n1"), ["f(\n", "1\n"]);
+        assert_eq!(entries("[1,"), ["[1,\n"]);
+    }
+
+    #[test]
+    fn commands_never_continue() {
+        assert_eq!(entries(":ast f(\n1"), [":ast f(\n", "1\n"]);
+    }
+
+    #[test]
+    fn history_entries_round_trip_through_one_line() {
+        let entry = "fn f() {\n    return \"\\\\n\";\n}";
+        let line = escape_history(entry);
+        assert!(!line.contains('\n'));
+        assert_eq!(unescape_history(&line), entry);
+    }
+}
@@ -0,0 +1,203 @@
+//! File-system access for scripts and the capabilities that limit it.
+//!
+//! Every [`Engine`](crate::Engine) provides `read_file`, `write_file`,
+//! `append_file`, `list_dir`, `exists` and `remove`, but a fresh engine may
+//! not touch any file with them: reading and writing are each allowed only
+//! inside the directories granted with [`Capabilities::allow_read`] and
+//! [`Capabilities::allow_write`]. Other paths fail with a runtime error, so
+//! untrusted scripts can be run with no access at all.
+//!
+//! Paths are resolved against the working directory, and symbolic links
+//! are followed before the check, so neither `..` nor a link can lead out
+//! of an allowed directory.
+//!
+//! `import` is checked too: besides the directories allowed for reading, a
+//! script may only import modules from its own directory and below, or
+//! from the working directory for code without a file. See
+//! [`Loader`](crate::loader::Loader).
+
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::logic::builtins::string;
+use crate::logic::value::{NativeFunction, Value};
+
+/// The directories scripts may read and write files in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+/// The kind of access a native needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    /// Writing, only below an allowed directory rather than to it.
+    Remove,
+}
+
+impl Capabilities {
+    /// Capabilities that allow no file access.
+    pub fn new() -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Allows reading files in `dir` and its subdirectories. Fails if `dir`
+    /// does not exist.
+    pub fn allow_read(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.read.push(fs::canonicalize(dir)?);
+        Ok(())
+    }
+
+    /// Allows creating, changing and removing files in `dir` and its
+    /// subdirectories, though not removing `dir` itself. Fails if `dir`
+    /// does not exist.
+    pub fn allow_write(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.write.push(fs::canonicalize(dir)?);
+        Ok(())
+    }
+
+    /// Whether reading `resolved`, a path returned by [`resolve`], is allowed.
+    pub(crate) fn can_read(&self, resolved: &Path) -> bool {
+        self.read.iter().any(|dir| resolved.starts_with(dir))
+    }
+
+    /// Resolves `path` and checks that `access` to it is allowed.
+    fn check(&self, path: &str, access: Access) -> Result<PathBuf, String> {
+        let allowed = match access {
+            Access::Read => &self.read,
+            Access::Write | Access::Remove => &self.write,
+        };
+        let verb = match access {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Remove => "remove",
+        };
+        let denied = || format!("permission denied: cannot {} `{}`", verb, path);
+
+        let resolved = resolve(Path::new(path)).ok_or_else(denied)?;
+        let inside = |dir: &PathBuf| {
+            resolved.starts_with(dir) && (access != Access::Remove || resolved != *dir)
+        };
+        if allowed.iter().any(inside) {
+            Ok(resolved)
+        } else {
+            Err(denied())
+        }
+    }
+}
+
+/// Makes `path` absolute with every symbolic link resolved. The part of the
+/// path that does not exist yet is kept as written, and must not contain
+/// `..` or a dangling link, which could not be resolved safely.
+pub(crate) fn resolve(path: &Path) -> Option<PathBuf> {
+    let path = std::env::current_dir().ok()?.join(path);
+    let mut existing = path.as_path();
+    let mut missing = Vec::new();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(mut resolved) => {
+                for component in missing.iter().rev() {
+                    resolved.push(component);
+                }
+                return Some(resolved);
+            }
+            Err(_) => {
+                if existing.symlink_metadata().is_ok() {
+                    return None;
+                }
+                match existing.components().next_back()? {
+                    Component::Normal(name) => missing.push(name),
+                    Component::CurDir => {}
+                    _ => return None,
+                }
+                existing = existing.parent()?;
+            }
+        }
+    }
+}
+
+/// The file-system natives, checking every access against `capabilities`.
+pub fn natives(capabilities: &Rc<RefCell<Capabilities>>) -> Vec<NativeFunction> {
+    vec![
+        native(capabilities, "read_file", 1, Access::Read, read_file),
+        native(capabilities, "write_file", 2, Access::Write, write_file),
+        native(capabilities, "append_file", 2, Access::Write, append_file),
+        native(capabilities, "list_dir", 1, Access::Read, list_dir),
+        native(capabilities, "exists", 1, Access::Read, exists),
+        native(capabilities, "remove", 1, Access::Remove, remove),
+    ]
+}
+
+/// Wraps `function`, whose first argument is a path and any others strings,
+/// to check `access` to that path before calling it with the resolved path
+/// and the remaining arguments.
+fn native(
+    capabilities: &Rc<RefCell<Capabilities>>,
+    name: &str,
+    arity: usize,
+    access: Access,
+    function: fn(&Path, &[&str]) -> io::Result<Value>,
+) -> NativeFunction {
+    let capabilities = capabilities.clone();
+    NativeFunction::new(name, Some(arity), move |arguments| {
+        let path = string(&arguments[0])?;
+        let rest = arguments[1..]
+            .iter()
+            .map(string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let resolved = capabilities.borrow().check(path, access)?;
+        function(&resolved, &rest).map_err(|error| format!("cannot access `{}`: {}", path, error))
+    })
+}
+
+/// `read_file(path)`: the contents of a text file.
+fn read_file(path: &Path, _: &[&str]) -> io::Result<Value> {
+    fs::read_to_string(path).map(Value::from)
+}
+
+/// `write_file(path, text)`: replaces the contents of a file, creating it if
+/// needed.
+fn write_file(path: &Path, arguments: &[&str]) -> io::Result<Value> {
+    fs::write(path, arguments[0])?;
+    Ok(Value::Nil)
+}
+
+/// `append_file(path, text)`: adds `text` to the end of a file, creating it
+/// if needed.
+fn append_file(path: &Path, arguments: &[&str]) -> io::Result<Value> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(arguments[0].as_bytes())?;
+    Ok(Value::Nil)
+}
+
+/// `list_dir(path)`: the names of the entries of a directory, sorted.
+fn list_dir(path: &Path, _: &[&str]) -> io::Result<Value> {
+    let mut names = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(Value::from(
+        names.into_iter().map(Value::from).collect::<Vec<_>>(),
+    ))
+}
+
+/// `exists(path)`: whether a file or directory exists.
+fn exists(path: &Path, _: &[&str]) -> io::Result<Value> {
+    Ok(Value::Bool(path.exists()))
+}
+
+/// `remove(path)`: deletes a file or an empty directory.
+fn remove(path: &Path, _: &[&str]) -> io::Result<Value> {
+    if path.is_dir() {
+        fs::remove_dir(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(Value::Nil)
+}
@@ -0,0 +1,72 @@
+//! Runs every script in `tests/fixtures/backends` on both backends, which
+//! must print the output in the `.expected` file next to it, followed by
+//! the error the script fails with, if any.
+
+mod common;
+
+use std::fs;
+
+use common::{fixture, run_file_on_both};
+
+#[test]
+fn backends_agree_on_every_fixture() {
+    let mut scripts: Vec<_> = fs::read_dir(fixture("backends"))
+        .expect("the fixtures exist")
+        .map(|entry| entry.expect("the fixtures are readable").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "nl"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    let prefix = format!("{}/", fixture("backends").display());
+    for script in scripts {
+        let outcome = run_file_on_both(&script);
+        let name = script.file_name().unwrap().to_string_lossy().into_owned();
+        let expected = fs::read_to_string(script.with_extension("expected"))
+            .unwrap_or_else(|_| panic!("{} has an expected output", name));
+
+        let mut actual: String = outcome
+            .output
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        if let Some(error) = &outcome.error {
+            actual += &error.replace(&prefix, "");
+        }
+        assert_eq!(actual, expected, "{} printed something else", name);
+        // Scripts named after an error fail; all others run to the end.
+        assert_eq!(
+            outcome.error.is_some(),
+            name.starts_with("error_"),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn functions_see_variables_declared_after_them() {
+    let outcome = run_file_on_both(&fixture("backends/scoping.nl"));
+    assert_eq!(
+        outcome.output,
+        ["5", "6", "1", "2", "3", "2", "1", "1", "2", "even", "true", "1", "2", "3"]
+    );
+}
+
+#[test]
+fn cyclic_collections_are_displayed_and_compared() {
+    let outcome = run_file_on_both(&fixture("backends/cyclic.nl"));
+    assert_eq!(
+        outcome.output,
+        [
+            "[[...]]",
+            r#"[[...], {"a": 1, "self": {...}}]"#,
+            r#"{"a": 1, "self": {...}}"#,
+            "true",
+            "true",
+            "true",
+            "false",
+            "[1, [...]]",
+        ]
+    );
+}
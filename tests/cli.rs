@@ -0,0 +1,180 @@
+//! Runs the `netl` binary to check its exit codes, which follow the BSD
+//! `sysexits.h` conventions.
+
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::fixture;
+
+/// Runs `netl` with `args` and `stdin`, returning its exit code and what it
+/// printed to stdout and stderr.
+fn netl(args: &[&str], stdin: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_netl"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("netl starts");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().expect("netl exits normally"),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn successful_scripts_exit_with_zero() {
+    assert_eq!(
+        netl(&["-e", "print args;", "a", "b"], ""),
+        (0, "[\"a\", \"b\"]\n".to_string(), String::new())
+    );
+    assert_eq!(
+        netl(&["run", "-"], "print 1 + 1;"),
+        (0, "2\n".to_string(), String::new())
+    );
+    let (code, stdout, _) = netl(
+        &[
+            "--backend",
+            "tree",
+            &fixture("backends/closures.nl").display().to_string(),
+        ],
+        "",
+    );
+    assert_eq!((code, stdout.lines().count()), (0, 10));
+}
+
+#[test]
+fn usage_errors_exit_with_64() {
+    let (code, stdout, stderr) = netl(&["--bogus"], "");
+    assert_eq!((code, stdout.as_str()), (64, ""));
+    assert!(
+        stderr.starts_with("netl: unknown option `--bogus`\n\nUsage:"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn syntax_errors_exit_with_65() {
+    let (code, stdout, stderr) = netl(&["-e", "print 1"], "");
+    assert_eq!((code, stdout.as_str()), (65, ""));
+    assert!(
+        stderr.starts_with("error[E0102]: expected `;`"),
+        "{}",
+        stderr
+    );
+
+    let (code, _, stderr) = netl(&["check"], "print x;");
+    assert_eq!(code, 65);
+    assert!(
+        stderr.starts_with("error[E0201]: undefined variable `x`"),
+        "{}",
+        stderr
+    );
+    let (code, _, stderr) = netl(&["check", "-"], "fn f() { let y = 2; }");
+    assert_eq!(code, 0);
+    assert!(
+        stderr.starts_with("warning: unused variable `y`\n --> <stdin>:1:10"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.ends_with("not needed\n"), "{:?}", stderr);
+}
+
+#[test]
+fn missing_input_exits_with_66() {
+    let (code, stdout, stderr) = netl(&["no/such/file.nl"], "");
+    assert_eq!((code, stdout.as_str()), (66, ""));
+    assert!(
+        stderr.starts_with("netl: cannot read no/such/file.nl: "),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn runtime_errors_exit_with_70_after_earlier_output() {
+    let (code, stdout, stderr) = netl(&["-e", "print 1; print 1 / 0;"], "");
+    assert_eq!((code, stdout.as_str()), (70, "1\n"));
+    assert!(
+        stderr.starts_with("error[E0203]: division by zero"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn repl_echoes_expressions_and_keeps_globals() {
+    let input = "let x = 1\nfn add(a,\n       b) {\n    return a + b;\n}\nadd(x, 2)\n\"s\"\nprint 1 / 0;\nx\n";
+    let (code, stdout, stderr) = netl(&["repl", "--backend", "tree"], input);
+    assert_eq!(code, 0);
+    assert_eq!(
+        stdout.lines().skip(1).collect::<Vec<_>>(),
+        [">> >> .. .. .. >> 3", ">> \"s\"", ">> >> 1", ">> "]
+    );
+    assert!(
+        stderr.starts_with("error[E0203]: division by zero\n --> <repl>:1:7"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn repl_errors_show_the_entry_or_file_the_failing_code_came_from() {
+    let dir = std::env::temp_dir().join(format!("netl-repl-load-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("lib.nl");
+    std::fs::write(&script, "fn g() {\n    return 1 / 0;\n}\n").unwrap();
+
+    let input = format!(
+        "fn f() {{\n    let x = 1;\n    return x - \"a\";\n}}\nf()\n:load {}\ng()\n",
+        script.display()
+    );
+    for backend in ["tree", "vm"] {
+        let (code, _, stderr) = netl(&["repl", "--backend", backend], &input);
+        assert_eq!(code, 0);
+        let entry = "error[E0202]: unsupported operand types for `-`: int and string\n \
+             --> <repl>:3:12\n  |\n3 |     return x - \"a\";\n  |            ^^^^^^^ int and string\n";
+        assert!(stderr.starts_with(entry), "{}", stderr);
+        let file = format!(
+            "error[E0203]: division by zero\n --> {}:2:12\n  |\n2 |     return 1 / 0;\n",
+            script.display()
+        );
+        assert!(stderr.contains(&file), "{}", stderr);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn repl_commands() {
+    let input = ":vars\nlet x = 1\n:vars\n:tokens 1 +\n:ast 1\n:history\n:nope\n:quit\nprint 2;\n";
+    let (code, stdout, stderr) = netl(&["repl"], input);
+    assert_eq!(code, 0);
+    let stdout: Vec<_> = stdout.lines().skip(1).collect();
+    assert_eq!(
+        stdout[..4],
+        [
+            ">> args = []",
+            ">> >> args = []",
+            "x = 1",
+            ">> 1:1\tNumber(1)"
+        ]
+    );
+    assert!(stdout.contains(&">> Number("), "{:?}", stdout);
+    let history = stdout
+        .iter()
+        .position(|line| line.ends_with("   1  :vars"))
+        .expect("the history is shown");
+    assert_eq!(stdout[history + 5..], ["   6  :history", ">> >> "]);
+    assert_eq!(stderr, "unknown command `:nope`; type :help for a list\n");
+}
@@ -0,0 +1,74 @@
+//! Helpers shared by the integration tests.
+
+// Each test file uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use netl::{Backend, Engine};
+
+pub const BACKENDS: [Backend; 2] = [Backend::TreeWalker, Backend::Vm];
+
+/// What running a script produced: the printed lines, followed by the
+/// rendered diagnostic if the script failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub output: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Runs `source` as the main script on `backend`. Imports are resolved
+/// against `path` when one is given.
+pub fn run(backend: Backend, source: &str, path: Option<&Path>) -> Outcome {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut engine = Engine::with_backend(backend);
+    let lines = output.clone();
+    engine.on_print(move |line| lines.borrow_mut().push(line.to_string()));
+    if let Some(path) = path {
+        engine.set_script_path(path);
+    }
+
+    let name = path.map_or_else(|| "<test>".to_string(), |path| path.display().to_string());
+    let error = match netl::parse(source) {
+        Ok(program) => engine
+            .execute(program)
+            .err()
+            .map(|error| error.to_diagnostic()),
+        Err(diagnostic) => Some(diagnostic),
+    };
+    let error = error.map(|diagnostic| diagnostic.render(source, &name, false));
+
+    let output = output.borrow().clone();
+    Outcome { output, error }
+}
+
+/// Runs the script at `path` on `backend`.
+pub fn run_file(backend: Backend, path: &Path) -> Outcome {
+    let source = std::fs::read_to_string(path).expect("the fixture exists");
+    run(backend, &source, Some(path))
+}
+
+/// Runs `source` on both backends, checking that they agree, and returns
+/// what they produced.
+pub fn run_on_both(source: &str) -> Outcome {
+    let [tree, vm] = BACKENDS.map(|backend| run(backend, source, None));
+    assert_eq!(tree, vm, "the backends disagree on {:?}", source);
+    tree
+}
+
+/// Runs `path` on both backends, checking that they agree, and returns what
+/// they produced.
+pub fn run_file_on_both(path: &Path) -> Outcome {
+    let [tree, vm] = BACKENDS.map(|backend| run_file(backend, path));
+    assert_eq!(tree, vm, "the backends disagree on {}", path.display());
+    tree
+}
+
+/// The path of a file under `tests/fixtures`.
+pub fn fixture(path: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path)
+}
@@ -0,0 +1,225 @@
+//! The embedding API, on both backends.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use common::BACKENDS;
+use netl::{Backend, Engine, Error, Value};
+
+/// An engine on `backend` whose `print`s are discarded.
+fn quiet(backend: Backend) -> Engine {
+    let mut engine = Engine::with_backend(backend);
+    engine.on_print(|_| {});
+    engine
+}
+
+#[test]
+fn eval_returns_the_value_of_a_lone_expression() {
+    for backend in BACKENDS {
+        let mut engine = quiet(backend);
+        assert_eq!(engine.backend(), backend);
+        assert_eq!(engine.eval("1 + 2 * 3").unwrap(), Value::Int(7));
+        assert_eq!(engine.eval("0..2").unwrap().to_string(), "[0, 1]");
+        assert_eq!(engine.eval("[1, \"a\"]").unwrap().to_string(), "[1, \"a\"]");
+        assert_eq!(engine.eval("let x = 1; print x;").unwrap(), Value::Nil);
+        assert_eq!(engine.eval("x").unwrap(), Value::Int(1));
+    }
+}
+
+#[test]
+fn eval_reports_parse_and_runtime_errors() {
+    for backend in BACKENDS {
+        let mut engine = quiet(backend);
+        let Err(Error::Parse(diagnostic)) = engine.eval("let = 1;") else {
+            panic!("a syntax error is a parse error");
+        };
+        assert_eq!(diagnostic.code, Some("E0103"));
+
+        let Err(Error::Runtime(error)) = engine.eval("1 / 0") else {
+            panic!("dividing by zero is a runtime error");
+        };
+        assert_eq!(error.to_diagnostic().code, Some("E0203"));
+        assert_eq!(
+            engine
+                .eval("let y = 2; y / 0;")
+                .unwrap_err()
+                .to_diagnostic()
+                .code,
+            Some("E0203")
+        );
+    }
+}
+
+#[test]
+fn errors_in_earlier_evaluations_render_against_their_source() {
+    for backend in BACKENDS {
+        let mut engine = quiet(backend);
+        engine.eval("fn f() {\n    return [1][5];\n}").unwrap();
+        let error = engine.eval("let x = 1;\nf();").unwrap_err();
+        assert_eq!(
+            error.to_diagnostic().render("let x = 1;\nf();", "<host>", false),
+            "error[E0215]: index 5 is out of bounds\n \
+             --> <eval>:2:12\n  |\n2 |     return [1][5];\n  |            ^^^^^^ the length is 1\n  \
+             |\n  = note: in <eval>, entered at 2:1\n"
+        );
+    }
+}
+
+#[test]
+fn globals_persist_across_evaluations() {
+    for backend in BACKENDS {
+        let mut engine = quiet(backend);
+        engine.set_global("base", Value::Int(40));
+        engine
+            .eval("let answer = base + 2; fn twice(x) { return x * 2; }")
+            .unwrap();
+        assert_eq!(engine.get_global("answer"), Some(Value::Int(42)));
+        assert_eq!(engine.eval("twice(answer)").unwrap(), Value::Int(84));
+        assert_eq!(engine.get_global("missing"), None);
+
+        engine.set_global("base", Value::from("replaced"));
+        assert_eq!(engine.eval("base").unwrap(), Value::from("replaced"));
+
+        let names: Vec<_> = engine
+            .globals()
+            .into_iter()
+            .filter(|(_, value)| !matches!(value, Value::Native(_)))
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["answer", "base", "twice"]);
+    }
+}
+
+#[test]
+fn registered_functions_are_callable_from_scripts() {
+    for backend in BACKENDS {
+        let mut engine = quiet(backend);
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        engine.register_fn("double", Some(1), move |args| {
+            counter.set(counter.get() + 1);
+            match &args[0] {
+                Value::Int(n) => Ok(Value::Int(n * 2)),
+                other => Err(format!("expected an int, found {}", other.type_name())),
+            }
+        });
+        engine.register_fn("count", None, |args| Ok(Value::Int(args.len() as i64)));
+
+        assert_eq!(engine.eval("double(double(3))").unwrap(), Value::Int(12));
+        assert_eq!(calls.get(), 2);
+        assert_eq!(
+            engine.eval("count() + count(1, 2, 3)").unwrap(),
+            Value::Int(3)
+        );
+        engine.eval("let f = double; let r = f(5);").unwrap();
+        assert_eq!(engine.get_global("r"), Some(Value::Int(10)));
+
+        let error = engine.eval("double(\"x\")").unwrap_err().to_diagnostic();
+        assert_eq!(error.message, "expected an int, found string");
+        let error = engine.eval("double(1, 2)").unwrap_err().to_diagnostic();
+        assert_eq!(error.code, Some("E0207"));
+        assert_eq!(calls.get(), 4);
+    }
+}
+
+#[test]
+fn tokenize_and_parse_are_exposed() {
+    let tokens = netl::tokenize("print 1;");
+    assert_eq!(tokens.len(), 4);
+    let program = netl::parse("print 1;").unwrap();
+    let mut engine = quiet(Backend::default());
+    engine.execute(program).unwrap();
+    assert!(netl::parse("print").is_err());
+}
+
+/// A `Write` whose contents stay readable after the engine takes it.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn on_print_receives_each_printed_line() {
+    for backend in BACKENDS {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::with_backend(backend);
+        let sink = lines.clone();
+        engine.on_print(move |line| sink.borrow_mut().push(line.to_string()));
+
+        engine
+            .eval(
+                "print 1; print \"two\nlines\"; print 0..2; print [1, 2]; print [nil]; print \"\";",
+            )
+            .unwrap();
+        engine
+            .eval("fn f() { print \"in f\"; return 1 / 0; } f();")
+            .unwrap_err();
+        assert_eq!(
+            *lines.borrow(),
+            ["1", "two\nlines", "[0, 1]", "[1, 2]", "[nil]", "", "in f"]
+        );
+    }
+}
+
+#[test]
+fn set_output_receives_the_printed_text() {
+    for backend in BACKENDS {
+        let buffer = Buffer::default();
+        let mut engine = Engine::with_backend(backend);
+        engine.set_output(buffer.clone());
+        engine
+            .eval("for i in 0..3 { print i; } print \"done\"; print \"${1 + 1} done\";")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.0.borrow().clone()).unwrap(),
+            "0\n1\n2\ndone\n2 done\n"
+        );
+
+        // A later sink replaces the earlier one.
+        let replacement = Buffer::default();
+        engine.set_output(replacement.clone());
+        engine.eval("print 3;").unwrap();
+        assert_eq!(buffer.0.borrow().len(), "0\n1\n2\ndone\n2 done\n".len());
+        assert_eq!(*replacement.0.borrow(), b"3\n");
+    }
+}
+
+/// A `Write` that refuses everything.
+struct Closed;
+
+impl Write for Closed {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failing_to_write_output_is_a_runtime_error() {
+    for backend in BACKENDS {
+        let mut engine = Engine::with_backend(backend);
+        engine.set_output(Closed);
+        let error = engine
+            .eval("let x = 1;\nprint x;")
+            .unwrap_err()
+            .to_diagnostic();
+        assert_eq!(error.code, Some("E0214"));
+        assert_eq!(error.message, "cannot write output: closed");
+        assert_eq!(error.primary_span().map(|span| span.line), Some(2));
+    }
+}
@@ -0,0 +1,20 @@
+print 1 + 2 * 3 - 4 / 2;
+print 7 % 3;
+print -7 / 2;
+print 1.5 + 2;
+print 10 / 4.0;
+print "a" + 1 + 2;
+print "a" + 1 + 2.5;
+print 1 < 2;
+print 1 < 2 && 2 <= 2 || false;
+print !(1 == 1);
+print !(1 == 1.0);
+print nil == nil;
+print [1, 2] == [1, 2];
+print {"a": 1, "b": 2} == {"b": 2, "a": 1};
+print 2 in [1, 2, 3];
+print "ell" in "hello";
+print 0 || "fallback";
+print 1 && nil;
+print -9223372036854775808;
+print -0x8000_0000_0000_0000 + 1;
@@ -0,0 +1,22 @@
+print type(1);
+print type(1.5);
+print type("a");
+print type(nil);
+print type([]);
+print type({});
+print type(len);
+print str([1, "a"]);
+print int("42");
+print float("2.5");
+print abs(-5);
+print min(3, 1, 2);
+print max([4, 2, 8]);
+print pow(2, 10);
+print sqrt(16);
+print upper("hi");
+print trim("  x  ");
+print split("a,b,,c", ",");
+print join([1, "b", true], "-");
+print replace("aaa", "a", "bb");
+print starts_with("netl", "ne");
+print contains([1, 2], 3);
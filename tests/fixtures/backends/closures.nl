@@ -0,0 +1,51 @@
+fn counter() {
+    let count = 0;
+    fn increment() {
+        count = count + 1;
+        return count;
+    }
+    return increment;
+}
+let a = counter();
+let b = counter();
+print a();
+print a();
+print b();
+let add0 = 0;
+let add1 = 0;
+let add2 = 0;
+for i in 0..3 {
+    fn add(x) {
+        return x + i;
+    }
+    if i == 0 {
+        add0 = add;
+    } elif i == 1 {
+        add1 = add;
+    } else {
+        add2 = add;
+    }
+}
+print add0(10);
+print add1(10);
+print add2(10);
+fn adders() {
+    let result = [];
+    for i in 0..3 {
+        fn add(x) {
+            return x + i;
+        }
+        push(result, add);
+    }
+    return result;
+}
+for add in adders() {
+    print add(10);
+}
+fn fib(n) {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+print fib(15);
@@ -0,0 +1,38 @@
+let xs = 0..5;
+print xs;
+let total = 0;
+for x in xs {
+    total = total + x;
+}
+print total;
+xs = [3, 1, 2];
+push(xs, 4);
+xs[0] = 10;
+print xs;
+print len(xs);
+print xs[3];
+let ys = xs;
+push(ys, 5);
+print xs;
+let m = {"one": 1, "two": 2};
+m["three"] = 3;
+m["one"] = 100;
+print m;
+print keys(m);
+print values(m);
+print "two" in m;
+print delete(m, "two");
+print pop(xs);
+print m;
+print 0..4;
+total = 0;
+for x in xs {
+    total = total + x;
+}
+print total;
+for key in m {
+    print key;
+}
+for c in "hey" {
+    print c;
+}
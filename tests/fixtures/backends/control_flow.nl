@@ -0,0 +1,44 @@
+let n = 0;
+while n < 10 {
+    n = n + 1;
+    if n == 2 {
+        continue;
+    }
+    if n > 4 {
+        break;
+    }
+    print n;
+    print "n = ${n}";
+}
+for i in 0..10 {
+    if i % 2 == 0 {
+        continue;
+    } elif i > 6 {
+        break;
+    } else {
+        let half = i / 2;
+        print half;
+    }
+}
+fn classify(x) {
+    if x < 0 {
+        return "negative";
+    } elif x == 0 {
+        return "zero";
+    }
+    return "positive";
+}
+print classify(-1);
+print classify(0);
+print classify(5);
+fn nothing() {
+}
+print nothing();
+let tries = 0;
+while true {
+    tries = tries + 1;
+    if tries == 3 {
+        break;
+    }
+}
+print tries;
@@ -0,0 +1,19 @@
+let xs = [];
+push(xs, xs);
+print xs;
+let m = {"a": 1};
+m["self"] = m;
+push(xs, m);
+print xs;
+print m;
+let ys = [];
+push(ys, ys);
+print xs == xs;
+print ys == [ys];
+let a = [1];
+push(a, a);
+let b = [1];
+push(b, b);
+print a == b;
+print a < b;
+print "${a}";
@@ -0,0 +1 @@
+undeclared = 1;
@@ -0,0 +1,4 @@
+let x = 1;
+print x;
+print 1 / 0;
+print "unreachable";
@@ -0,0 +1 @@
+print int("nope");
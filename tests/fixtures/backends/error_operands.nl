@@ -0,0 +1 @@
+print "a" - 1;
@@ -0,0 +1 @@
+print 2147483647 * 2147483647 * 4;
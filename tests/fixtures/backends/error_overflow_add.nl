@@ -0,0 +1 @@
+print 9223372036854775807 + 1;
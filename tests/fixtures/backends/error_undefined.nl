@@ -0,0 +1,4 @@
+fn f() {
+    return missing;
+}
+print f();
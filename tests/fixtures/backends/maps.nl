@@ -0,0 +1,53 @@
+// Map literals keep their entries in insertion order.
+let m = {"b": 1, "a": 2, 3: "three"};
+print m;
+print len(m);
+print m["a"];
+print m[3];
+
+// Assigning to an existing key keeps its position; a new key goes last.
+m["b"] = 10;
+m["c"] = 30;
+print m;
+print keys(m);
+print values(m);
+
+// A repeated key in a literal keeps the first position and the last value.
+print {"x": 1, "y": 2, "x": 3};
+
+print "a" in m;
+print "z" in m;
+print 3 in m;
+print "3" in m;
+
+// Deleting returns the value and keeps the order of the rest.
+print delete(m, "a");
+print delete(m, "missing");
+print m;
+m["a"] = 20;
+print keys(m);
+
+let order = [];
+for key in m {
+    push(order, key);
+}
+print order;
+
+// Maps are shared, not copied.
+let alias = m;
+alias["d"] = 40;
+print len(m);
+
+let nested = {"inner": {"n": 1}};
+nested["inner"]["n"] = 2;
+print nested;
+print {} == {};
+print {"a": 1, "b": 2} == {"b": 2, "a": 1};
+print {"a": 1} == {"a": 2};
+print {"s": "q\"uote"};
+
+// Bools are keys too, distinct from the ints and strings they resemble.
+let flags = {true: "yes", 1: "one", "true": "string"};
+print flags[true];
+print flags;
+print false in flags;
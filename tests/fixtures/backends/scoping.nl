@@ -0,0 +1,75 @@
+// Functions see variables declared after them in the same block, since
+// they usually run later.
+fn outer() {
+    fn g() {
+        return y;
+    }
+    let y = 5;
+    return g();
+}
+print outer();
+if 1 < 2 {
+    fn h() {
+        return z;
+    }
+    let z = 6;
+    print h();
+}
+// Until its declaration, a name still refers to the enclosing variable.
+let x = 1;
+fn shadow() {
+    print x;
+    let x = 2;
+    print x;
+    if 1 < 2 {
+        let x = x + 1;
+        print x;
+    }
+    print x;
+}
+shadow();
+print x;
+// So do functions called before the declaration has run.
+if 1 < 2 {
+    fn f() {
+        return x;
+    }
+    print f();
+    let x = 2;
+    print f();
+}
+fn mutual() {
+    fn even(n) {
+        if n == 0 {
+            return "even";
+        }
+        return odd(n - 1);
+    }
+    fn odd(n) {
+        if n == 0 {
+            return "odd";
+        }
+        return even(n - 1);
+    }
+    return even(10);
+}
+print mutual();
+fn parity() {
+    fn even(n) {
+        return n == 0 || odd(n - 1);
+    }
+    fn odd(n) {
+        return n != 0 && even(n - 1);
+    }
+    return even(10) && !odd(10);
+}
+print parity();
+let i = 0;
+while i < 3 {
+    i = i + 1;
+    let seen = i;
+    fn show() {
+        return seen;
+    }
+    print show();
+}
@@ -0,0 +1,73 @@
+// Each value in `if`, `!`, `&&`, `||` and `while`.
+fn f() {}
+let values = [false, nil, 0, 0.0, -0.0, "", [], {}, true, 1, -1, 0.5, "0", " ", [0], {"": nil}, f, len];
+for value in values {
+    let branch = "false";
+    if value {
+        branch = "true";
+    }
+    print [value, branch, !value, value && "and", value || "or"];
+}
+
+let count = 3;
+while count {
+    count = count - 1;
+}
+print count;
+
+// Comparisons and `!` give bools.
+print 1 < 2;
+print 2 == 2.0;
+print !nil;
+print [1 == 1, 1 != 1, "a" <= "b"];
+
+// `&&` and `||` give an operand and only evaluate the right one if needed.
+fn loud(value) {
+    print "evaluated ${value}";
+    return value;
+}
+print 0 && loud(1);
+print 2 && loud(3);
+print "" || loud("right");
+print "left" || loud("never");
+
+// The first true condition of an `elif` chain wins, and later conditions
+// are not evaluated.
+fn describe(n) {
+    if n < 0 {
+        return "negative";
+    } elif loud(n == 0) {
+        return "zero";
+    } elif n % 2 == 0 {
+        return "even";
+    } elif n < 10 {
+        return "small odd";
+    } else {
+        return "large odd";
+    }
+}
+for n in [-1, 0, 4, 7, 11] {
+    print describe(n);
+}
+
+// Without an `else`, a chain may run no branch at all.
+let ran = "none";
+if nil {
+    ran = "if";
+} elif [] {
+    ran = "first elif";
+} elif {} {
+    ran = "second elif";
+}
+print ran;
+if 0 {
+    print "no";
+} elif 0.0 {
+    print "no";
+} else {
+    if "x" {
+        print "nested if";
+    } elif true {
+        print "no";
+    }
+}
@@ -0,0 +1,17 @@
+// Leading comment.
+let total = 0; // trailing comment
+
+/* block comment */
+fn count(n) {
+    // inside a function
+    let i = 0;
+    while i < n {
+        i = i + 1; // counted
+    }
+    return i;
+    // before the closing brace
+}
+
+total = count(3);
+print total;
+// at the end
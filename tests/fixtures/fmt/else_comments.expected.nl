@@ -0,0 +1,13 @@
+let x = true;
+if x {} // c1
+else {}
+
+if x {
+    print 1;
+} /* c2 */ elif false {
+    print 2;
+} // c3
+// c4
+else {
+    print 3; // c5
+} // c6
@@ -0,0 +1,11 @@
+// Spacing, indentation and parentheses are normalised.
+let   a=1+2*3 ;
+let b = (1 + 2) * 3;
+let c = ((a));
+fn add(x,y){return x+y;}
+let list = [ 1,2 , 3 ];
+let map = { "a":1, "b" : [a,b] };
+
+
+while a<10{a=a+1;}
+print add( a , b ) ;print c; print list[0x1]; print map["b"][1_0 - 9];
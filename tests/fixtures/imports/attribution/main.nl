@@ -0,0 +1,3 @@
+import util;
+print "before";
+print util.divide(1, 0);
@@ -0,0 +1,4 @@
+// Helpers.
+export fn divide(a, b) {
+    return a / b;
+}
@@ -0,0 +1,6 @@
+print "loading counter";
+let count = 0;
+export fn next() {
+    count = count + 1;
+    return count;
+}
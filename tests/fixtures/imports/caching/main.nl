@@ -0,0 +1,6 @@
+import counter;
+import "counter.nl" as again;
+import user;
+print counter.next();
+print again.next();
+print user.next_twice();
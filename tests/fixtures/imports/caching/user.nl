@@ -0,0 +1,5 @@
+import counter;
+export fn next_twice() {
+    counter.next();
+    return counter.next();
+}
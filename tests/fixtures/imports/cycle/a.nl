@@ -0,0 +1,2 @@
+import b;
+export let x = 1;
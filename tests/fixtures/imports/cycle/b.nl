@@ -0,0 +1,2 @@
+import a;
+export let y = 2;
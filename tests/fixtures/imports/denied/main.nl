@@ -0,0 +1,2 @@
+import "../outside.nl" as outside;
+print outside.secret;
@@ -0,0 +1,5 @@
+let hidden = "private";
+export let shown = "public";
+export fn twice(n) {
+    return n * 2;
+}
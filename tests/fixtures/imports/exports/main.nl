@@ -0,0 +1,4 @@
+import lib;
+print lib.shown;
+print lib.twice(2);
+print lib.hidden;
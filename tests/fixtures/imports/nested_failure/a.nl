@@ -0,0 +1,5 @@
+fn h() {
+    import b;
+    return 1;
+}
+export let x = h();
@@ -0,0 +1,5 @@
+fn f() {
+    import a;
+    return a.x;
+}
+print f();
@@ -0,0 +1 @@
+export let secret = "hunter2";
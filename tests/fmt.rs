@@ -0,0 +1,71 @@
+mod common;
+
+use common::{fixture, run, BACKENDS};
+use netl::logic::formatter::format;
+
+/// Each `name.nl` under `tests/fixtures/fmt` formats to `name.expected.nl`,
+/// which formats to itself and runs the same as the original.
+#[test]
+fn fixtures_format_as_expected() {
+    let mut inputs: Vec<_> = std::fs::read_dir(fixture("fmt"))
+        .expect("the fixture directory exists")
+        .map(|entry| entry.expect("the fixture is readable").path())
+        .filter(|path| !path.to_string_lossy().ends_with(".expected.nl"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    for input in inputs {
+        let source = std::fs::read_to_string(&input).expect("the fixture exists");
+        let expected = std::fs::read_to_string(input.with_extension("expected.nl"))
+            .expect("the fixture has an expected layout");
+
+        let formatted = format(&source)
+            .unwrap_or_else(|error| panic!("{} failed: {:?}", input.display(), error));
+        assert_eq!(
+            formatted,
+            expected,
+            "{} formatted differently",
+            input.display()
+        );
+        assert_eq!(
+            format(&formatted).ok().as_ref(),
+            Some(&formatted),
+            "{} is not idempotent",
+            input.display()
+        );
+
+        for backend in BACKENDS {
+            assert_eq!(
+                run(backend, &source, None),
+                run(backend, &formatted, None),
+                "{} changed meaning",
+                input.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn comment_after_a_then_block_stays_before_else() {
+    let formatted = format("if x { } // c1\nelse { }\n").unwrap();
+    assert_eq!(formatted, "if x {} // c1\nelse {}\n");
+}
+
+#[test]
+fn comments_between_branches_keep_their_order() {
+    let source = "if a { } /* one */ elif b { }\n// two\n// three\nelse { }\n";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        formatted,
+        "if a {} /* one */ elif b {} // two\n// three\nelse {}\n"
+    );
+}
+
+#[test]
+fn the_smallest_int_keeps_its_minus_next_to_it() {
+    assert_eq!(
+        format("print - 9223372036854775808;\n").unwrap(),
+        "print -9223372036854775808;\n"
+    );
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::{fixture, run_file_on_both};
+use netl::loader::MAX_IMPORT_DEPTH;
+
+/// Writes `main.nl` importing `m1.nl`, which imports `m2.nl` and so on up
+/// to `m<length>.nl`, into a fresh scratch directory named after `name`.
+fn import_chain(name: &str, length: usize) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("netl-imports-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.nl"), "import m1;\nprint m1.depth;\n").unwrap();
+    for index in 1..length {
+        let source = format!(
+            "import m{next};\nexport let depth = m{next}.depth;\n",
+            next = index + 1
+        );
+        fs::write(dir.join(format!("m{}.nl", index)), source).unwrap();
+    }
+    fs::write(
+        dir.join(format!("m{}.nl", length)),
+        format!("export let depth = {};\n", length),
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn failing_import_inside_nested_import_is_reported() {
+    let outcome = run_file_on_both(&fixture("imports/nested_failure/main.nl"));
+    assert!(outcome.output.is_empty());
+    let error = outcome.error.expect("the script fails");
+    assert!(
+        error.contains("error[E0203]: division by zero"),
+        "{}",
+        error
+    );
+    assert!(error.contains("b.nl"), "{}", error);
+}
+
+#[test]
+fn modules_outside_the_script_directory_are_denied() {
+    let outcome = run_file_on_both(&fixture("imports/denied/main.nl"));
+    assert!(outcome.output.is_empty());
+    let error = outcome.error.expect("the import is denied");
+    assert!(
+        error.contains("error[E0225]: permission denied: cannot import"),
+        "{}",
+        error
+    );
+    assert!(!error.contains("hunter2"), "{}", error);
+}
+
+#[test]
+fn modules_run_once_and_are_shared() {
+    let outcome = run_file_on_both(&fixture("imports/caching/main.nl"));
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.output, ["loading counter", "1", "2", "4"]);
+}
+
+#[test]
+fn import_cycles_are_reported() {
+    let outcome = run_file_on_both(&fixture("imports/cycle/main.nl"));
+    assert!(outcome.output.is_empty());
+    let error = outcome.error.expect("the cycle is an error");
+    assert!(error.contains("error[E0221]: import cycle:"), "{}", error);
+    assert!(error.contains("a.nl -> "), "{}", error);
+    assert!(error.contains("cycle/b.nl:1:1"), "{}", error);
+}
+
+#[test]
+fn only_exported_declarations_are_visible() {
+    let outcome = run_file_on_both(&fixture("imports/exports/main.nl"));
+    assert_eq!(outcome.output, ["public", "4"]);
+    let error = outcome.error.expect("the private name is an error");
+    assert!(
+        error.contains("error[E0222]: module `lib` has no export `hidden`"),
+        "{}",
+        error
+    );
+    assert!(!error.contains("private"), "{}", error);
+}
+
+#[test]
+fn errors_in_module_functions_point_at_the_module() {
+    let outcome = run_file_on_both(&fixture("imports/attribution/main.nl"));
+    assert_eq!(outcome.output, ["before"]);
+    let error = outcome.error.expect("the division fails");
+    assert!(
+        error.contains("error[E0203]: division by zero"),
+        "{}",
+        error
+    );
+    assert!(error.contains("attribution/util.nl:3:12"), "{}", error);
+    assert!(error.contains("return a / b;"), "{}", error);
+}
+
+#[test]
+fn import_chains_are_limited_in_depth() {
+    let allowed = import_chain("allowed", MAX_IMPORT_DEPTH - 1);
+    let outcome = run_file_on_both(&allowed.join("main.nl"));
+    assert_eq!(outcome.error, None);
+    assert_eq!(outcome.output, [(MAX_IMPORT_DEPTH - 1).to_string()]);
+
+    let too_deep = import_chain("too-deep", MAX_IMPORT_DEPTH);
+    let outcome = run_file_on_both(&too_deep.join("main.nl"));
+    let error = outcome.error.expect("the chain is too long");
+    let message = format!(
+        "error[E0224]: imports nest more than {} files deep",
+        MAX_IMPORT_DEPTH
+    );
+    assert!(error.starts_with(&message), "{}", error);
+    assert!(
+        error.contains(&format!("m{}.nl:1:1", MAX_IMPORT_DEPTH - 1)),
+        "{}",
+        error
+    );
+
+    let _ = fs::remove_dir_all(allowed);
+    let _ = fs::remove_dir_all(too_deep);
+}
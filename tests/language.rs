@@ -0,0 +1,248 @@
+//! Behaviour of the core language, on both backends.
+
+mod common;
+
+use common::run_on_both;
+
+/// The lines `source` prints, which must run without an error.
+fn output(source: &str) -> Vec<String> {
+    let outcome = run_on_both(source);
+    assert_eq!(outcome.error, None, "{}", source);
+    outcome.output
+}
+
+/// The rendered error `source` fails with.
+fn error(source: &str) -> String {
+    run_on_both(source).error.expect("the script fails")
+}
+
+#[test]
+fn operators_bind_by_precedence() {
+    let source = "
+        print 1 + 2 * 3 == 7;
+        print (1 + 2) * 3;
+        print 10 - 4 - 3;
+        print 7 % 4 * 2;
+        print 1 + 1 < 3 == true;
+    ";
+    assert_eq!(output(source), ["true", "9", "3", "6", "true"]);
+}
+
+#[test]
+fn comparisons() {
+    let source = "
+        print 1 == 1;
+        print 1 != 1;
+        print 1 < 2;
+        print 2 <= 2;
+        print 3 > 4;
+        print 3 >= 4;
+    ";
+    assert_eq!(
+        output(source),
+        ["true", "false", "true", "true", "false", "false"]
+    );
+}
+
+#[test]
+fn arithmetic_keeps_ints_and_floats_apart_and_checks_its_results() {
+    let source = "
+        print 7 / 2;
+        print -7 % 3;
+        print 7.5 % 2.0;
+        print 1.5 * 2.0;
+        print 1 / 2.0;
+        print 0.5 < 0.25;
+        print 2.0 >= 2;
+        print 3 != 3.0;
+    ";
+    assert_eq!(
+        output(source),
+        ["3", "-1", "1.5", "3.0", "0.5", "false", "true", "false"]
+    );
+
+    for source in ["1 / 0", "1 % 0", "1.0 / 0.0", "1.5 % 0.0", "1 / 0.0"] {
+        let message = error(&format!("print {};", source));
+        assert!(
+            message.starts_with("error[E0203]: division by zero"),
+            "{}",
+            message
+        );
+    }
+    for source in [
+        "9223372036854775807 + 1",
+        "-9223372036854775807 - 2",
+        "4611686018427387904 * 2",
+        "-9223372036854775808 / -1",
+        "-9223372036854775808 % -1",
+    ] {
+        let message = error(&format!("print {};", source));
+        assert!(
+            message.starts_with("error[E0204]: integer overflow"),
+            "{}",
+            message
+        );
+    }
+}
+
+#[test]
+fn unary_operators() {
+    let source = "
+        print -2 * 3;
+        print --1;
+        print !(1 == 2);
+        print -(1 + 2);
+    ";
+    assert_eq!(output(source), ["-6", "1", "true", "-3"]);
+    assert!(
+        error("print -\"a\";").contains("error[E0211]: unsupported operand type for `-`: string")
+    );
+}
+
+#[test]
+fn while_loops_run_until_their_condition_fails() {
+    let source = "
+        let i = 0;
+        while i < 3 {
+            print i;
+            i = i + 1;
+        }
+        print i;
+    ";
+    assert_eq!(output(source), ["0", "1", "2", "3"]);
+}
+
+#[test]
+fn for_loops_visit_ranges_and_lists() {
+    let source = "
+        for i in 0..3 { print i; }
+        for i in 3..3 { print \"never\"; }
+        for item in [\"a\", \"b\"] { print item; }
+    ";
+    assert_eq!(output(source), ["0", "1", "2", "a", "b"]);
+}
+
+#[test]
+fn break_and_continue_affect_the_innermost_loop() {
+    let source = "
+        for i in 0..3 {
+            let j = 0;
+            while true {
+                j = j + 1;
+                if j == 2 { continue; }
+                if j > 3 { break; }
+                print i * 10 + j;
+            }
+            if i == 1 { break; }
+        }
+    ";
+    assert_eq!(output(source), ["1", "3", "11", "13"]);
+}
+
+#[test]
+fn break_outside_a_loop_is_rejected() {
+    for source in ["break;", "continue;", "fn f() { break; }"] {
+        let diagnostic = netl::parse(source).expect_err("there is no loop");
+        assert_eq!(diagnostic.code, Some("E0108"), "{}", source);
+    }
+}
+
+#[test]
+fn elif_chains_run_the_first_branch_that_holds() {
+    let source = "
+        fn classify(n) {
+            if n < 0 {
+                print \"negative\";
+            } elif n < 10 {
+                print \"small\";
+            } elif n < 100 {
+                print \"medium\";
+            } else {
+                print \"large\";
+            }
+        }
+        classify(-5);
+        classify(5);
+        classify(50);
+        classify(500);
+    ";
+    assert_eq!(output(source), ["negative", "small", "medium", "large"]);
+}
+
+#[test]
+fn elif_conditions_are_evaluated_in_order_until_one_holds() {
+    let source = "
+        fn check(name, result) {
+            print name;
+            return result;
+        }
+        if check(\"if\", false) {
+            print \"first\";
+        } elif check(\"elif 1\", true) {
+            print \"second\";
+        } elif check(\"elif 2\", true) {
+            print \"third\";
+        }
+        if false {
+        } elif false {
+        } else {
+            print \"else\";
+        }
+    ";
+    assert_eq!(output(source), ["if", "elif 1", "second", "else"]);
+}
+
+#[test]
+fn let_in_a_block_does_not_leak_out_of_it() {
+    let source = "
+        if 1 < 2 {
+            let inner = 1;
+            print inner;
+        }
+        print inner;
+    ";
+    let outcome = run_on_both(source);
+    assert_eq!(outcome.output, ["1"]);
+    let error = outcome.error.expect("`inner` is out of scope");
+    assert!(
+        error.contains("error[E0201]: undefined variable `inner`"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn blocks_shadow_and_assign_enclosing_variables() {
+    let source = "
+        let x = 1;
+        let y = 1;
+        if 1 < 2 {
+            let x = 2;
+            y = 2;
+            print x;
+        }
+        print x;
+        print y;
+        for i in 0..2 {
+            let y = i * 10;
+        }
+        print y;
+    ";
+    assert_eq!(output(source), ["2", "1", "2", "2"]);
+}
+
+#[test]
+fn assigning_an_undeclared_variable_is_an_error() {
+    let source = "
+        if 1 < 2 {
+            let local = 1;
+        }
+        local = 2;
+    ";
+    let error = error(source);
+    assert!(
+        error.contains("error[E0213]: cannot assign to undeclared variable `local`"),
+        "{}",
+        error
+    );
+}
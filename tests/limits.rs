@@ -0,0 +1,111 @@
+mod common;
+
+use netl::{Engine, Value};
+
+/// Recursion this deep runs on both backends on the test thread's default
+/// stack, and gives the same result.
+#[test]
+fn backends_agree_on_deep_recursion() {
+    let source = "
+        fn sum(n) {
+            if n == 0 { return 0; }
+            for i in 0..1 { while n > 0 { if n > 0 { return n + sum(n - 1); } } }
+        }
+        print sum(10000);
+        fn count(n) {
+            if n == 0 { return 0; }
+            while true { if nil { return 0; } else { return 1 + count(n - 1); } }
+        }
+        print count(10000);
+    ";
+    for backend in common::BACKENDS {
+        let outcome = common::run(backend, source, None);
+        assert_eq!(outcome.error, None, "{:?}", backend);
+        assert_eq!(outcome.output, ["50005000", "10000"], "{:?}", backend);
+    }
+}
+
+#[test]
+fn unbounded_recursion_fails_the_same_on_both_backends() {
+    let source = "fn f(n) { return f(n + 1) + 1; }\nprint f(0);";
+    let [tree, vm] = common::BACKENDS.map(|backend| common::run(backend, source, None));
+    assert_eq!(tree, vm);
+    let error = tree.error.expect("the recursion is unbounded");
+    assert!(error.contains("error[E0209]: stack overflow"), "{}", error);
+}
+
+#[test]
+fn engine_recovers_from_an_error_deep_in_recursion() {
+    for backend in common::BACKENDS {
+        let mut engine = Engine::with_backend(backend);
+        engine.eval("let base = 1;").unwrap();
+        let error = engine
+            .eval("fn down(n) { if n == 0 { return 1 / 0; } return down(n - 1); }\ndown(10000);")
+            .expect_err("the innermost call divides by zero");
+        assert_eq!(error.to_diagnostic().code, Some("E0203"));
+        assert_eq!(engine.eval("base + 1").unwrap(), Value::Int(2));
+    }
+}
+
+#[test]
+fn parser_rejects_deeply_nested_code() {
+    let cases = [
+        format!("print {}1{};", "(".repeat(100_000), ")".repeat(100_000)),
+        format!("print {}1;", "-".repeat(100_000)),
+        format!("print {}1{};", "[".repeat(100_000), "]".repeat(100_000)),
+        format!("{}{}", "if true { ".repeat(100_000), "}".repeat(100_000)),
+    ];
+    for source in cases {
+        let error = netl::parse(&source).expect_err("the code nests too deeply");
+        assert_eq!(error.code, Some("E0112"));
+    }
+
+    let source = format!(
+        "print {}1{};",
+        "\"${".repeat(100_000),
+        "}\"".repeat(100_000)
+    );
+    let error = netl::parse(&source).expect_err("the interpolations nest too deeply");
+    assert_eq!(error.code, Some("E0006"));
+}
+
+#[test]
+fn code_at_the_nesting_limit_runs_on_both_backends() {
+    let source = format!("print {}1{};", "(".repeat(120), ")".repeat(120));
+    for backend in common::BACKENDS {
+        let outcome = common::run(backend, &source, None);
+        assert_eq!(outcome.error, None);
+        assert_eq!(outcome.output, ["1"]);
+    }
+}
+
+/// Lists and maps nested far deeper than the call depth limit.
+#[test]
+fn deeply_nested_collections_are_displayed_and_compared() {
+    let source = "
+        let list = [];
+        let copy = [];
+        let map = {};
+        for i in 0..50000 {
+            list = [list];
+            copy = [copy];
+            map = {\"m\": map};
+        }
+    ";
+    for backend in common::BACKENDS {
+        let mut engine = Engine::with_backend(backend);
+        engine.eval(source).unwrap();
+        let shown = |name| engine.get_global(name).unwrap().to_string().len();
+        assert_eq!(shown("list"), 100002, "{:?}", backend);
+        assert_eq!(shown("map"), 350002, "{:?}", backend);
+        for (comparison, expected) in [
+            ("list == copy", true),
+            ("list < [copy]", true),
+            ("copy <= list", true),
+            ("list[0] == copy", false),
+        ] {
+            let result = engine.eval(comparison).unwrap();
+            assert_eq!(result, Value::Bool(expected), "{:?}", backend);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::BACKENDS;
+use netl::{Backend, Capabilities, Engine, Value};
+
+/// A scratch directory holding `root`, the directory granted to scripts,
+/// next to `outside`, which they must not reach:
+///
+/// ```text
+/// root/a.txt          "hello"
+/// root/sub/
+/// root/link     ->    outside
+/// root/secret   ->    outside/secret.txt
+/// outside/secret.txt  "hunter2"
+/// ```
+struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let dir =
+            std::env::temp_dir().join(format!("netl-sandbox-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root/sub")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(dir.join("root/a.txt"), "hello").unwrap();
+        fs::write(dir.join("outside/secret.txt"), "hunter2").unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("root/link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside/secret.txt"), dir.join("root/secret"))
+            .unwrap();
+        Scratch { dir }
+    }
+
+    fn path(&self, path: &str) -> String {
+        self.dir.join(path).display().to_string()
+    }
+
+    fn root(&self) -> PathBuf {
+        self.dir.join("root")
+    }
+
+    /// An engine allowed to read and write `root` as requested.
+    fn engine(&self, backend: Backend, read: bool, write: bool) -> Engine {
+        let mut capabilities = Capabilities::new();
+        if read {
+            capabilities.allow_read(self.root()).unwrap();
+        }
+        if write {
+            capabilities.allow_write(self.root()).unwrap();
+        }
+        let mut engine = Engine::with_backend(backend);
+        engine.set_capabilities(capabilities);
+        engine
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Calls the native `name` with `arguments`, returning its result or the
+/// message of the error it failed with.
+fn call(engine: &mut Engine, name: &str, arguments: &[&str]) -> Result<Value, String> {
+    let arguments: Vec<_> = arguments
+        .iter()
+        .map(|argument| format!("{:?}", argument))
+        .collect();
+    engine
+        .eval(&format!("{}({})", name, arguments.join(", ")))
+        .map_err(|error| error.to_diagnostic().message)
+}
+
+fn denied(verb: &str, path: &str) -> Result<Value, String> {
+    Err(format!("permission denied: cannot {} `{}`", verb, path))
+}
+
+fn list(names: &[&str]) -> Value {
+    Value::from(
+        names
+            .iter()
+            .map(|&name| Value::from(name))
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[test]
+fn reading_is_allowed_only_inside_granted_directories() {
+    let scratch = Scratch::new("read");
+    let (file, outside) = (
+        scratch.path("root/a.txt"),
+        scratch.path("outside/secret.txt"),
+    );
+    for backend in BACKENDS {
+        let mut engine = scratch.engine(backend, true, false);
+        assert_eq!(
+            call(&mut engine, "read_file", &[&file]),
+            Ok(Value::from("hello"))
+        );
+        assert_eq!(
+            call(&mut engine, "list_dir", &[&scratch.path("root")]),
+            Ok(list(&["a.txt", "link", "secret", "sub"]))
+        );
+        assert_eq!(call(&mut engine, "exists", &[&file]), Ok(Value::Bool(true)));
+        assert_eq!(
+            call(&mut engine, "exists", &[&scratch.path("root/b.txt")]),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            call(&mut engine, "read_file", &[&outside]),
+            denied("read", &outside)
+        );
+        assert_eq!(
+            call(&mut engine, "exists", &[&outside]),
+            denied("read", &outside)
+        );
+        assert_eq!(
+            call(&mut engine, "write_file", &[&file, "x"]),
+            denied("write", &file)
+        );
+
+        let mut engine = scratch.engine(backend, false, false);
+        assert_eq!(
+            call(&mut engine, "read_file", &[&file]),
+            denied("read", &file)
+        );
+        assert_eq!(
+            call(&mut engine, "list_dir", &[&scratch.path("root")]),
+            denied("read", &scratch.path("root"))
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(scratch.path("root/a.txt")).unwrap(),
+        "hello"
+    );
+}
+
+#[test]
+fn writing_is_allowed_only_inside_granted_directories() {
+    let scratch = Scratch::new("write");
+    let (file, outside) = (
+        scratch.path("root/sub/b.txt"),
+        scratch.path("outside/b.txt"),
+    );
+    for backend in BACKENDS {
+        let mut engine = scratch.engine(backend, true, true);
+        assert_eq!(
+            call(&mut engine, "write_file", &[&file, "one"]),
+            Ok(Value::Nil)
+        );
+        assert_eq!(
+            call(&mut engine, "append_file", &[&file, " two"]),
+            Ok(Value::Nil)
+        );
+        assert_eq!(
+            call(&mut engine, "read_file", &[&file]),
+            Ok(Value::from("one two"))
+        );
+        assert_eq!(call(&mut engine, "remove", &[&file]), Ok(Value::Nil));
+        assert_eq!(
+            call(&mut engine, "exists", &[&file]),
+            Ok(Value::Bool(false))
+        );
+
+        assert_eq!(
+            call(&mut engine, "write_file", &[&outside, "x"]),
+            denied("write", &outside)
+        );
+        assert_eq!(
+            call(&mut engine, "append_file", &[&outside, "x"]),
+            denied("write", &outside)
+        );
+
+        let mut engine = scratch.engine(backend, true, false);
+        assert_eq!(
+            call(&mut engine, "append_file", &[&file, "x"]),
+            denied("write", &file)
+        );
+        assert_eq!(
+            call(&mut engine, "remove", &[&scratch.path("root/a.txt")]),
+            denied("remove", &scratch.path("root/a.txt"))
+        );
+    }
+    assert!(!scratch.dir.join("outside/b.txt").exists());
+    assert!(scratch.dir.join("root/a.txt").exists());
+}
+
+#[test]
+fn dot_dot_cannot_lead_out_of_a_granted_directory() {
+    let scratch = Scratch::new("dotdot");
+    let secret = scratch.path("root/sub/../../outside/secret.txt");
+    let created = scratch.path("root/../outside/new.txt");
+    let missing = scratch.path("root/missing/../../outside/new.txt");
+    for backend in BACKENDS {
+        let mut engine = scratch.engine(backend, true, true);
+        assert_eq!(
+            call(&mut engine, "read_file", &[&secret]),
+            denied("read", &secret)
+        );
+        assert_eq!(
+            call(&mut engine, "write_file", &[&created, "x"]),
+            denied("write", &created)
+        );
+        assert_eq!(
+            call(&mut engine, "write_file", &[&missing, "x"]),
+            denied("write", &missing)
+        );
+        assert_eq!(
+            call(&mut engine, "remove", &[&secret]),
+            denied("remove", &secret)
+        );
+
+        // `..` that stays inside is fine.
+        let inside = scratch.path("root/sub/../a.txt");
+        assert_eq!(
+            call(&mut engine, "read_file", &[&inside]),
+            Ok(Value::from("hello"))
+        );
+    }
+    assert!(!scratch.dir.join("outside/new.txt").exists());
+    assert!(scratch.dir.join("outside/secret.txt").exists());
+}
+
+#[test]
+fn symlinks_cannot_lead_out_of_a_granted_directory() {
+    let scratch = Scratch::new("symlink");
+    let (through_dir, through_file) = (
+        scratch.path("root/link/secret.txt"),
+        scratch.path("root/secret"),
+    );
+    let created = scratch.path("root/link/new.txt");
+    for backend in BACKENDS {
+        let mut engine = scratch.engine(backend, true, true);
+        assert_eq!(
+            call(&mut engine, "read_file", &[&through_dir]),
+            denied("read", &through_dir)
+        );
+        assert_eq!(
+            call(&mut engine, "read_file", &[&through_file]),
+            denied("read", &through_file)
+        );
+        assert_eq!(
+            call(&mut engine, "list_dir", &[&scratch.path("root/link")]),
+            denied("read", &scratch.path("root/link"))
+        );
+        assert_eq!(
+            call(&mut engine, "exists", &[&through_dir]),
+            denied("read", &through_dir)
+        );
+        assert_eq!(
+            call(&mut engine, "write_file", &[&created, "x"]),
+            denied("write", &created)
+        );
+        assert_eq!(
+            call(&mut engine, "append_file", &[&through_file, "x"]),
+            denied("write", &through_file)
+        );
+        assert_eq!(
+            call(&mut engine, "remove", &[&through_file]),
+            denied("remove", &through_file)
+        );
+    }
+    assert!(!scratch.dir.join("outside/new.txt").exists());
+    assert_eq!(
+        fs::read_to_string(scratch.dir.join("outside/secret.txt")).unwrap(),
+        "hunter2"
+    );
+}
+
+#[test]
+fn remove_cannot_delete_a_granted_directory() {
+    let scratch = Scratch::new("remove");
+    let granted = scratch.path("root/sub");
+    let dotted = scratch.path("root/sub/inner/..");
+    for backend in BACKENDS {
+        fs::create_dir_all(scratch.dir.join("root/sub/inner")).unwrap();
+        let mut capabilities = Capabilities::new();
+        capabilities.allow_write(&granted).unwrap();
+        let mut engine = Engine::with_backend(backend);
+        engine.set_capabilities(capabilities);
+
+        assert_eq!(
+            call(&mut engine, "remove", &[&scratch.path("root/sub/inner")]),
+            Ok(Value::Nil)
+        );
+        // The granted directory is now empty, so only the check keeps it.
+        assert_eq!(
+            call(&mut engine, "remove", &[&granted]),
+            denied("remove", &granted)
+        );
+        assert_eq!(
+            call(&mut engine, "remove", &[&dotted]),
+            denied("remove", &dotted)
+        );
+        assert!(Path::new(&granted).is_dir());
+    }
+}